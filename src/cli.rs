@@ -1,23 +1,227 @@
-use crate::TartarFS;
-use clap::Parser;
+use crate::formats::{Formats, Oci, Warc};
+use crate::{
+    Collisions, Decompress, IndexCache, Normalization, Options, PathFilter, Spool, TartarFS,
+};
+use clap::{Parser, Subcommand};
 use fuser::MountOption;
 use std::fs::metadata;
+use std::io::{Error, ErrorKind};
 use std::os::unix::fs::MetadataExt;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use tracing::info;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Args {
-    pub archive_path: String,
-    pub mount_path: String,
+    /// The archive to mount: a local path or an `http(s)://` URL, or an OCI
+    /// image layout directory
+    #[arg(required = true)]
+    pub archive_path: Option<String>,
+    #[arg(required = true)]
+    pub mount_path: Option<String>,
+
+    #[command(flatten)]
+    pub options: OptionArgs,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Index an archive ahead of time, for mounts to load the index
+    ///
+    /// The index is saved next to the archive, or in `--index-dir`, and gets
+    /// loaded by mounts with the same options
+    Index {
+        /// The archive to index: a local path or an `http(s)://` URL
+        archive_path: String,
+
+        #[command(flatten)]
+        options: OptionArgs,
+    },
+}
+
+// no doc comment, which clap would take for the description of the program
+#[derive(clap::Args, Debug)]
+pub struct OptionArgs {
+    /// Only expose archive paths matching this glob, e.g. `docs/**` (repeatable)
+    #[arg(long, value_name = "GLOB")]
+    pub include: Vec<String>,
+
+    /// Hide archive paths matching this glob, e.g. `**/*.tmp` (repeatable)
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+
+    /// Read exclude globs from a file, one per line
+    #[arg(long, value_name = "FILE")]
+    pub exclude_from: Option<String>,
+
+    /// Look up names case-insensitively, e.g. for archives authored on Windows
+    #[arg(long)]
+    pub case_insensitive: bool,
+
+    /// Normalize names to a Unicode form, so NFD names from macOS match NFC lookups
+    #[arg(long, value_enum, value_name = "FORM")]
+    pub normalize: Option<Normalization>,
+
+    /// Show every regular file directly in the mount root, resolving name clashes
+    /// by a counter suffix (default) or by encoding the path as `a__b__c.txt`;
+    /// symlinks and special files stay where they are
+    #[arg(long, value_enum, value_name = "COLLISIONS")]
+    #[arg(num_args = 0..=1, require_equals = true, default_missing_value = "counter")]
+    pub flatten: Option<Collisions>,
+
+    /// Show archives inside the archive as directories, up to this many levels deep
+    #[arg(long, value_name = "DEPTH", default_value_t = 0)]
+    pub nested: u32,
+
+    /// Show `.gz`, `.zst`, `.xz`, `.bz2`, `.lz4`, `.lz`, `.lzma` and `.br`
+    /// members decompressed, next to the original (default) or instead of it;
+    /// members whose size isn't recorded, like `.xz` or gzip of 4 MB or more, are
+    /// decoded in full once when mounting to find it
+    #[arg(long, value_enum, value_name = "MODE")]
+    #[arg(num_args = 0..=1, require_equals = true, default_missing_value = "alongside")]
+    pub decompress: Option<Decompress>,
+
+    /// Check file digests recorded in the archive (RPM) when first reading a
+    /// file, failing reads of corrupted files
+    #[arg(long)]
+    pub verify_digests: bool,
+
+    /// Serve only the bodies of HTTP responses in WARC archives, without the
+    /// HTTP headers and with chunked transfer encoding undone
+    #[arg(long)]
+    pub warc_payload: bool,
+
+    /// Also show each layer of a container image as it is, under `.layers/<digest>/`
+    #[arg(long)]
+    pub oci_layers: bool,
+
+    /// Decompress lz4, lzip, brotli and `.lzma` streams into this directory
+    /// [default: the temp directory]
+    #[arg(long, value_name = "DIR")]
+    pub spool_dir: Option<PathBuf>,
+
+    /// Spool at most this much in all, e.g. `512M` or `8G`; streams that
+    /// don't fit in what is left are decoded on every read instead
+    #[arg(long, value_name = "SIZE", default_value = "4G", value_parser = parse_size)]
+    pub spool_max_size: u64,
+
+    /// Keep spool files after unmounting, to reuse when mounting the same archive again
+    #[arg(long)]
+    pub keep_spool: bool,
+
+    /// Index archives compressed as several independent members, like
+    /// concatenated gzip, multi-frame zstd (`pzstd`) or multi-block xz (`xz -T`,
+    /// `pixz`), on this many threads; a single gzip member, as plain `gzip` and
+    /// `pigz` write, is still decoded on one thread
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    pub jobs: usize,
+
+    /// Try this format backend first, e.g. `ar` to see the outer members of a `.deb`
+    #[arg(long, value_name = "NAME")]
+    pub format: Option<String>,
+
+    /// Save the index of the archive next to it, as `<archive>.tartarfs-index`,
+    /// and load it on the next mount instead of reading the archive; it's
+    /// keyed by the archive's size, mtime and a hash of its first and last MiB
+    #[arg(long, conflicts_with = "index_dir")]
+    pub index_sidecar: bool,
+
+    /// Save and load indexes of archives in this directory, e.g. for archives
+    /// at a URL or in a read-only directory
+    #[arg(long, value_name = "DIR")]
+    pub index_dir: Option<PathBuf>,
+
+    /// Hash the whole archive to key its saved index, not only its first and
+    /// last MiB, to notice changes in the middle that kept the size and mtime
+    #[arg(long)]
+    pub index_full_hash: bool,
+}
+
+impl OptionArgs {
+    pub fn to_options(&self) -> std::io::Result<Options> {
+        let mut exclude = self.exclude.clone();
+        if let Some(path) = &self.exclude_from {
+            exclude.extend(PathFilter::read_patterns(path)?);
+        }
+        let filter = PathFilter::new(&self.include, &exclude)
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+
+        let mut formats = Formats::default();
+        if self.warc_payload {
+            formats.replace(Arc::new(Warc { payload_only: true }));
+        }
+        if self.oci_layers {
+            formats.replace(Arc::new(Oci { layers: true }));
+        }
+        if let Some(name) = &self.format
+            && !formats.prefer(name)
+        {
+            let known = formats.names().join(", ");
+            let message = format!("unknown format {name}, expected one of: {known}");
+            return Err(Error::new(ErrorKind::InvalidInput, message));
+        }
+
+        Ok(Options {
+            filter,
+            case_insensitive: self.case_insensitive,
+            normalization: self.normalize,
+            flatten: self.flatten,
+            nested_depth: self.nested,
+            decompress: self.decompress,
+            spool: Spool {
+                dir: self.spool_dir.clone().unwrap_or_else(std::env::temp_dir),
+                max_size: self.spool_max_size,
+                keep: self.keep_spool,
+                ..Spool::default()
+            },
+            jobs: self.jobs,
+            verify_digests: self.verify_digests,
+            formats,
+            index_cache: match (&self.index_dir, self.index_sidecar) {
+                (Some(dir), _) => Some(IndexCache::Dir(dir.clone())),
+                (None, true) => Some(IndexCache::Sidecar),
+                (None, false) => None,
+            },
+            index_full_hash: self.index_full_hash,
+        })
+    }
+}
+
+/// Parse a byte count with an optional `K`, `M`, `G` or `T` suffix.
+fn parse_size(text: &str) -> Result<u64, String> {
+    let text = text.trim();
+    let (number, shift) = match text.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&text[..i], 10),
+        Some((i, 'M' | 'm')) => (&text[..i], 20),
+        Some((i, 'G' | 'g')) => (&text[..i], 30),
+        Some((i, 'T' | 't')) => (&text[..i], 40),
+        _ => (text, 0),
+    };
+    let number: u64 = number.parse().map_err(|e| format!("{e}"))?;
+    number
+        .checked_mul(1 << shift)
+        .ok_or_else(|| format!("{text} is too big"))
+}
+
+/// Index an archive and save the index, for `tartarfs index`.
+pub fn index(archive_path: &str, options: &OptionArgs) -> std::io::Result<PathBuf> {
+    TartarFS::write_index(archive_path, options.to_options()?)
 }
 
 pub fn run(args: Args) -> std::io::Result<()> {
-    let filesystem = TartarFS::new(args.archive_path);
+    let (Some(archive_path), Some(mount_path)) = (args.archive_path, args.mount_path) else {
+        let message = "an archive and a mount point are needed";
+        return Err(Error::new(ErrorKind::InvalidInput, message));
+    };
+    let options = args.options.to_options()?;
+    let filesystem = TartarFS::with_options(archive_path, options);
 
-    let mount_path = Path::new(&args.mount_path);
+    let mount_path = Path::new(&mount_path);
     if !mount_path.exists() {
         std::fs::create_dir_all(mount_path)?;
     }
@@ -25,7 +229,7 @@ pub fn run(args: Args) -> std::io::Result<()> {
     let options = vec![MountOption::FSName("tartarfs".into()), MountOption::RO];
 
     // the mount will be unmounted when the session is dropped
-    let session = fuser::spawn_mount2(filesystem, &mount_path, &options)?;
+    let session = fuser::spawn_mount2(filesystem, mount_path, &options)?;
     let session = Arc::new(Mutex::new(Some(session)));
     let session_for_handler = Arc::clone(&session);
 
@@ -54,7 +258,7 @@ pub fn run(args: Args) -> std::io::Result<()> {
                 return Ok(());
             }
 
-            if !is_mounted(&mount_path) {
+            if !is_mounted(mount_path) {
                 info!("Mount point no longer mounted");
                 return Ok(());
             }
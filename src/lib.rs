@@ -5,4 +5,7 @@ mod tartarfs;
 #[cfg(test)]
 mod test_utils;
 
-pub use tartarfs::TartarFS;
+pub use tartarfs::{compression, digest, formats, source};
+pub use tartarfs::{
+    Collisions, Decompress, IndexCache, Normalization, Options, PathFilter, Spool, TartarFS,
+};
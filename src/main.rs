@@ -1,4 +1,5 @@
 use clap::Parser;
+use tartarfs::cli::Command;
 use tracing::info;
 use tracing_subscriber::{fmt, EnvFilter};
 
@@ -9,8 +10,22 @@ fn main() {
         .init();
 
     let args = tartarfs::cli::Args::parse();
-    info!("Archive: {}", args.archive_path);
-    info!("Mount: {}", args.mount_path);
+    if let Some(Command::Index {
+        archive_path,
+        options,
+    }) = &args.command
+    {
+        info!("Indexing: {}", archive_path);
+        let path = tartarfs::cli::index(archive_path, options)
+            .unwrap_or_else(|e| panic!("failed to index: {}", e));
+        println!("{}", path.display());
+        return;
+    }
+    info!(
+        "Archive: {}",
+        args.archive_path.as_deref().unwrap_or_default()
+    );
+    info!("Mount: {}", args.mount_path.as_deref().unwrap_or_default());
 
     tartarfs::cli::run(args).unwrap_or_else(|e| panic!("failed to mount: {}", e));
 }
@@ -0,0 +1,51 @@
+use super::TartarFS;
+use std::collections::hash_map::Entry;
+use tracing::warn;
+
+/// Fold a path for caseless comparison, e.g. `Textures/Straße.PNG` -> `textures/strasse.png`.
+pub fn fold_case(path: &str) -> String {
+    caseless::default_case_fold_str(path)
+}
+
+impl TartarFS {
+    /// Build the secondary index used by case-insensitive lookups.
+    ///
+    /// Paths that only differ by case are reported, and the byte-wise smallest
+    /// spelling wins so that the outcome doesn't depend on archive order.
+    /// Exact-case lookups still reach the other spellings.
+    pub(super) fn build_case_folded_index(&mut self) {
+        let mut paths: Vec<_> = self.path_to_inode.iter().collect();
+        paths.sort_by(|a, b| a.0.cmp(b.0));
+
+        for (path, &inode) in paths {
+            match self.folded_path_to_inode.entry(fold_case(path)) {
+                Entry::Vacant(entry) => {
+                    entry.insert(inode);
+                }
+                Entry::Occupied(entry) => {
+                    let winner = self
+                        .inode_to_item
+                        .get(entry.get())
+                        .map(|item| item.name.as_str())
+                        .unwrap_or_default();
+                    warn!(
+                        "Case collision: {:?} and {:?}, case-insensitive lookups resolve to {:?}",
+                        winner, path, winner
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fold_case;
+
+    #[test]
+    fn test_fold_case() {
+        assert_eq!(fold_case("Textures/Grass.PNG"), "textures/grass.png");
+        assert_eq!(fold_case("STRASSE"), fold_case("straße"));
+        assert_eq!(fold_case("ΣΊΣΥΦΟΣ"), fold_case("σίσυφος"));
+    }
+}
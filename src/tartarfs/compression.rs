@@ -0,0 +1,1692 @@
+use super::digest::sha256_hex;
+use super::source::{ByteSource, Recipe, SliceSource, SourceReader};
+use flate2::bufread::GzDecoder;
+use flate2::read::DeflateDecoder;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::os::unix::fs::FileExt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::UNIX_EPOCH;
+use tracing::{debug, info, warn};
+
+/// How to present individually compressed members like `app.log.gz`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, clap::ValueEnum)]
+pub enum Decompress {
+    /// Show `app.log` next to `app.log.gz`.
+    Alongside,
+    /// Show only `app.log`.
+    Replace,
+}
+
+/// Whole-stream compression formats we can decode.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Codec {
+    /// Raw deflate without any framing, as used inside zip archives.
+    Deflate,
+    Gzip,
+    Zstd,
+    Xz,
+    Bzip2,
+    /// LZ4 frames, as written by the `lz4` tool.
+    Lz4,
+    Lzip,
+    /// Brotli has no magic bytes, so it is only recognized by name.
+    Brotli,
+    /// The legacy `.lzma` format: a 13-byte header and one LZMA stream.
+    LzmaAlone,
+    /// Raw LZMA of a known size, with the properties byte and dictionary
+    /// size kept apart from the data as in 7z archives.
+    Lzma {
+        properties: u8,
+        dict_size: u32,
+        size: u64,
+    },
+    /// Raw LZMA2 chunks without any framing, as in 7z archives.
+    Lzma2 {
+        dict_size: u32,
+    },
+}
+
+impl Codec {
+    /// Match a name like `app.log.gz` to its codec and the decompressed name `app.log`.
+    pub fn from_name(name: &str) -> Option<(Codec, &str)> {
+        const EXTENSIONS: [(&str, Codec); 8] = [
+            (".gz", Codec::Gzip),
+            (".zst", Codec::Zstd),
+            (".xz", Codec::Xz),
+            (".bz2", Codec::Bzip2),
+            (".lz4", Codec::Lz4),
+            (".lz", Codec::Lzip),
+            (".br", Codec::Brotli),
+            (".lzma", Codec::LzmaAlone),
+        ];
+        EXTENSIONS.iter().find_map(|&(extension, codec)| {
+            let split = name.len().checked_sub(extension.len())?;
+            let stem = name.get(..split)?;
+            let matches = name[split..].eq_ignore_ascii_case(extension);
+            (matches && !stem.is_empty() && !stem.ends_with('/')).then_some((codec, stem))
+        })
+    }
+
+    /// Find the codec whose magic bytes `source` starts with, if any.
+    pub fn detect(source: &dyn ByteSource) -> Option<Codec> {
+        // `.lzma` headers are only plausible, not certain, so they go last
+        [
+            Codec::Gzip,
+            Codec::Zstd,
+            Codec::Xz,
+            Codec::Bzip2,
+            Codec::Lz4,
+            Codec::Lzip,
+            Codec::LzmaAlone,
+        ]
+        .into_iter()
+        .find(|codec| codec.sniff(source))
+    }
+
+    /// Check whether `source` starts with this codec's magic bytes.
+    pub fn sniff(&self, source: &dyn ByteSource) -> bool {
+        let magic: &[u8] = match self {
+            // no framing, so nothing to check
+            Codec::Deflate | Codec::Brotli | Codec::Lzma { .. } | Codec::Lzma2 { .. } => &[],
+            Codec::Gzip => &[0x1f, 0x8b],
+            Codec::Zstd => &[0x28, 0xb5, 0x2f, 0xfd],
+            Codec::Xz => &[0xfd, b'7', b'z', b'X', b'Z', 0x00],
+            Codec::Bzip2 => b"BZh",
+            Codec::Lz4 => &[0x04, 0x22, 0x4d, 0x18],
+            Codec::Lzip => b"LZIP",
+            Codec::LzmaAlone => return lzma_alone_header(source),
+        };
+        let mut header = vec![0; magic.len()];
+        matches!(source.read_full_at(0, &mut header), Ok(n) if n == magic.len()) && header == magic
+    }
+
+    pub fn decoder(&self, source: Arc<dyn ByteSource>) -> io::Result<Box<dyn Read + Send>> {
+        let reader = SourceReader::new(source);
+        Ok(match *self {
+            Codec::Deflate => Box::new(DeflateDecoder::new(reader)),
+            Codec::Gzip => Box::new(GzipMembers::new(reader)),
+            Codec::Zstd => Box::new(zstd::Decoder::new(reader)?),
+            Codec::Xz => Box::new(xz2::read::XzDecoder::new_multi_decoder(reader)),
+            Codec::Bzip2 => Box::new(bzip2::read::MultiBzDecoder::new(reader)),
+            Codec::Lz4 => Box::new(lz4_flex::frame::FrameDecoder::new(reader)),
+            Codec::Lzip => Box::new(lzma_rust2::LzipReader::new(BufReader::new(reader))),
+            Codec::Brotli => Box::new(brotli_decompressor::Decompressor::new(reader, 4096)),
+            Codec::LzmaAlone => Box::new(lzma_rust2::LzmaReader::new_mem_limit(
+                BufReader::new(reader),
+                u32::MAX,
+                None,
+            )?),
+            Codec::Lzma {
+                properties,
+                dict_size,
+                size,
+            } => Box::new(lzma_rust2::LzmaReader::new_with_props(
+                BufReader::new(reader),
+                size,
+                properties,
+                dict_size,
+                None,
+            )?),
+            Codec::Lzma2 { dict_size } => Box::new(lzma_rust2::Lzma2Reader::new(
+                BufReader::new(reader),
+                dict_size,
+                None,
+            )),
+        })
+    }
+
+    /// Whether this codec has no structure to find a way into the middle of
+    /// the stream by, so reads are better served from a [`Spool`].
+    pub fn is_stream_only(&self) -> bool {
+        matches!(
+            self,
+            Codec::Lz4 | Codec::Lzip | Codec::Brotli | Codec::LzmaAlone
+        )
+    }
+}
+
+/// Check for a `.lzma` header: valid properties, a dictionary size `xz`
+/// would write and an uncompressed size that is unknown or not absurd.
+fn lzma_alone_header(source: &dyn ByteSource) -> bool {
+    let mut header = [0; 13];
+    if !matches!(source.read_full_at(0, &mut header), Ok(13)) {
+        return false;
+    }
+    let dict_size = u32::from_le_bytes([header[1], header[2], header[3], header[4]]);
+    let size = u64::from_le_bytes(header[5..13].try_into().unwrap_or_default());
+    // 2^n or 2^n + 2^(n-1)
+    let plausible_dict =
+        dict_size >= 4096 && matches!(dict_size >> dict_size.trailing_zeros(), 1 | 3);
+    header[0] < 9 * 5 * 5 && plausible_dict && (size == u64::MAX || size < 1 << 48)
+}
+
+/// Decodes concatenated gzip members, stopping quietly at anything else
+/// after them, like the zero padding of initramfs images or tape blocks.
+struct GzipMembers<R: Read> {
+    decoder: Option<GzDecoder<BufReader<R>>>,
+}
+
+impl<R: Read> GzipMembers<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            decoder: Some(GzDecoder::new(BufReader::new(reader))),
+        }
+    }
+}
+
+impl<R: Read> Read for GzipMembers<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while let Some(decoder) = &mut self.decoder {
+            let n = decoder.read(buf)?;
+            if n > 0 || buf.is_empty() {
+                return Ok(n);
+            }
+            // the member is done; only go on if another one follows
+            let mut rest = self.decoder.take().map(GzDecoder::into_inner);
+            if let Some(reader) = &mut rest
+                && reader.fill_buf()?.starts_with(&[0x1f, 0x8b])
+            {
+                self.decoder = rest.map(GzDecoder::new);
+            }
+        }
+        Ok(0)
+    }
+}
+
+/// One member of a multi-member gzip stream, with what was read from it.
+pub(crate) struct GzipMember<T> {
+    /// Where the member starts in the compressed stream.
+    pub offset: u64,
+    /// Its decompressed contents, which decode only this member.
+    pub content: Arc<dyn ByteSource>,
+    pub read: io::Result<T>,
+}
+
+/// Decode each member of a multi-member gzip stream once, handing `read` a
+/// reader for its contents, and note where it is so that later reads from
+/// it needn't decode the members before it.
+pub(crate) fn gzip_members<T>(
+    source: &Arc<dyn ByteSource>,
+    mut read: impl FnMut(&mut dyn BufRead) -> io::Result<T>,
+) -> io::Result<Vec<GzipMember<T>>> {
+    let mut members = Vec::new();
+    let mut compressed = Counting::new(BufReader::new(SourceReader::new(Arc::clone(source))));
+    while compressed.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        let offset = compressed.count;
+        let mut decoded = Counting::new(BufReader::new(GzDecoder::new(compressed)));
+        let result = read(&mut decoded);
+        // whatever `read` left of the member
+        io::copy(&mut decoded, &mut io::sink())?;
+        let size = decoded.count;
+        compressed = decoded.inner.into_inner().into_inner();
+
+        let member = SliceSource::new(Arc::clone(source), offset, compressed.count - offset);
+        members.push(GzipMember {
+            offset,
+            content: Arc::new(DecompressedSource::with_size(
+                Arc::new(member),
+                Codec::Gzip,
+                size,
+            )),
+            read: result,
+        });
+    }
+    Ok(members)
+}
+
+/// A part of a compressed stream that decodes on its own, like a gzip
+/// member, a zstd frame or an xz block, with what was read from it.
+pub(crate) struct Member<T> {
+    /// Its decompressed contents, which decode only this member.
+    pub content: Arc<dyn ByteSource>,
+    pub read: T,
+}
+
+/// Where a member might start, and how long it is if that is known
+/// without decoding it.
+struct Candidate {
+    offset: u64,
+    length: Option<u64>,
+    codec: Codec,
+}
+
+/// Decode the members of a compressed stream on `jobs` threads at once,
+/// handing `read` a reader for the contents of each, and return them in
+/// stream order whichever thread finishes first.
+///
+/// `None` if the stream can't be split up this way, e.g. xz with filters
+/// other than LZMA2, or if any member fails to decode or gzip members stop
+/// short of the end, so that reading it from the start can report what is wrong.
+pub(crate) fn parallel_members<T: Send>(
+    source: &Arc<dyn ByteSource>,
+    codec: Codec,
+    jobs: usize,
+    read: impl Fn(&mut dyn BufRead) -> io::Result<T> + Sync,
+) -> Option<Vec<Member<T>>> {
+    let candidates = match codec {
+        Codec::Gzip => gzip_candidates(source.as_ref(), jobs)?,
+        Codec::Zstd => zstd_frames(source.as_ref())?,
+        Codec::Xz => xz_blocks(source.as_ref())?,
+        _ => return None,
+    };
+    debug!(
+        "Decoding {} {:?} members on {} threads",
+        candidates.len(),
+        codec,
+        jobs
+    );
+    let decoded = in_parallel(&candidates, jobs, |candidate| {
+        decode_member(source, candidate, &read)
+    });
+
+    let mut members = Vec::new();
+    let mut next = 0;
+    for (candidate, decoded) in candidates.iter().zip(decoded) {
+        // gzip members are guessed at, so only those right after the last one count
+        if candidate.length.is_none() && candidate.offset != next {
+            continue;
+        }
+        match decoded {
+            Ok((length, member)) => {
+                next = candidate.offset + length;
+                members.push(member);
+            }
+            // like `GzipMembers`, stop quietly at what isn't another member
+            Err(_) if candidate.length.is_none() && !members.is_empty() => break,
+            Err(e) => {
+                debug!(
+                    "Can't decode {:?} member at {}: {}",
+                    codec, candidate.offset, e
+                );
+                return None;
+            }
+        }
+    }
+    // a gzip member that doesn't decode ends them quietly, which only
+    // leaves nothing out if padding is all that follows
+    if codec == Codec::Gzip && !zeros_from(source.as_ref(), next).unwrap_or(false) {
+        debug!(
+            "{:?} members end at {} of {}, not at padding",
+            codec,
+            next,
+            source.size()
+        );
+        return None;
+    }
+    if members.len() == 1 {
+        info!(
+            "Only one {:?} member, as plain gzip and pigz write, so it was decoded on one thread",
+            codec
+        );
+    }
+    (!members.is_empty()).then_some(members)
+}
+
+/// Whether `source` holds nothing but zeros from `offset` on.
+fn zeros_from(source: &dyn ByteSource, mut offset: u64) -> io::Result<bool> {
+    let mut buf = vec![0; 1 << 16];
+    while offset < source.size() {
+        let n = source.read_at(offset, &mut buf)?;
+        if n == 0 {
+            break;
+        }
+        if buf[..n].iter().any(|&byte| byte != 0) {
+            return Ok(false);
+        }
+        offset += n as u64;
+    }
+    Ok(true)
+}
+
+/// Decode one member, returning how long it turned out to be in the
+/// compressed stream.
+fn decode_member<T>(
+    source: &Arc<dyn ByteSource>,
+    candidate: &Candidate,
+    read: impl Fn(&mut dyn BufRead) -> io::Result<T>,
+) -> io::Result<(u64, Member<T>)> {
+    let end = candidate
+        .length
+        .map_or(source.size(), |length| candidate.offset + length);
+    let slice: Arc<dyn ByteSource> = Arc::new(SliceSource::new(
+        Arc::clone(source),
+        candidate.offset,
+        end - candidate.offset,
+    ));
+    let mut compressed = Counting::new(BufReader::new(SourceReader::new(Arc::clone(&slice))));
+    let decoder: Box<dyn Read> = match candidate.length {
+        // just the one member, to find where it ends
+        None => Box::new(GzDecoder::new(&mut compressed)),
+        Some(_) => candidate.codec.decoder(Arc::clone(&slice))?,
+    };
+    let mut decoded = Counting::new(BufReader::new(decoder));
+    let result = read(&mut decoded)?;
+    // whatever `read` left of the member
+    io::copy(&mut decoded, &mut io::sink())?;
+    let size = decoded.count;
+    drop(decoded);
+
+    let length = candidate.length.unwrap_or(compressed.count);
+    let member = SliceSource::new(Arc::clone(source), candidate.offset, length);
+    let content = DecompressedSource::with_size(Arc::new(member), candidate.codec, size);
+    let member = Member {
+        content: Arc::new(content),
+        read: result,
+    };
+    Ok((length, member))
+}
+
+/// Run `f` on each of `items` on `jobs` threads, keeping the results in order.
+fn in_parallel<I: Sync, R: Send>(items: &[I], jobs: usize, f: impl Fn(&I) -> R + Sync) -> Vec<R> {
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new((0..items.len()).map(|_| None).collect::<Vec<_>>());
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, items.len().max(1)) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(index) else {
+                    break;
+                };
+                let result = f(item);
+                results.lock().unwrap_or_else(|e| e.into_inner())[index] = Some(result);
+            });
+        }
+    });
+    let results = results.into_inner().unwrap_or_else(|e| e.into_inner());
+    // a thread that panicked would have taken the scope down with it
+    results.into_iter().map(Option::unwrap).collect()
+}
+
+/// Everywhere a gzip member could start, going by [`is_gzip_header`].
+/// BGZF blocks record their size, so they needn't be guessed.
+fn gzip_candidates(source: &dyn ByteSource, jobs: usize) -> Option<Vec<Candidate>> {
+    let size = source.size();
+    if bgzf_block_size(source, 0).is_some() {
+        let mut blocks = Vec::new();
+        let mut offset = 0;
+        while let Some(length) = bgzf_block_size(source, offset) {
+            blocks.push(Candidate {
+                offset,
+                length: Some(length),
+                codec: Codec::Gzip,
+            });
+            offset += length;
+        }
+        return Some(blocks);
+    }
+
+    const CHUNK_SIZE: u64 = 4 << 20;
+    let chunks: Vec<u64> = (0..size.div_ceil(CHUNK_SIZE)).collect();
+    let found = in_parallel(&chunks, jobs, |&chunk| {
+        let start = chunk * CHUNK_SIZE;
+        // overlapping the next chunk by the rest of the header
+        let mut buf = vec![0; (CHUNK_SIZE + 9).min(size - start) as usize];
+        let n = source.read_full_at(start, &mut buf)?;
+        let offsets = buf[..n]
+            .windows(10)
+            .take(CHUNK_SIZE as usize)
+            .enumerate()
+            .filter(|(_, header)| is_gzip_header(header))
+            .map(|(i, _)| start + i as u64);
+        io::Result::Ok(offsets.collect::<Vec<_>>())
+    });
+    let mut candidates = Vec::new();
+    for offsets in found {
+        candidates.extend(offsets.ok()?.into_iter().map(|offset| Candidate {
+            offset,
+            length: None,
+            codec: Codec::Gzip,
+        }));
+    }
+    Some(candidates)
+}
+
+/// Check the fixed 10 bytes a gzip member starts with, so that the magic
+/// turning up in compressed data is seldom taken for a member: deflate, no
+/// reserved flags, and an extra flags byte and OS that writers use.
+fn is_gzip_header(header: &[u8]) -> bool {
+    let [0x1f, 0x8b, 8, flags, _, _, _, _, extra_flags, os] = *header else {
+        return false;
+    };
+    flags & 0xe0 == 0 && matches!(extra_flags, 0 | 2 | 4) && (os <= 13 || os == 255)
+}
+
+/// Where each frame of a zstd stream is, found by walking its block headers
+/// rather than decoding it. Skippable frames, like those `pzstd` writes,
+/// are left out.
+fn zstd_frames(source: &dyn ByteSource) -> Option<Vec<Candidate>> {
+    let mut frames = Vec::new();
+    let mut offset = 0;
+    while offset < source.size() {
+        let mut header = [0; 8];
+        if source.read_full_at(offset, &mut header).ok()? < header.len() {
+            return None;
+        }
+        let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        if magic & 0xffff_fff0 == 0x184d_2a50 {
+            let length = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+            offset += 8 + length as u64;
+            continue;
+        }
+        if magic != 0xfd2f_b528 {
+            return None;
+        }
+
+        let descriptor = header[4];
+        let single_segment = descriptor & 0x20 != 0;
+        let content_size_length = match descriptor >> 6 {
+            0 => single_segment as u64,
+            1 => 2,
+            2 => 4,
+            _ => 8,
+        };
+        let dictionary_length = [0, 1, 2, 4][(descriptor & 3) as usize];
+        let mut position =
+            offset + 5 + !single_segment as u64 + dictionary_length + content_size_length;
+        loop {
+            let mut block = [0; 3];
+            if source.read_full_at(position, &mut block).ok()? < block.len() {
+                return None;
+            }
+            let block = u32::from_le_bytes([block[0], block[1], block[2], 0]);
+            let length = match block >> 1 & 3 {
+                1 => 1, // a run of one byte
+                3 => return None,
+                _ => (block >> 3) as u64,
+            };
+            position += 3 + length;
+            if block & 1 != 0 {
+                break;
+            }
+        }
+        if descriptor & 4 != 0 {
+            position += 4; // content checksum
+        }
+        frames.push(Candidate {
+            offset,
+            length: Some(position - offset),
+            codec: Codec::Zstd,
+        });
+        offset = position;
+    }
+    Some(frames)
+}
+
+/// Where the LZMA2 data of each block of an xz stream is, from the index
+/// at the end of the stream, as `xz -T` and `pixz` write several blocks;
+/// `None` if a block uses other filters, e.g. BCJ.
+fn xz_blocks(source: &dyn ByteSource) -> Option<Vec<Candidate>> {
+    let mut blocks = Vec::new();
+    let mut end = source.size();
+    // concatenated streams are walked back to front
+    while end > 0 {
+        let mut footer = [0; 12];
+        source
+            .read_full_at(end.checked_sub(12)?, &mut footer)
+            .ok()?;
+        if footer[8..] == [0; 4] {
+            end -= 4; // stream padding
+            continue;
+        }
+        if footer[10..] != *b"YZ" {
+            return None;
+        }
+        let index_size =
+            (u32::from_le_bytes([footer[4], footer[5], footer[6], footer[7]]) as u64 + 1) * 4;
+        let check_size = match footer[9] & 0x0f {
+            0 => 0,
+            1..=3 => 4,
+            4..=6 => 8,
+            7..=9 => 16,
+            10..=12 => 32,
+            _ => 64,
+        };
+        let index_start = (end - 12).checked_sub(index_size)?;
+        if index_size > 64 << 20 {
+            return None;
+        }
+        let mut index = vec![0; index_size as usize];
+        source.read_full_at(index_start, &mut index).ok()?;
+        if index[0] != 0 {
+            return None;
+        }
+        let mut position = 1;
+        let count = xz_varint(&index, &mut position)?;
+        let mut unpadded_sizes = Vec::new();
+        for _ in 0..count {
+            unpadded_sizes.push(xz_varint(&index, &mut position)?);
+            xz_varint(&index, &mut position)?; // the uncompressed size
+        }
+
+        let blocks_size = unpadded_sizes
+            .iter()
+            .map(|size| size.next_multiple_of(4))
+            .sum();
+        let stream_start = index_start.checked_sub(blocks_size)?.checked_sub(12)?;
+        let mut magic = [0; 6];
+        source.read_full_at(stream_start, &mut magic).ok()?;
+        if magic != [0xfd, b'7', b'z', b'X', b'Z', 0x00] {
+            return None;
+        }
+        let mut offset = stream_start + 12;
+        let mut stream_blocks = Vec::new();
+        for unpadded_size in unpadded_sizes {
+            let (header_size, dict_size) = xz_block_header(source, offset)?;
+            stream_blocks.push(Candidate {
+                offset: offset + header_size,
+                length: Some(unpadded_size.checked_sub(header_size + check_size)?),
+                codec: Codec::Lzma2 { dict_size },
+            });
+            offset += unpadded_size.next_multiple_of(4);
+        }
+        blocks.splice(0..0, stream_blocks);
+        end = stream_start;
+    }
+    Some(blocks)
+}
+
+/// The size of the xz block header at `offset` and the dictionary size of
+/// its one filter, which has to be LZMA2.
+fn xz_block_header(source: &dyn ByteSource, offset: u64) -> Option<(u64, u32)> {
+    let mut size = [0];
+    source.read_full_at(offset, &mut size).ok()?;
+    let size = (size[0] as u64 + 1) * 4;
+    let mut header = vec![0; size as usize];
+    if size == 4 || source.read_full_at(offset, &mut header).ok()? < header.len() {
+        return None;
+    }
+    let flags = header[1];
+    if flags & 3 != 0 {
+        return None; // more than one filter
+    }
+    let mut position = 2;
+    for present in [flags & 0x40 != 0, flags & 0x80 != 0] {
+        if present {
+            xz_varint(&header, &mut position)?; // compressed and uncompressed size
+        }
+    }
+    let filter = xz_varint(&header, &mut position)?;
+    let properties_size = xz_varint(&header, &mut position)?;
+    if filter != 0x21 || properties_size != 1 {
+        return None;
+    }
+    let dict_size = match *header.get(position)? {
+        bits @ 0..40 => (2 | (bits as u32 & 1)) << (bits / 2 + 11),
+        40 => u32::MAX,
+        _ => return None,
+    };
+    Some((size, dict_size))
+}
+
+/// Read one of the variable-length integers xz headers and indexes use.
+fn xz_varint(data: &[u8], position: &mut usize) -> Option<u64> {
+    let mut value = 0;
+    for shift in (0..63).step_by(7) {
+        let byte = *data.get(*position)?;
+        *position += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Counts the bytes a buffered reader has handed out.
+struct Counting<R> {
+    inner: R,
+    count: u64,
+}
+
+impl<R> Counting<R> {
+    fn new(inner: R) -> Self {
+        Self { inner, count: 0 }
+    }
+}
+
+impl<R: BufRead> Read for Counting<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for Counting<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.count += amount as u64;
+        self.inner.consume(amount);
+    }
+}
+
+/// The decompressed contents of a compressed stream.
+///
+/// These formats have no random access, so reads decode from the start of
+/// the stream. The decoder of the previous read is kept around so that
+/// reading a file front to back doesn't start over on every chunk.
+pub struct DecompressedSource {
+    compressed: Arc<dyn ByteSource>,
+    codec: Codec,
+    size: OnceLock<u64>,
+    decoder: Mutex<Option<(u64, Box<dyn Read + Send>)>>,
+}
+
+impl DecompressedSource {
+    pub fn new(compressed: Arc<dyn ByteSource>, codec: Codec) -> Self {
+        Self {
+            compressed,
+            codec,
+            size: OnceLock::new(),
+            decoder: Mutex::new(None),
+        }
+    }
+
+    /// For containers that record the decompressed size, so it needn't be worked out.
+    pub fn with_size(compressed: Arc<dyn ByteSource>, codec: Codec, size: u64) -> Self {
+        let source = Self::new(compressed, codec);
+        let _ = source.size.set(size);
+        source
+    }
+
+    fn new_decoder(&self) -> io::Result<Box<dyn Read + Send>> {
+        self.codec.decoder(Arc::clone(&self.compressed))
+    }
+
+    /// The decompressed size, which takes decoding the whole stream once;
+    /// an error if the stream doesn't decode.
+    pub fn try_size(&self) -> io::Result<u64> {
+        if let Some(&size) = self.size.get() {
+            return Ok(size);
+        }
+        let size = io::copy(&mut self.new_decoder()?, &mut io::sink())?;
+        Ok(*self.size.get_or_init(|| size))
+    }
+}
+
+impl ByteSource for DecompressedSource {
+    /// The decompressed size, or 0 for a stream that doesn't decode; use
+    /// [`DecompressedSource::try_size`] to tell those apart.
+    fn size(&self) -> u64 {
+        self.try_size().unwrap_or_else(|e| {
+            warn!("Can't decode {:?} stream: {}", self.codec, e);
+            *self.size.get_or_init(|| 0)
+        })
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let mut cached = self.decoder.lock().unwrap_or_else(|e| e.into_inner());
+        let (mut position, mut decoder) = match cached.take() {
+            Some((position, decoder)) if position <= offset => (position, decoder),
+            _ => (0, self.new_decoder()?),
+        };
+
+        let skipped = io::copy(&mut (&mut decoder).take(offset - position), &mut io::sink())?;
+        position += skipped;
+        if position < offset {
+            return Ok(0); // past the end
+        }
+
+        let n = decoder.read(buf)?;
+        *cached = Some((position + n as u64, decoder));
+        Ok(n)
+    }
+
+    fn recipe(&self) -> Option<Recipe> {
+        Some(Recipe::Decompressed {
+            compressed: Arc::clone(&self.compressed),
+            codec: self.codec,
+            size: self.size.get().copied(),
+        })
+    }
+}
+
+/// The decompressed contents of a whole compressed stream, with random
+/// access where the stream allows it, as BGZF files do, and through `spool`
+/// where it has no structure to help.
+pub fn decompress(
+    compressed: Arc<dyn ByteSource>,
+    codec: Codec,
+    spool: &Spool,
+) -> Arc<dyn ByteSource> {
+    if codec == Codec::Gzip && bgzf_block_size(compressed.as_ref(), 0).is_some() {
+        return Arc::new(BgzfSource::new(compressed));
+    }
+    if codec.is_stream_only() {
+        return Arc::new(SpooledSource::new(compressed, codec, spool.clone()));
+    }
+    Arc::new(DecompressedSource::new(compressed, codec))
+}
+
+/// Like [`decompress`], but with the size known up front: as the stream
+/// records it where that can be trusted, or else by decoding the stream
+/// once, so that a corrupt stream is an error here rather than an empty file.
+pub fn decompress_sized(
+    compressed: Arc<dyn ByteSource>,
+    codec: Codec,
+    spool: &Spool,
+) -> io::Result<Arc<dyn ByteSource>> {
+    if codec == Codec::Gzip && bgzf_block_size(compressed.as_ref(), 0).is_some() {
+        let source = BgzfSource::new(compressed);
+        source.try_size()?;
+        return Ok(Arc::new(source));
+    }
+    if codec.is_stream_only() {
+        let source = SpooledSource::new(compressed, codec, spool.clone());
+        source.try_size()?;
+        return Ok(Arc::new(source));
+    }
+    if let Some(size) = recorded_size(compressed.as_ref(), codec) {
+        return Ok(Arc::new(DecompressedSource::with_size(
+            compressed, codec, size,
+        )));
+    }
+    let source = DecompressedSource::new(compressed, codec);
+    source.try_size()?;
+    Ok(Arc::new(source))
+}
+
+/// The most deflate can expand data by.
+const MAX_DEFLATE_RATIO: u64 = 1032;
+
+/// The decompressed size as the stream itself records it, if it has a
+/// single member or frame whose recorded size is the whole of it.
+fn recorded_size(compressed: &dyn ByteSource, codec: Codec) -> Option<u64> {
+    let size = compressed.size();
+    match codec {
+        // the trailer has the size modulo 4 GiB, exact for a member too
+        // short to decode to that, and only of the last member
+        Codec::Gzip => {
+            if size < 18 || size.saturating_mul(MAX_DEFLATE_RATIO) >= 1 << 32 {
+                return None;
+            }
+            if gzip_candidates(compressed, 1)?.len() != 1 {
+                return None;
+            }
+            let mut trailer = [0; 4];
+            if compressed.read_full_at(size - 4, &mut trailer).ok()? < trailer.len() {
+                return None;
+            }
+            // and what a truncated member has there is seldom within reach
+            let recorded = u32::from_le_bytes(trailer) as u64;
+            (recorded <= size * MAX_DEFLATE_RATIO).then_some(recorded)
+        }
+        Codec::Zstd => {
+            let [frame] = &zstd_frames(compressed)?[..] else {
+                return None;
+            };
+            // the longest frame header there is
+            let mut header = [0; 18];
+            let n = compressed.read_full_at(frame.offset, &mut header).ok()?;
+            zstd::zstd_safe::get_frame_content_size(&header[..n]).ok()?
+        }
+        _ => None,
+    }
+}
+
+/// How compressed streams found while indexing an archive get decoded.
+#[derive(Debug, Clone, Default)]
+pub struct Decoding {
+    /// Where streams without random access get decompressed to.
+    pub spool: Spool,
+    /// How many threads to decode streams of several members with.
+    pub jobs: usize,
+}
+
+/// Reads this far into a spooled stream are decoded from the stream, so
+/// that sniffing formats doesn't decompress whole archives.
+const SPOOL_HEAD: u64 = 64 * 1024;
+
+/// Where streams without random access get decompressed to, once, so that
+/// reads needn't decode them from the start over and over.
+#[derive(Debug, Clone)]
+pub struct Spool {
+    /// The directory to write spool files to.
+    pub dir: PathBuf,
+    /// How many bytes the spool files of a mount may hold together; streams
+    /// that don't fit in what is left are decoded on every read instead.
+    pub max_size: u64,
+    /// Keep spool files after unmounting and reuse them when the same
+    /// archive, by size, modification time and hash, is mounted again.
+    pub keep: bool,
+    /// The bytes spooled so far, shared by clones of the spool.
+    pub used: Arc<AtomicU64>,
+}
+
+impl Default for Spool {
+    fn default() -> Self {
+        Self {
+            dir: std::env::temp_dir(),
+            max_size: 4 << 30,
+            keep: false,
+            used: Arc::default(),
+        }
+    }
+}
+
+impl Spool {
+    /// Decompress `compressed` into a spool file, or reuse a kept one;
+    /// `None` if it doesn't fit in what is left of `max_size`.
+    fn fill(&self, compressed: &Arc<dyn ByteSource>, codec: Codec) -> io::Result<Option<File>> {
+        let kept = match self.keep {
+            true => Some(self.dir.join(self.key(compressed.as_ref())?)),
+            false => None,
+        };
+        if let Some(path) = &kept
+            && let Ok(file) = File::open(path)
+        {
+            debug!("Reusing spool file {}", path.display());
+            return Ok(Some(file));
+        }
+
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let partial = self.dir.join(format!(
+            "tartarfs-{}-{}.partial",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&partial)?;
+        if kept.is_none() {
+            // the open file lives on until unmounting without a name
+            fs::remove_file(&partial)?;
+        }
+
+        let mut written = 0;
+        let fits = codec
+            .decoder(Arc::clone(compressed))
+            .and_then(|mut decoder| {
+                let mut writer = BufWriter::new(&file);
+                let mut buf = vec![0; 64 * 1024];
+                loop {
+                    let n = match decoder.read(&mut buf) {
+                        Ok(0) => break,
+                        Ok(n) => n,
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(e) => return Err(e),
+                    };
+                    if !self.reserve(n as u64) {
+                        return Ok(false);
+                    }
+                    written += n as u64;
+                    writer.write_all(&buf[..n])?;
+                }
+                writer.flush()?;
+                Ok(true)
+            });
+        if !matches!(fits, Ok(true)) {
+            self.release(written);
+            if kept.is_some() {
+                let _ = fs::remove_file(&partial);
+            }
+            return fits.map(|_| None);
+        }
+        debug!("Spooled {} decompressed bytes", written);
+        if let Some(path) = &kept {
+            fs::rename(&partial, path)?;
+        }
+        Ok(Some(file))
+    }
+
+    /// Count `size` more bytes as spooled; false, counting nothing, if that
+    /// would take the spool past `max_size`.
+    fn reserve(&self, size: u64) -> bool {
+        self.used
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+                used.checked_add(size).filter(|&used| used <= self.max_size)
+            })
+            .is_ok()
+    }
+
+    fn release(&self, size: u64) {
+        self.used.fetch_sub(size, Ordering::AcqRel);
+    }
+
+    /// Name a kept spool file after the compressed stream it holds.
+    fn key(&self, compressed: &dyn ByteSource) -> io::Result<String> {
+        let modified = compressed
+            .modified()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |duration| duration.as_secs());
+        let hash = sha256_hex(compressed)?;
+        Ok(format!(
+            "tartarfs-{hash}-{}-{modified}.spool",
+            compressed.size()
+        ))
+    }
+}
+
+/// The decompressed contents of a stream-only codec like lz4, decoded once
+/// into a file in a [`Spool`] on the first read past the start of it.
+///
+/// Streams too big for the spool, or that fail to spool, are decoded on
+/// every read like a [`DecompressedSource`].
+pub struct SpooledSource {
+    compressed: Arc<dyn ByteSource>,
+    codec: Codec,
+    spool: Spool,
+    stream: DecompressedSource,
+    file: OnceLock<Option<(File, u64)>>,
+}
+
+impl SpooledSource {
+    pub fn new(compressed: Arc<dyn ByteSource>, codec: Codec, spool: Spool) -> Self {
+        Self {
+            stream: DecompressedSource::new(Arc::clone(&compressed), codec),
+            compressed,
+            codec,
+            spool,
+            file: OnceLock::new(),
+        }
+    }
+
+    /// The decompressed size, spooling the stream if it fits in the spool;
+    /// an error if the stream doesn't decode.
+    pub fn try_size(&self) -> io::Result<u64> {
+        match self.spooled() {
+            Some(&(_, size)) => Ok(size),
+            None => self.stream.try_size(),
+        }
+    }
+
+    fn spooled(&self) -> Option<&(File, u64)> {
+        self.file
+            .get_or_init(|| {
+                let file = self
+                    .spool
+                    .fill(&self.compressed, self.codec)
+                    .inspect_err(|e| warn!("Can't spool {:?} stream: {}", self.codec, e))
+                    .ok()??;
+                let size = file.metadata().ok()?.len();
+                Some((file, size))
+            })
+            .as_ref()
+    }
+}
+
+impl Drop for SpooledSource {
+    fn drop(&mut self) {
+        // unlinked spool files go away with the source, kept ones stay
+        if !self.spool.keep
+            && let Some(Some((_, size))) = self.file.get()
+        {
+            self.spool.release(*size);
+        }
+    }
+}
+
+impl ByteSource for SpooledSource {
+    fn size(&self) -> u64 {
+        match self.spooled() {
+            Some(&(_, size)) => size,
+            None => self.stream.size(),
+        }
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        if self.file.get().is_none() && offset + buf.len() as u64 <= SPOOL_HEAD {
+            return self.stream.read_at(offset, buf);
+        }
+        match self.spooled() {
+            Some((file, _)) => file.read_at(buf, offset),
+            None => self.stream.read_at(offset, buf),
+        }
+    }
+
+    fn recipe(&self) -> Option<Recipe> {
+        Some(Recipe::Spooled {
+            compressed: Arc::clone(&self.compressed),
+            codec: self.codec,
+        })
+    }
+}
+
+/// The size of the BGZF block at `offset`, from the `BC` subfield of its
+/// gzip header; `None` if there is no BGZF block there.
+fn bgzf_block_size(source: &dyn ByteSource, offset: u64) -> Option<u64> {
+    let mut header = [0; 12];
+    source.read_full_at(offset, &mut header).ok()?;
+    // gzip with deflate and the FEXTRA flag
+    if header[..4] != [0x1f, 0x8b, 8, 4] {
+        return None;
+    }
+    let mut extra = vec![0; u16::from_le_bytes([header[10], header[11]]) as usize];
+    if source.read_full_at(offset + 12, &mut extra).ok()? < extra.len() {
+        return None;
+    }
+    let mut subfields = &extra[..];
+    while subfields.len() >= 4 {
+        let length = u16::from_le_bytes([subfields[2], subfields[3]]) as usize;
+        let data = subfields.get(4..4 + length)?;
+        if subfields[..2] == *b"BC" && length == 2 {
+            return Some(u16::from_le_bytes([data[0], data[1]]) as u64 + 1);
+        }
+        subfields = &subfields[4 + length..];
+    }
+    None
+}
+
+/// A block of a BGZF file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BgzfBlock {
+    /// Where the block starts in the compressed file, and its size there.
+    pub compressed_offset: u64,
+    pub compressed_size: u64,
+    /// Where its contents start in the decompressed stream.
+    pub offset: u64,
+    pub size: u64,
+}
+
+/// The decompressed contents of a BGZF file, the blocked gzip of genomics
+/// tools like `bgzip`: gzip members of at most 64 KiB that record their
+/// size in the gzip header.
+///
+/// Going from header to header indexes the blocks without decompressing
+/// them, so reads only decompress the blocks they cover. The index is
+/// built on first use; a file with anything but BGZF blocks after the
+/// first one is read like any other gzip stream.
+pub struct BgzfSource {
+    compressed: Arc<dyn ByteSource>,
+    blocks: OnceLock<Option<Vec<BgzfBlock>>>,
+    fallback: DecompressedSource,
+    /// The last block read, for reads of a block in several parts.
+    cached: Mutex<Option<(usize, Arc<Vec<u8>>)>>,
+}
+
+impl BgzfSource {
+    pub fn new(compressed: Arc<dyn ByteSource>) -> Self {
+        Self {
+            fallback: DecompressedSource::new(Arc::clone(&compressed), Codec::Gzip),
+            compressed,
+            blocks: OnceLock::new(),
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// For a file whose blocks were indexed before, e.g. by a saved index.
+    pub fn with_blocks(compressed: Arc<dyn ByteSource>, blocks: Vec<BgzfBlock>) -> Self {
+        let source = Self::new(compressed);
+        let _ = source.blocks.set(Some(blocks));
+        source
+    }
+
+    fn blocks(&self) -> Option<&[BgzfBlock]> {
+        let blocks = self.blocks.get_or_init(|| {
+            let mut blocks = Vec::new();
+            let (mut compressed_offset, mut offset) = (0, 0);
+            while compressed_offset < self.compressed.size() {
+                let compressed_size = bgzf_block_size(self.compressed.as_ref(), compressed_offset)?;
+                // the trailer ends with the decompressed size
+                let mut size = [0; 4];
+                let end = compressed_offset + compressed_size;
+                if compressed_size < 26
+                    || self.compressed.read_full_at(end - 4, &mut size).ok()? < 4
+                {
+                    return None;
+                }
+                let size = u32::from_le_bytes(size) as u64;
+                blocks.push(BgzfBlock {
+                    compressed_offset,
+                    compressed_size,
+                    offset,
+                    size,
+                });
+                compressed_offset = end;
+                offset += size;
+            }
+            Some(blocks)
+        });
+        blocks.as_deref()
+    }
+
+    /// The decompressed size, from the block index if there is one; an
+    /// error if the file is read as a gzip stream that doesn't decode.
+    pub fn try_size(&self) -> io::Result<u64> {
+        match self.blocks() {
+            Some(blocks) => Ok(blocks.last().map_or(0, |last| last.offset + last.size)),
+            None => self.fallback.try_size(),
+        }
+    }
+
+    fn read_block(&self, index: usize, block: &BgzfBlock) -> io::Result<Arc<Vec<u8>>> {
+        let mut cached = self.cached.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((cached_index, data)) = &*cached
+            && *cached_index == index
+        {
+            return Ok(Arc::clone(data));
+        }
+
+        let mut compressed = vec![0; block.compressed_size as usize];
+        self.compressed
+            .read_full_at(block.compressed_offset, &mut compressed)?;
+        let extra_size = u16::from_le_bytes([compressed[10], compressed[11]]) as usize;
+        let deflated = compressed
+            .get(12 + extra_size..compressed.len() - 8)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "bad BGZF block"))?;
+        let mut data = Vec::with_capacity(block.size as usize);
+        DeflateDecoder::new(deflated).read_to_end(&mut data)?;
+
+        let trailer = &compressed[compressed.len() - 8..];
+        let mut crc = flate2::Crc::new();
+        crc.update(&data);
+        if data.len() as u64 != block.size || crc.sum().to_le_bytes() != trailer[..4] {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "corrupt BGZF block",
+            ));
+        }
+        let data = Arc::new(data);
+        *cached = Some((index, Arc::clone(&data)));
+        Ok(data)
+    }
+}
+
+impl ByteSource for BgzfSource {
+    fn size(&self) -> u64 {
+        match self.blocks() {
+            Some(blocks) => blocks.last().map_or(0, |last| last.offset + last.size),
+            None => self.fallback.size(),
+        }
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(blocks) = self.blocks() else {
+            return self.fallback.read_at(offset, buf);
+        };
+        // skipping empty blocks, like the one marking the end of the file
+        let index = blocks.partition_point(|block| block.offset + block.size <= offset);
+        let Some(block) = blocks.get(index) else {
+            return Ok(0);
+        };
+        let data = self.read_block(index, block)?;
+        let start = (offset - block.offset) as usize;
+        let n = buf.len().min(data.len() - start);
+        buf[..n].copy_from_slice(&data[start..start + n]);
+        Ok(n)
+    }
+
+    fn recipe(&self) -> Option<Recipe> {
+        Some(Recipe::Bgzf {
+            compressed: Arc::clone(&self.compressed),
+            blocks: self.blocks.get().cloned().flatten(),
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::tartarfs::source::{ConcatSource, MemorySource};
+    use std::io::Write;
+
+    fn compress(codec: Codec, data: &[u8]) -> io::Result<Vec<u8>> {
+        Ok(match codec {
+            Codec::Deflate => {
+                let mut encoder =
+                    flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::fast());
+                encoder.write_all(data)?;
+                encoder.finish()?
+            }
+            Codec::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+                encoder.write_all(data)?;
+                encoder.finish()?
+            }
+            Codec::Zstd => zstd::encode_all(data, 1)?,
+            Codec::Xz => {
+                let mut encoder = xz2::write::XzEncoder::new(Vec::new(), 1);
+                encoder.write_all(data)?;
+                encoder.finish()?
+            }
+            Codec::Bzip2 => {
+                let mut encoder =
+                    bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::fast());
+                encoder.write_all(data)?;
+                encoder.finish()?
+            }
+            Codec::Lz4 => {
+                let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
+                encoder.write_all(data)?;
+                encoder.finish().map_err(io::Error::other)?
+            }
+            Codec::Lzip => {
+                let options = lzma_rust2::LzipOptions::with_preset(1);
+                let mut encoder = lzma_rust2::LzipWriter::new(Vec::new(), options);
+                encoder.write_all(data)?;
+                encoder.finish()?
+            }
+            Codec::Brotli => {
+                let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, 1, 22);
+                encoder.write_all(data)?;
+                encoder.into_inner()
+            }
+            Codec::LzmaAlone => {
+                let options = lzma_rust2::LzmaOptions::with_preset(1);
+                let size = Some(data.len() as u64);
+                let mut encoder =
+                    lzma_rust2::LzmaWriter::new_use_header(Vec::new(), &options, size)?;
+                encoder.write_all(data)?;
+                encoder.finish()?
+            }
+            Codec::Lzma {
+                properties,
+                dict_size,
+                ..
+            } => {
+                let mut options = lzma_rust2::LzmaOptions::with_preset(1);
+                let properties = properties as u32;
+                options.dict_size = dict_size;
+                (options.lc, options.lp, options.pb) =
+                    (properties % 9, properties / 9 % 5, properties / 45);
+                let mut encoder =
+                    lzma_rust2::LzmaWriter::new_no_header(Vec::new(), &options, false)?;
+                encoder.write_all(data)?;
+                encoder.finish()?
+            }
+            Codec::Lzma2 { dict_size } => {
+                let mut options = lzma_rust2::Lzma2Options::with_preset(1);
+                options.lzma_options.dict_size = dict_size;
+                let mut encoder = lzma_rust2::Lzma2Writer::new(Vec::new(), options);
+                encoder.write_all(data)?;
+                encoder.finish()?
+            }
+        })
+    }
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(
+            Codec::from_name("logs/app.log.gz"),
+            Some((Codec::Gzip, "logs/app.log"))
+        );
+        assert_eq!(Codec::from_name("data.ZST"), Some((Codec::Zstd, "data")));
+        assert_eq!(Codec::from_name("x.tar.xz"), Some((Codec::Xz, "x.tar")));
+        assert_eq!(Codec::from_name("a.bz2"), Some((Codec::Bzip2, "a")));
+        assert_eq!(Codec::from_name("a.tar.lz"), Some((Codec::Lzip, "a.tar")));
+        assert_eq!(Codec::from_name("a.lzma"), Some((Codec::LzmaAlone, "a")));
+        assert_eq!(
+            Codec::from_name("index.html.br"),
+            Some((Codec::Brotli, "index.html"))
+        );
+        assert_eq!(Codec::from_name(".gz"), None);
+        assert_eq!(Codec::from_name("dir/.gz"), None);
+        assert_eq!(Codec::from_name("plain.txt"), None);
+    }
+
+    #[test]
+    fn test_random_access() -> io::Result<()> {
+        let text: Vec<u8> = (0..100_000u32).flat_map(|i| i.to_le_bytes()).collect();
+
+        for codec in [
+            Codec::Deflate,
+            Codec::Gzip,
+            Codec::Zstd,
+            Codec::Xz,
+            Codec::Bzip2,
+            Codec::Lz4,
+            Codec::Lzip,
+            Codec::Brotli,
+            Codec::LzmaAlone,
+            Codec::Lzma {
+                properties: 0x5d,
+                dict_size: 1 << 16,
+                size: text.len() as u64,
+            },
+            Codec::Lzma2 { dict_size: 1 << 16 },
+        ] {
+            let compressed: Arc<dyn ByteSource> =
+                Arc::new(MemorySource::new(compress(codec, &text)?));
+            assert!(codec.sniff(compressed.as_ref()), "{codec:?} magic");
+
+            let source = DecompressedSource::new(compressed, codec);
+            assert_eq!(source.size(), text.len() as u64);
+
+            // forwards, backwards and past the end
+            let mut buf = vec![0; 1000];
+            for offset in [0, 5000, 300_000, 1234] {
+                let n = source.read_full_at(offset, &mut buf)?;
+                assert_eq!(&buf[..n], &text[offset as usize..offset as usize + 1000]);
+            }
+            assert_eq!(source.read_full_at(text.len() as u64 - 10, &mut buf)?, 10);
+            assert_eq!(source.read_full_at(text.len() as u64 + 10, &mut buf)?, 0);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_detect() -> io::Result<()> {
+        for codec in [Codec::Lz4, Codec::Lzip, Codec::LzmaAlone] {
+            let compressed = MemorySource::new(compress(codec, b"detect me")?);
+            assert_eq!(Codec::detect(&compressed), Some(codec));
+        }
+        let text = MemorySource::new(&b"]\0\0\x80\0 just text that looks a bit like lzma"[..]);
+        assert_eq!(Codec::detect(&text), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_spool() -> io::Result<()> {
+        let text: Vec<u8> = (0..100_000u32).flat_map(|i| i.to_le_bytes()).collect();
+        let compressed: Arc<dyn ByteSource> =
+            Arc::new(MemorySource::new(compress(Codec::Lz4, &text)?));
+        let dir = tempfile::TempDir::new()?;
+        let spool_files = || fs::read_dir(dir.path()).map(|files| files.count());
+
+        // unlinked as soon as it is created
+        let spool = Spool {
+            dir: dir.path().to_path_buf(),
+            ..Spool::default()
+        };
+        let source = SpooledSource::new(Arc::clone(&compressed), Codec::Lz4, spool.clone());
+        let mut buf = vec![0; 1000];
+        assert_eq!(source.read_full_at(100, &mut buf)?, 1000);
+        assert!(
+            source.file.get().is_none(),
+            "the head is read from the stream"
+        );
+        assert_eq!(source.size(), text.len() as u64);
+        assert!(source.file.get().is_some_and(Option::is_some));
+        assert_eq!(spool_files()?, 0);
+        for offset in [300_000, 5000] {
+            source.read_full_at(offset, &mut buf)?;
+            assert_eq!(buf, &text[offset as usize..offset as usize + 1000]);
+        }
+
+        // too big to spool
+        let small = Spool {
+            max_size: 1000,
+            ..spool.clone()
+        };
+        let source = SpooledSource::new(Arc::clone(&compressed), Codec::Lz4, small);
+        assert_eq!(source.size(), text.len() as u64);
+        assert!(source.file.get().is_some_and(Option::is_none));
+        source.read_full_at(300_000, &mut buf)?;
+        assert_eq!(buf, &text[300_000..301_000]);
+
+        // kept and reused
+        let kept = Spool {
+            keep: true,
+            ..spool
+        };
+        for _ in 0..2 {
+            let source = SpooledSource::new(Arc::clone(&compressed), Codec::Lz4, kept.clone());
+            source.read_full_at(200_000, &mut buf)?;
+            assert_eq!(buf, &text[200_000..201_000]);
+            assert_eq!(spool_files()?, 1);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_spool_budget() -> io::Result<()> {
+        let text: Vec<u8> = (0..100_000u32).flat_map(|i| i.to_le_bytes()).collect();
+        let compressed: Arc<dyn ByteSource> =
+            Arc::new(MemorySource::new(compress(Codec::Lz4, &text)?));
+        let dir = tempfile::TempDir::new()?;
+        // room for one stream but not two
+        let spool = Spool {
+            dir: dir.path().to_path_buf(),
+            max_size: text.len() as u64 * 3 / 2,
+            ..Spool::default()
+        };
+        let spooled = |source: &SpooledSource| source.file.get().is_some_and(Option::is_some);
+
+        let first = SpooledSource::new(Arc::clone(&compressed), Codec::Lz4, spool.clone());
+        assert_eq!(first.size(), text.len() as u64);
+        assert!(spooled(&first));
+        let second = SpooledSource::new(Arc::clone(&compressed), Codec::Lz4, spool.clone());
+        assert_eq!(second.size(), text.len() as u64);
+        assert!(!spooled(&second), "the spool is full");
+        let mut buf = vec![0; 1000];
+        second.read_full_at(300_000, &mut buf)?;
+        assert_eq!(buf, &text[300_000..301_000]);
+        assert_eq!(spool.used.load(Ordering::Acquire), text.len() as u64);
+
+        // dropping a spooled stream makes room again
+        drop(first);
+        assert_eq!(spool.used.load(Ordering::Acquire), 0);
+        let third = SpooledSource::new(Arc::clone(&compressed), Codec::Lz4, spool);
+        assert_eq!(third.size(), text.len() as u64);
+        assert!(spooled(&third));
+        Ok(())
+    }
+
+    /// An xz stream of several blocks, as `xz -T` writes.
+    pub(crate) fn xz_blocks(data: &[u8], block_size: u64) -> io::Result<Vec<u8>> {
+        let mut options = lzma_rust2::XzOptions::with_preset(1);
+        options.lzma_options.dict_size = 1 << 16;
+        options.set_block_size(std::num::NonZeroU64::new(block_size));
+        let mut encoder = lzma_rust2::XzWriter::new(Vec::new(), options)?;
+        encoder.write_all(data)?;
+        encoder.finish()
+    }
+
+    #[test]
+    fn test_parallel_members() -> io::Result<()> {
+        let text: Vec<u8> = (0..100_000u32).flat_map(|i| i.to_le_bytes()).collect();
+        let pieces: Vec<&[u8]> = text.chunks(70_001).collect();
+
+        // one member has another gzip member stored in it as is
+        let inner = compress(Codec::Gzip, b"inner")?;
+        let mut stored = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::none());
+        stored.write_all(&inner)?;
+        let mut gzip = compress(Codec::Gzip, pieces[0])?;
+        gzip.extend(stored.finish()?);
+        let mut gzip_text = [pieces[0], &inner].concat();
+        for piece in &pieces[1..] {
+            gzip.extend(compress(Codec::Gzip, piece)?);
+            gzip_text.extend_from_slice(piece);
+        }
+
+        // `pzstd` puts a skippable frame before each frame
+        let mut zstd = Vec::new();
+        for piece in &pieces {
+            zstd.extend([0x50, 0x2a, 0x4d, 0x18, 4, 0, 0, 0, 1, 2, 3, 4]);
+            zstd.extend(compress(Codec::Zstd, piece)?);
+        }
+
+        for (codec, compressed, text, count) in [
+            (Codec::Gzip, gzip, &gzip_text, 7),
+            (Codec::Gzip, bgzf(&text)?, &text, 8),
+            (Codec::Zstd, zstd, &text, 6),
+            (Codec::Xz, xz_blocks(&text, 100_000)?, &text, 4),
+        ] {
+            let source: Arc<dyn ByteSource> = Arc::new(MemorySource::new(compressed));
+            let members = parallel_members(&source, codec, 3, |reader| {
+                let mut first = Vec::new();
+                reader.take(4).read_to_end(&mut first)?;
+                Ok(first)
+            });
+            let members = members.unwrap_or_else(|| panic!("{codec:?} members"));
+            assert_eq!(members.len(), count, "{codec:?} members");
+
+            let mut offset = 0;
+            for member in &members {
+                assert_eq!(member.read, text[offset..text.len().min(offset + 4)]);
+                offset += member.content.size() as usize;
+            }
+            let contents = members.into_iter().map(|member| member.content).collect();
+            let decompressed = ConcatSource::new(contents);
+            assert_eq!(decompressed.size(), text.len() as u64);
+            let mut buf = vec![0; 1000];
+            for offset in [300_000, 69_500, 0] {
+                let n = decompressed.read_full_at(offset, &mut buf)?;
+                assert_eq!(&buf[..n], &text[offset as usize..offset as usize + 1000]);
+            }
+        }
+
+        let lz4: Arc<dyn ByteSource> = Arc::new(MemorySource::new(compress(Codec::Lz4, &text)?));
+        assert!(parallel_members(&lz4, Codec::Lz4, 3, |_| Ok(())).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parallel_members_cut_short() -> io::Result<()> {
+        let text: Vec<u8> = (0..100_000u32).flat_map(|i| i.to_le_bytes()).collect();
+        let mut gzip = compress(Codec::Gzip, &text[..200_000])?;
+        let second = gzip.len();
+        gzip.extend(compress(Codec::Gzip, &text[200_000..])?);
+
+        // padding after the last member is fine
+        let mut padded = gzip.clone();
+        padded.extend([0; 1000]);
+        let padded: Arc<dyn ByteSource> = Arc::new(MemorySource::new(padded));
+        let members = parallel_members(&padded, Codec::Gzip, 3, |_| Ok(())).unwrap();
+        assert_eq!(members.len(), 2);
+
+        // but a member that doesn't decode leaves it to decoding from the start
+        let mut broken = gzip;
+        broken[second + 1000..second + 1100].fill(0xff);
+        let broken: Arc<dyn ByteSource> = Arc::new(MemorySource::new(broken));
+        assert!(parallel_members(&broken, Codec::Gzip, 3, |_| Ok(())).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_gzip_magic_in_payload() -> io::Result<()> {
+        // stored as is, the payload puts the gzip magic in the compressed stream,
+        // once with a header no writer makes and once with a plausible one
+        let payload = [
+            &b"before "[..],
+            &[0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0x77, 0x99],
+            b" between ",
+            &[0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 3],
+            b" after",
+        ]
+        .concat();
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::none());
+        gzip.write_all(&payload)?;
+        let gzip = gzip.finish()?;
+        let plausible = gzip
+            .windows(10)
+            .position(|w| w == &payload[26..36])
+            .unwrap() as u64;
+
+        let offsets: Vec<u64> = gzip_candidates(&MemorySource::new(gzip.clone()), 3)
+            .unwrap()
+            .iter()
+            .map(|candidate| candidate.offset)
+            .collect();
+        assert_eq!(offsets, [0, plausible]);
+
+        // the plausible one lies inside the first member, so it isn't split there
+        let source: Arc<dyn ByteSource> = Arc::new(MemorySource::new(gzip));
+        let members = parallel_members(&source, Codec::Gzip, 3, |_| Ok(())).unwrap();
+        assert_eq!(members.len(), 1);
+        let mut buf = vec![0; payload.len() + 1];
+        let n = members[0].content.read_full_at(0, &mut buf)?;
+        assert_eq!(&buf[..n], &payload[..]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_recorded_size() -> io::Result<()> {
+        let text: Vec<u8> = (0..100_000u32).flat_map(|i| i.to_le_bytes()).collect();
+        let recorded = |codec, data: Vec<u8>| recorded_size(&MemorySource::new(data), codec);
+        assert_eq!(
+            recorded(Codec::Gzip, compress(Codec::Gzip, &text)?),
+            Some(400_000)
+        );
+        // as the `zstd` tool writes it, but not when streaming
+        let zstd = zstd::bulk::compress(&text, 3)?;
+        assert_eq!(recorded(Codec::Zstd, zstd.clone()), Some(400_000));
+        assert_eq!(recorded(Codec::Zstd, compress(Codec::Zstd, &text)?), None);
+        assert_eq!(recorded(Codec::Xz, compress(Codec::Xz, &text)?), None);
+
+        // only the last of several members is in the trailer
+        let mut gzip = compress(Codec::Gzip, &text)?;
+        gzip.extend(compress(Codec::Gzip, b"more")?);
+        assert_eq!(recorded(Codec::Gzip, gzip), None);
+        let mut truncated = compress(Codec::Gzip, &text)?;
+        truncated.truncate(truncated.len() / 2);
+        assert_eq!(recorded(Codec::Gzip, truncated), None);
+
+        // reading from it decodes as usual
+        let compressed = Arc::new(MemorySource::new(zstd));
+        let source = decompress_sized(compressed, Codec::Zstd, &Spool::default())?;
+        assert_eq!(source.size(), text.len() as u64);
+        let mut buf = vec![0; 100];
+        source.read_full_at(300_000, &mut buf)?;
+        assert_eq!(buf, text[300_000..300_100]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_gzip_members() -> io::Result<()> {
+        let mut data = compress(Codec::Gzip, b"first ")?;
+        data.extend(compress(Codec::Gzip, b"second")?);
+        data.extend([0; 512]);
+
+        let source = DecompressedSource::new(Arc::new(MemorySource::new(data)), Codec::Gzip);
+        let mut buf = [0; 100];
+        let n = source.read_full_at(0, &mut buf)?;
+        assert_eq!(&buf[..n], b"first second");
+        assert_eq!(source.size(), 12);
+
+        Ok(())
+    }
+
+    /// Compress like `bgzip`, in blocks with their size in the header,
+    /// ending with an empty block.
+    pub(crate) fn bgzf(data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut compressed = Vec::new();
+        for chunk in data.chunks(60_000).chain([&[][..]]) {
+            let mut encoder =
+                flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::fast());
+            encoder.write_all(chunk)?;
+            let deflated = encoder.finish()?;
+            let block_size = (deflated.len() + 26 - 1) as u16;
+            compressed.extend([
+                0x1f, 0x8b, 8, 4, 0, 0, 0, 0, 0, 0xff, 6, 0, b'B', b'C', 2, 0,
+            ]);
+            compressed.extend(block_size.to_le_bytes());
+            compressed.extend(deflated);
+            let mut crc = flate2::Crc::new();
+            crc.update(chunk);
+            compressed.extend(crc.sum().to_le_bytes());
+            compressed.extend((chunk.len() as u32).to_le_bytes());
+        }
+        Ok(compressed)
+    }
+
+    #[test]
+    fn test_bgzf() -> io::Result<()> {
+        let text: Vec<u8> = (0..100_000u32).flat_map(|i| i.to_le_bytes()).collect();
+        let compressed: Arc<dyn ByteSource> = Arc::new(MemorySource::new(bgzf(&text)?));
+        let source = decompress(Arc::clone(&compressed), Codec::Gzip, &Spool::default());
+        assert_eq!(source.size(), text.len() as u64);
+
+        // within a block, across blocks and past the end
+        let mut buf = vec![0; 1000];
+        for offset in [300_000, 0, 59_500, 1234] {
+            let n = source.read_full_at(offset, &mut buf)?;
+            assert_eq!(&buf[..n], &text[offset as usize..offset as usize + 1000]);
+        }
+        assert_eq!(source.read_full_at(text.len() as u64 + 10, &mut buf)?, 0);
+
+        // anything else after the blocks is read by decoding from the start
+        let mut mixed = bgzf(b"blocked ")?;
+        mixed.extend(compress(Codec::Gzip, b"plain")?);
+        let source = decompress(
+            Arc::new(MemorySource::new(mixed)),
+            Codec::Gzip,
+            &Spool::default(),
+        );
+        let n = source.read_full_at(0, &mut buf)?;
+        assert_eq!(&buf[..n], b"blocked plain");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,147 @@
+use super::source::{ByteSource, Recipe};
+use md5::Md5;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Sha224, Sha256, Sha384, Sha512};
+use std::io::{self, Error, ErrorKind};
+use std::sync::{Arc, OnceLock};
+
+/// Hash algorithms that archives record file digests with.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Algorithm {
+    Md5,
+    Sha1,
+    Sha224,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+/// The digest a member's data is expected to have.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Digest {
+    pub algorithm: Algorithm,
+    pub expected: Vec<u8>,
+}
+
+impl Digest {
+    /// Parse a hex digest, e.g. as listed by `sha256sum`.
+    pub fn from_hex(algorithm: Algorithm, hex: &str) -> Option<Self> {
+        if !hex.len().is_multiple_of(2) {
+            return None;
+        }
+        let expected = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        Some(Self {
+            algorithm,
+            expected,
+        })
+    }
+
+    /// Hash all of `source` and compare.
+    pub fn matches(&self, source: &dyn ByteSource) -> io::Result<bool> {
+        let actual = match self.algorithm {
+            Algorithm::Md5 => hash::<Md5>(source)?,
+            Algorithm::Sha1 => hash::<Sha1>(source)?,
+            Algorithm::Sha224 => hash::<Sha224>(source)?,
+            Algorithm::Sha256 => hash::<Sha256>(source)?,
+            Algorithm::Sha384 => hash::<Sha384>(source)?,
+            Algorithm::Sha512 => hash::<Sha512>(source)?,
+        };
+        Ok(actual == self.expected)
+    }
+}
+
+/// The SHA-256 of all of `source` in hex, e.g. to name cache files by.
+pub(crate) fn sha256_hex(source: &dyn ByteSource) -> io::Result<String> {
+    Ok(hash::<Sha256>(source)?
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
+
+fn hash<D: sha2::Digest>(source: &dyn ByteSource) -> io::Result<Vec<u8>> {
+    let mut hasher = D::new();
+    let mut buf = vec![0; 64 * 1024];
+    let mut offset = 0;
+    loop {
+        let n = source.read_full_at(offset, &mut buf)?;
+        if n == 0 {
+            return Ok(hasher.finalize().to_vec());
+        }
+        hasher.update(&buf[..n]);
+        offset += n as u64;
+    }
+}
+
+/// Member data that is checked against its digest before the first read,
+/// so corrupted files fail to read instead of quietly returning bad data.
+pub struct VerifiedSource {
+    inner: Arc<dyn ByteSource>,
+    digest: Digest,
+    verified: OnceLock<bool>,
+}
+
+impl VerifiedSource {
+    pub fn new(inner: Arc<dyn ByteSource>, digest: Digest) -> Self {
+        Self {
+            inner,
+            digest,
+            verified: OnceLock::new(),
+        }
+    }
+}
+
+impl ByteSource for VerifiedSource {
+    fn size(&self) -> u64 {
+        self.inner.size()
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        // a failed read while hashing isn't a mismatch, it may work next time
+        if self.verified.get().is_none() {
+            let _ = self.verified.set(self.digest.matches(self.inner.as_ref())?);
+        }
+        if self.verified.get() != Some(&true) {
+            return Err(Error::new(ErrorKind::InvalidData, "digest mismatch"));
+        }
+        self.inner.read_at(offset, buf)
+    }
+
+    fn recipe(&self) -> Option<Recipe> {
+        Some(Recipe::Verified {
+            inner: Arc::clone(&self.inner),
+            digest: self.digest.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::MemorySource;
+
+    #[test]
+    fn test_verified_source() -> io::Result<()> {
+        // sha256 of "hello"
+        let hex = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        let digest = Digest::from_hex(Algorithm::Sha256, hex).unwrap();
+
+        let good = VerifiedSource::new(
+            Arc::new(MemorySource::new(b"hello".as_slice())),
+            digest.clone(),
+        );
+        let mut buf = [0; 5];
+        assert_eq!(good.read_full_at(0, &mut buf)?, 5);
+        assert_eq!(&buf, b"hello");
+
+        let bad = VerifiedSource::new(Arc::new(MemorySource::new(b"hullo".as_slice())), digest);
+        assert!(bad.read_full_at(0, &mut buf).is_err());
+
+        assert_eq!(Digest::from_hex(Algorithm::Md5, "abc"), None);
+        assert_eq!(Digest::from_hex(Algorithm::Md5, "zz"), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,168 @@
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use std::fmt;
+use std::path::Path;
+
+/// Decides which archive paths are exposed in the mounted view.
+///
+/// A path is visible if it (or one of its ancestors) matches an include
+/// pattern, or if there are no include patterns at all, and neither it
+/// nor any of its ancestors matches an exclude pattern. So is a directory
+/// on the way to what an include pattern matches, like `docs` for `docs/**`.
+#[derive(Clone, Default)]
+pub struct PathFilter {
+    include: Option<GlobSet>,
+    exclude: Option<GlobSet>,
+    /// The part of each include pattern before its first wildcard.
+    include_prefixes: Vec<String>,
+    /// The patterns the sets were built from, which a `GlobSet` doesn't keep.
+    patterns: (Vec<String>, Vec<String>),
+}
+
+impl PathFilter {
+    pub fn new<I, E>(include: I, exclude: E) -> Result<Self, globset::Error>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+        E: IntoIterator,
+        E::Item: AsRef<str>,
+    {
+        let include: Vec<String> = include.into_iter().map(|p| p.as_ref().into()).collect();
+        let exclude: Vec<String> = exclude.into_iter().map(|p| p.as_ref().into()).collect();
+        Ok(Self {
+            include: build_glob_set(&include)?,
+            exclude: build_glob_set(&exclude)?,
+            include_prefixes: include.iter().map(|p| literal_prefix(p)).collect(),
+            patterns: (include, exclude),
+        })
+    }
+
+    /// Read exclude patterns from a file, one per line;
+    /// blank lines and lines starting with `#` are ignored.
+    pub fn read_patterns<P: AsRef<Path>>(path: P) -> std::io::Result<Vec<String>> {
+        let text = std::fs::read_to_string(path)?;
+        let patterns = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(String::from)
+            .collect();
+        Ok(patterns)
+    }
+
+    /// Check a normalized archive path, e.g. `docs/guide/index.md`.
+    pub fn is_match(&self, path: &str) -> bool {
+        if let Some(exclude) = &self.exclude
+            && Path::new(path).ancestors().any(|p| exclude.is_match(p))
+        {
+            return false;
+        }
+        match &self.include {
+            Some(include) => Path::new(path).ancestors().any(|p| include.is_match(p)),
+            None => true,
+        }
+    }
+
+    /// Like [`PathFilter::is_match`], but for a directory, which is also
+    /// visible on the way to what an include pattern matches, so that it
+    /// keeps its own mode and owner rather than getting made up.
+    pub fn is_dir_match(&self, path: &str) -> bool {
+        if self.is_match(path) {
+            return true;
+        }
+        if let Some(exclude) = &self.exclude
+            && Path::new(path).ancestors().any(|p| exclude.is_match(p))
+        {
+            return false;
+        }
+        self.include_prefixes.iter().any(|prefix| {
+            prefix
+                .strip_prefix(path)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+    }
+}
+
+impl fmt::Debug for PathFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PathFilter")
+            .field("include", &self.patterns.0)
+            .field("exclude", &self.patterns.1)
+            .finish()
+    }
+}
+
+fn build_glob_set(patterns: &[String]) -> Result<Option<GlobSet>, globset::Error> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        // archive paths have no leading slash, so allow `/docs/**` too
+        builder.add(build_glob(pattern.trim_start_matches('/'))?);
+    }
+    builder.build().map(Some)
+}
+
+/// The whole components of `pattern` before its first wildcard, e.g.
+/// `docs/guide` for `docs/guide/*.md`.
+fn literal_prefix(pattern: &str) -> String {
+    let pattern = pattern.trim_start_matches('/');
+    let components: Vec<&str> = pattern
+        .split('/')
+        .take_while(|component| !component.contains(['*', '?', '[', '{', '\\']))
+        .collect();
+    components.join("/")
+}
+
+fn build_glob(pattern: &str) -> Result<Glob, globset::Error> {
+    // `*` stays within a path component, `**` crosses them
+    GlobBuilder::new(pattern).literal_separator(true).build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PathFilter;
+
+    #[test]
+    fn test_include_and_exclude() {
+        let filter = PathFilter::new(["docs/**", "README.md"], ["**/*.tmp", "docs/private"])
+            .expect("patterns should be valid");
+
+        assert!(filter.is_match("docs/index.md"));
+        assert!(filter.is_match("docs/guide/intro.md"));
+        assert!(filter.is_match("README.md"));
+        assert!(!filter.is_match("src/main.rs"));
+        assert!(!filter.is_match("docs/notes.tmp"));
+        assert!(!filter.is_match("docs/private"));
+        assert!(!filter.is_match("docs/private/secret.md"));
+    }
+
+    #[test]
+    fn test_dirs_on_the_way() {
+        let filter = PathFilter::new(["docs/guide/*.md", "src/**"], ["src/tmp"]).unwrap();
+
+        assert!(filter.is_dir_match("docs"));
+        assert!(filter.is_dir_match("docs/guide"));
+        assert!(filter.is_dir_match("src"));
+        assert!(!filter.is_dir_match("doc"));
+        assert!(!filter.is_dir_match("docs/other"));
+        assert!(!filter.is_dir_match("src/tmp"));
+        // only directories are let in that way
+        assert!(!filter.is_match("docs"));
+    }
+
+    #[test]
+    fn test_single_star_stays_in_component() {
+        let filter = PathFilter::new(["*.md"], Vec::<String>::new()).unwrap();
+
+        assert!(filter.is_match("README.md"));
+        assert!(!filter.is_match("docs/index.md"));
+    }
+
+    #[test]
+    fn test_default_matches_everything() {
+        let filter = PathFilter::default();
+
+        assert!(filter.is_match("anything/at/all.txt"));
+    }
+}
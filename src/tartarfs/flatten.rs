@@ -0,0 +1,148 @@
+use super::TartarFS;
+use crate::inode::Inode;
+use std::collections::{HashMap, HashSet};
+use tracing::debug;
+
+/// How to name files that share a base name once everything is in the root.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, clap::ValueEnum)]
+pub enum Collisions {
+    /// Keep the first name, suffix the rest with a counter: `c.txt`, `c-1.txt`, ...
+    Counter,
+    /// Name every colliding file after its original path: `a__b__c.txt`.
+    EncodePath,
+}
+
+impl TartarFS {
+    /// Re-key the index so that every regular file sits directly in the root
+    /// and the directories left empty by that disappear. Symlinks and special
+    /// files stay where they are, since their meaning may depend on it.
+    pub(super) fn flatten(&mut self, collisions: Collisions) {
+        let root_inode = self.path_to_inode[""];
+        let is_file = |inode: &Inode| {
+            self.inode_to_item
+                .get(inode)
+                .is_some_and(|i| !i.is_dir && i.link_target.is_none() && i.special.is_none())
+        };
+
+        // sort by original path so names don't depend on archive order
+        let mut files: Vec<(String, Inode)> = self
+            .path_to_inode
+            .iter()
+            .filter(|(_, inode)| is_file(inode))
+            .map(|(path, &inode)| (path.clone(), inode))
+            .collect();
+        files.sort();
+
+        let mut by_base_name: HashMap<&str, Vec<&str>> = HashMap::new();
+        for (path, _) in &files {
+            by_base_name.entry(base_name(path)).or_default().push(path);
+        }
+
+        // what stays, along with the directories it is in
+        let mut kept: Vec<(String, Inode)> = Vec::new();
+        let mut kept_dirs: HashSet<&str> = HashSet::new();
+        for (path, &inode) in &self.path_to_inode {
+            let Some(item) = self.inode_to_item.get(&inode) else {
+                continue;
+            };
+            if path.is_empty() || item.is_dir || is_file(&inode) {
+                continue;
+            }
+            kept.push((path.clone(), inode));
+            kept_dirs.extend(path.match_indices('/').map(|(index, _)| &path[..index]));
+        }
+        for dir in &kept_dirs {
+            kept.push((dir.to_string(), self.path_to_inode[*dir]));
+        }
+
+        // files moved to the root mustn't take the names of what stays there
+        let mut taken: HashSet<String> = kept
+            .iter()
+            .map(|(path, _)| path.split('/').next().unwrap_or(path).to_string())
+            .collect();
+        let mut renamed: Vec<(String, Inode)> = Vec::with_capacity(files.len());
+        for (path, inode) in &files {
+            let siblings = &by_base_name[base_name(path)];
+            let candidate = if siblings.len() == 1 {
+                base_name(path).to_string()
+            } else {
+                match collisions {
+                    Collisions::Counter => base_name(path).to_string(),
+                    Collisions::EncodePath => path.replace('/', "__"),
+                }
+            };
+            let name = unique_name(&taken, candidate);
+            debug!("Flattened {} to {}", path, name);
+            taken.insert(name.clone());
+            renamed.push((name, *inode));
+        }
+
+        let dir_inodes: Vec<Inode> = self
+            .path_to_inode
+            .iter()
+            .filter(|(path, _)| !path.is_empty() && !kept_dirs.contains(path.as_str()))
+            .map(|(_, &inode)| inode)
+            .filter(|inode| self.inode_to_item.get(inode).is_some_and(|i| i.is_dir))
+            .collect();
+        for inode in dir_inodes {
+            self.inode_to_item.remove(&inode);
+        }
+
+        self.path_to_inode.clear();
+        self.path_to_inode.insert("".into(), root_inode);
+        self.path_to_inode.extend(kept);
+        for (name, inode) in renamed {
+            if let Some(item) = self.inode_to_item.get_mut(&inode) {
+                item.name = name.clone();
+            }
+            self.path_to_inode.insert(name, inode);
+        }
+    }
+}
+
+fn base_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Return `name` or, if taken, the first free `stem-N.ext` variant of it.
+fn unique_name(taken: &HashSet<String>, name: String) -> String {
+    if !taken.contains(&name) {
+        return name;
+    }
+
+    // split at the first dot so `logs.tar.gz` becomes `logs-1.tar.gz`,
+    // but leave dotfiles like `.bashrc` whole
+    let dot = name.char_indices().skip(1).find(|&(_, c)| c == '.');
+    let (stem, extension) = match dot {
+        Some((index, _)) => name.split_at(index),
+        None => (name.as_str(), ""),
+    };
+    (1..)
+        .map(|counter| format!("{stem}-{counter}{extension}"))
+        .find(|candidate| !taken.contains(candidate))
+        .expect("some counter is always free")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::unique_name;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_unique_name() {
+        let mut taken = HashSet::new();
+        assert_eq!(unique_name(&taken, "c.txt".into()), "c.txt");
+
+        taken.insert("c.txt".to_string());
+        assert_eq!(unique_name(&taken, "c.txt".into()), "c-1.txt");
+
+        taken.insert("c-1.txt".to_string());
+        assert_eq!(unique_name(&taken, "c.txt".into()), "c-2.txt");
+
+        taken.insert("logs.tar.gz".to_string());
+        assert_eq!(unique_name(&taken, "logs.tar.gz".into()), "logs-1.tar.gz");
+
+        taken.insert(".bashrc".to_string());
+        assert_eq!(unique_name(&taken, ".bashrc".into()), ".bashrc-1");
+    }
+}
@@ -0,0 +1,138 @@
+use super::{has_extension, ArchiveFormat, Entry, Tar};
+use crate::tartarfs::compression::{gzip_members, Decoding};
+use crate::tartarfs::source::{ByteSource, MemorySource, SourceReader};
+use flate2::bufread::GzDecoder;
+use std::io::{self, BufRead, BufReader, ErrorKind};
+use std::sync::Arc;
+use tracing::debug;
+
+/// Where the signature and the control files go, e.g. `.apk/.PKGINFO`.
+pub const METADATA_DIR: &str = ".apk";
+
+/// An Alpine package: gzip members for the signature, the control files
+/// and the data, each a tar fragment without an end-of-archive marker.
+///
+/// The data is shown at the root and the rest under [`METADATA_DIR`], with
+/// names as stored, like `.SIGN.RSA.builder.rsa.pub`, `.PKGINFO` and
+/// `.post-install`. Reading a file only decodes the data member.
+pub struct Apk;
+
+impl ArchiveFormat for Apk {
+    fn name(&self) -> &str {
+        "apk"
+    }
+
+    fn sniff(&self, source: &Arc<dyn ByteSource>) -> bool {
+        let mut magic = [0; 2];
+        if !matches!(source.read_full_at(0, &mut magic), Ok(2)) || magic != [0x1f, 0x8b] {
+            return false;
+        }
+        let reader = BufReader::new(SourceReader::new(Arc::clone(source)));
+        let mut decoder = BufReader::new(GzDecoder::new(reader));
+        matches!(first_name(&mut decoder), Ok(Some(name)) if is_metadata(&name))
+    }
+
+    fn matches_name(&self, name: &str) -> bool {
+        has_extension(name, &[".apk"])
+    }
+
+    fn entries(&self, source: &Arc<dyn ByteSource>, decoding: &Decoding) -> io::Result<Vec<Entry>> {
+        // finding where the members end takes decoding them once up front
+        let members = gzip_members(source, first_name)?;
+        let mut entries = vec![Entry::directory(METADATA_DIR.to_string())];
+        for member in members {
+            let Some(name) = member.read? else {
+                debug!("Skipping empty apk member at {}", member.offset);
+                continue;
+            };
+            let metadata = is_metadata(&name);
+            for mut entry in Tar.entries(&member.content, decoding)? {
+                if metadata {
+                    entry.path = format!("{METADATA_DIR}/{}", entry.path);
+                }
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+}
+
+/// The signature member starts with `.SIGN.*` files, the control one with `.PKGINFO`.
+fn is_metadata(name: &str) -> bool {
+    name.starts_with(".SIGN.") || name == ".PKGINFO"
+}
+
+/// The name of the first file in a tar fragment; `None` if it doesn't
+/// start with a tar header.
+fn first_name(reader: &mut dyn BufRead) -> io::Result<Option<String>> {
+    let mut block = vec![0; 512];
+    match reader.read_exact(&mut block) {
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        result => result?,
+    }
+    let header = tar::Header::from_byte_slice(&block);
+    let path = header.path()?.to_string_lossy().into_owned();
+    let block: Arc<dyn ByteSource> = Arc::new(MemorySource::new(block));
+    Ok(Tar.sniff(&block).then_some(path))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+
+    /// A tar fragment, cut off before its end-of-archive marker.
+    fn fragment(files: &[(&str, &str)]) -> io::Result<Vec<u8>> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for &(path, text) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(text.len() as u64);
+            header.set_mode(0o644);
+            builder.append_data(&mut header, path, text.as_bytes())?;
+        }
+        let mut tar = builder.into_inner()?;
+        tar.truncate(tar.len() - 1024);
+        Ok(tar)
+    }
+
+    /// A signed package with a signature, control and data member.
+    pub(crate) fn apk() -> io::Result<Vec<u8>> {
+        let mut package = Vec::new();
+        for files in [
+            &[(".SIGN.RSA.builder.rsa.pub", "signature")][..],
+            &[
+                (".PKGINFO", "pkgname = hello\n"),
+                (".post-install", "#!/bin/sh\n"),
+            ],
+            &[("usr/bin/hello", "Hello!")],
+        ] {
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(&fragment(files)?)?;
+            package.extend(encoder.finish()?);
+        }
+        Ok(package)
+    }
+
+    #[test]
+    fn test_entries() -> io::Result<()> {
+        let source: Arc<dyn ByteSource> = Arc::new(MemorySource::new(apk()?));
+        assert!(Apk.sniff(&source));
+        let entries = Apk.entries(&source, &Decoding::default())?;
+        let paths: Vec<_> = entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                ".apk",
+                ".apk/.SIGN.RSA.builder.rsa.pub",
+                ".apk/.PKGINFO",
+                ".apk/.post-install",
+                "usr/bin/hello"
+            ]
+        );
+
+        let plain: Arc<dyn ByteSource> = Arc::new(MemorySource::new(fragment(&[("a", "b")])?));
+        assert!(!Apk.sniff(&plain));
+        Ok(())
+    }
+}
@@ -0,0 +1,259 @@
+use super::{has_extension, ArchiveFormat, Entry};
+use crate::tartarfs::compression::Decoding;
+use crate::tartarfs::source::{ByteSource, SliceSource};
+use std::io::{self, Error, ErrorKind};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+pub(crate) const MAGIC: &[u8; 8] = b"!<arch>\n";
+const HEADER_SIZE: usize = 60;
+const BSD_LONG_NAME: &str = "#1/";
+
+/// A Unix `ar` archive, like a static library, with GNU and BSD long names.
+///
+/// The symbol tables of static libraries are left out; they are for the
+/// linker, not for browsing.
+pub struct Ar;
+
+impl ArchiveFormat for Ar {
+    fn name(&self) -> &str {
+        "ar"
+    }
+
+    fn sniff(&self, source: &Arc<dyn ByteSource>) -> bool {
+        is_ar(source.as_ref())
+    }
+
+    fn matches_name(&self, name: &str) -> bool {
+        has_extension(name, &[".a", ".ar"])
+    }
+
+    fn entries(
+        &self,
+        source: &Arc<dyn ByteSource>,
+        _decoding: &Decoding,
+    ) -> io::Result<Vec<Entry>> {
+        let members = read_members(source)?;
+        let entries = members.into_iter().map(|member| Entry {
+            path: member.name,
+            is_dir: false,
+            mode: member.mode & 0o7777,
+            uid: member.uid,
+            gid: member.gid,
+            mtime: SystemTime::UNIX_EPOCH + Duration::from_secs(member.mtime),
+            content: Some(member.content),
+            digest: None,
+            link_target: None,
+            special: None,
+            xattrs: Vec::new(),
+        });
+        Ok(entries.collect())
+    }
+}
+
+/// One member of a Unix `ar` archive.
+pub(crate) struct Member {
+    pub name: String,
+    pub mtime: u64,
+    pub uid: u32,
+    pub gid: u32,
+    pub mode: u32,
+    pub content: Arc<dyn ByteSource>,
+}
+
+pub(crate) fn is_ar(source: &dyn ByteSource) -> bool {
+    let mut magic = [0; 8];
+    matches!(source.read_full_at(0, &mut magic), Ok(8)) && magic == *MAGIC
+}
+
+/// List the members of an `ar` archive in order, resolving long names.
+pub(crate) fn read_members(source: &Arc<dyn ByteSource>) -> io::Result<Vec<Member>> {
+    if !is_ar(source.as_ref()) {
+        return Err(Error::new(ErrorKind::InvalidData, "not an ar archive"));
+    }
+    let invalid = |what: &str| Error::new(ErrorKind::InvalidData, format!("bad ar {what}"));
+
+    let mut members = Vec::new();
+    // GNU keeps long names in a `//` member, each ending in `/\n`
+    let mut long_names = Vec::new();
+    let mut offset = MAGIC.len() as u64;
+    while offset < source.size() {
+        let mut header = [0; HEADER_SIZE];
+        if source.read_full_at(offset, &mut header)? < HEADER_SIZE || &header[58..] != b"`\n" {
+            return Err(invalid("member header"));
+        }
+        let field = |range: std::ops::Range<usize>| {
+            String::from_utf8_lossy(&header[range])
+                .trim_end()
+                .to_string()
+        };
+        let number = |range: std::ops::Range<usize>, radix: u32| {
+            u64::from_str_radix(&field(range), radix).unwrap_or(0)
+        };
+
+        let mut data_offset = offset + HEADER_SIZE as u64;
+        let mut size: u64 = field(48..58).parse().map_err(|_| invalid("member size"))?;
+        if size > source.size().saturating_sub(data_offset) {
+            return Err(invalid("member size"));
+        }
+        // members start on even offsets
+        offset = (data_offset + size).next_multiple_of(2);
+
+        let raw_name = field(0..16);
+        let name = if let Some(length) = raw_name.strip_prefix(BSD_LONG_NAME) {
+            // BSD puts long names right before the data, counted in its size
+            let length: u64 = length.parse().map_err(|_| invalid("long name"))?;
+            let mut name = vec![0; length.min(size) as usize];
+            source.read_full_at(data_offset, &mut name)?;
+            data_offset += name.len() as u64;
+            size -= name.len() as u64;
+            let end = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+            String::from_utf8_lossy(&name[..end]).into_owned()
+        } else if raw_name == "//" {
+            long_names = vec![0; size as usize];
+            source.read_full_at(data_offset, &mut long_names)?;
+            continue;
+        } else if raw_name == "/" || raw_name == "/SYM64/" {
+            continue; // GNU symbol table
+        } else if let Some(index) = raw_name.strip_prefix('/') {
+            let index: usize = index.parse().map_err(|_| invalid("long name"))?;
+            let name = long_names
+                .get(index..)
+                .ok_or_else(|| invalid("long name"))?;
+            let end = name.iter().position(|&b| b == b'\n').unwrap_or(name.len());
+            let name = &name[..end];
+            String::from_utf8_lossy(name.strip_suffix(b"/").unwrap_or(name)).into_owned()
+        } else {
+            // GNU ends names with a slash so that they may contain spaces
+            raw_name.strip_suffix('/').unwrap_or(&raw_name).to_string()
+        };
+        if name.starts_with("__.SYMDEF") {
+            continue; // BSD symbol table
+        }
+
+        members.push(Member {
+            name,
+            mtime: number(16..28, 10),
+            uid: number(28..34, 10) as u32,
+            gid: number(34..40, 10) as u32,
+            mode: number(40..48, 8) as u32,
+            content: Arc::new(SliceSource::new(Arc::clone(source), data_offset, size)),
+        });
+    }
+    Ok(members)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::tartarfs::source::MemorySource;
+
+    fn header(name: &str, size: usize) -> String {
+        format!(
+            "{:<16}{:<12}{:<6}{:<6}{:<8o}{:<10}`\n",
+            name, 1_700_000_000, 1000, 100, 0o100640, size
+        )
+    }
+
+    fn pad(archive: &mut Vec<u8>) {
+        if archive.len() % 2 == 1 {
+            archive.push(b'\n');
+        }
+    }
+
+    /// Build a GNU style `ar` archive of `(name, data)` members.
+    pub(crate) fn ar(members: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut archive = MAGIC.to_vec();
+        for (name, data) in members {
+            archive.extend(header(&format!("{name}/"), data.len()).bytes());
+            archive.extend_from_slice(data);
+            pad(&mut archive);
+        }
+        archive
+    }
+
+    fn read(entry: &Entry) -> io::Result<Vec<u8>> {
+        let content = entry.content.as_ref().unwrap();
+        let mut buf = vec![0; content.size() as usize];
+        content.read_full_at(0, &mut buf)?;
+        Ok(buf)
+    }
+
+    #[test]
+    fn test_gnu_long_names() -> io::Result<()> {
+        let table = b"a_rather_long_object_name.o/\nanother_long_object_name.o/\n";
+        let mut archive = MAGIC.to_vec();
+        for (name, data) in [
+            ("/", &b"\0\0\0\0"[..]),
+            ("//", table),
+            ("/29", b"second"),
+            ("/0", b"first"),
+            ("short.o/", b"odd"),
+        ] {
+            archive.extend(header(name, data.len()).bytes());
+            archive.extend_from_slice(data);
+            pad(&mut archive);
+        }
+
+        let source: Arc<dyn ByteSource> = Arc::new(MemorySource::new(archive));
+        assert!(Ar.sniff(&source));
+        let entries = Ar.entries(&source, &Decoding::default())?;
+        let paths: Vec<_> = entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "another_long_object_name.o",
+                "a_rather_long_object_name.o",
+                "short.o"
+            ]
+        );
+        assert_eq!(read(&entries[0])?, b"second");
+        assert_eq!(read(&entries[1])?, b"first");
+        assert_eq!(read(&entries[2])?, b"odd");
+        assert_eq!(entries[2].mode, 0o640);
+        assert_eq!((entries[2].uid, entries[2].gid), (1000, 100));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bsd_long_names() -> io::Result<()> {
+        let mut archive = MAGIC.to_vec();
+        for (name, data) in [
+            ("__.SYMDEF SORTED", &b"symbols"[..]),
+            ("a_rather_long_object_name.o", b"data"),
+            ("x.o", b"x"),
+        ] {
+            let name = format!("{name}\0\0\0");
+            let size = name.len() + data.len();
+            archive.extend(header(&format!("#1/{}", name.len()), size).bytes());
+            archive.extend(name.bytes());
+            archive.extend_from_slice(data);
+            pad(&mut archive);
+        }
+
+        let source: Arc<dyn ByteSource> = Arc::new(MemorySource::new(archive));
+        let entries = Ar.entries(&source, &Decoding::default())?;
+        let paths: Vec<_> = entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, ["a_rather_long_object_name.o", "x.o"]);
+        assert_eq!(read(&entries[0])?, b"data");
+        assert_eq!(read(&entries[1])?, b"x");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncated() {
+        let mut archive = ar(&[("first.o", b"first"), ("second.o", b"second")]);
+        archive.truncate(archive.len() - 4);
+        let source: Arc<dyn ByteSource> = Arc::new(MemorySource::new(archive));
+        assert!(Ar.entries(&source, &Decoding::default()).is_err());
+
+        // a long name table claiming far more than there is isn't read
+        let mut archive = MAGIC.to_vec();
+        archive.extend(header("//", 9_999_999_999).bytes());
+        archive.extend(b"name.o/\n");
+        let source: Arc<dyn ByteSource> = Arc::new(MemorySource::new(archive));
+        assert!(Ar.entries(&source, &Decoding::default()).is_err());
+    }
+}
@@ -0,0 +1,134 @@
+use super::{has_extension, ArchiveFormat, CompressedTar, Entry, Zip};
+use crate::tartarfs::compression::Decoding;
+use crate::tartarfs::source::ByteSource;
+use std::io::{self, Error, ErrorKind};
+use std::sync::Arc;
+use tracing::debug;
+
+/// A conda package in the `.conda` format: an uncompressed zip of
+/// `metadata.json`, `info-*.tar.zst` and `pkg-*.tar.zst`, shown as `info/`
+/// with the package metadata next to the files the package installs.
+pub struct Conda;
+
+impl ArchiveFormat for Conda {
+    fn name(&self) -> &str {
+        "conda"
+    }
+
+    /// Check for a zip whose first member is one conda packages start with.
+    fn sniff(&self, source: &Arc<dyn ByteSource>) -> bool {
+        let mut header = [0; 30 + 64];
+        let Ok(n) = source.read_full_at(0, &mut header) else {
+            return false;
+        };
+        if n < 30 || !header.starts_with(b"PK\x03\x04") {
+            return false;
+        }
+        let name_length = u16::from_le_bytes([header[26], header[27]]) as usize;
+        let Some(name) = header[..n].get(30..30 + name_length) else {
+            return false;
+        };
+        let name = String::from_utf8_lossy(name);
+        name == "metadata.json" || tarball_kind(&name).is_some()
+    }
+
+    fn matches_name(&self, name: &str) -> bool {
+        has_extension(name, &[".conda"])
+    }
+
+    fn entries(&self, source: &Arc<dyn ByteSource>, decoding: &Decoding) -> io::Result<Vec<Entry>> {
+        let mut entries = Vec::new();
+        for member in Zip.entries(source, decoding)? {
+            let (Some(kind), Some(content)) = (tarball_kind(&member.path), &member.content) else {
+                debug!("Skipping conda member: {}", member.path);
+                continue;
+            };
+            if !CompressedTar.sniff(content) {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("unsupported conda member {}", member.path),
+                ));
+            }
+            for mut entry in CompressedTar.entries(content, decoding)? {
+                // the info tarball has its files under `info/` already
+                let path = entry.path.trim_start_matches("./");
+                if kind == Kind::Info && !path.starts_with("info/") && path != "info" {
+                    entry.path = format!("info/{path}");
+                }
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Kind {
+    Info,
+    Pkg,
+}
+
+/// Tell `info-numpy-2.0-py312.tar.zst` from `pkg-numpy-2.0-py312.tar.zst`.
+fn tarball_kind(name: &str) -> Option<Kind> {
+    if !name.ends_with(".tar.zst") || name.contains('/') {
+        return None;
+    }
+    if name.starts_with("info-") {
+        Some(Kind::Info)
+    } else if name.starts_with("pkg-") {
+        Some(Kind::Pkg)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::tartarfs::source::MemorySource;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+    use zip::{CompressionMethod, ZipWriter};
+
+    fn tar_zst(files: &[(&str, &str)]) -> io::Result<Vec<u8>> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for &(path, text) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(text.len() as u64);
+            header.set_mode(0o644);
+            builder.append_data(&mut header, path, text.as_bytes())?;
+        }
+        zstd::encode_all(&builder.into_inner()?[..], 3)
+    }
+
+    /// A package with `info/index.json` and `lib/hello.py`.
+    pub(crate) fn conda() -> io::Result<Vec<u8>> {
+        let mut writer = ZipWriter::new(io::Cursor::new(Vec::new()));
+        let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        writer.start_file("metadata.json", stored)?;
+        writer.write_all(br#"{"conda_pkg_format_version": 2}"#)?;
+        writer.start_file("pkg-hello-1.0-0.tar.zst", stored)?;
+        writer.write_all(&tar_zst(&[("lib/hello.py", "print('hello')\n")])?)?;
+        writer.start_file("info-hello-1.0-0.tar.zst", stored)?;
+        writer.write_all(&tar_zst(&[("info/index.json", r#"{"name": "hello"}"#)])?)?;
+        Ok(writer.finish()?.into_inner())
+    }
+
+    #[test]
+    fn test_entries() -> io::Result<()> {
+        let source: Arc<dyn ByteSource> = Arc::new(MemorySource::new(conda()?));
+        assert!(Conda.sniff(&source));
+        let entries = Conda.entries(&source, &Decoding::default())?;
+        let paths: Vec<_> = entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, ["lib/hello.py", "info/index.json"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tarball_kind() {
+        assert_eq!(tarball_kind("info-a-1-0.tar.zst"), Some(Kind::Info));
+        assert_eq!(tarball_kind("pkg-a-1-0.tar.zst"), Some(Kind::Pkg));
+        assert_eq!(tarball_kind("pkg-a-1-0.tar.bz2"), None);
+        assert_eq!(tarball_kind("metadata.json"), None);
+    }
+}
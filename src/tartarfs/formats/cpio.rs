@@ -0,0 +1,428 @@
+use super::{has_extension, ArchiveFormat, Entry, Special};
+use crate::tartarfs::compression::{Codec, Decoding, DecompressedSource};
+use crate::tartarfs::source::{ByteSource, SliceSource};
+use std::collections::HashMap;
+use std::io::{self, Error, ErrorKind};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tracing::{debug, warn};
+
+const NEWC_MAGIC: &[u8; 6] = b"070701";
+const CRC_MAGIC: &[u8; 6] = b"070702";
+const ODC_MAGIC: &[u8; 6] = b"070707";
+const NEWC_HEADER_SIZE: usize = 110;
+const ODC_HEADER_SIZE: usize = 76;
+const TRAILER: &[u8] = b"TRAILER!!!";
+const MAX_NAME_SIZE: u64 = 64 * 1024;
+
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+/// A cpio archive in the newc, crc or odc format.
+///
+/// Linux initramfs images are often several of these back to back, e.g.
+/// an uncompressed one with CPU microcode followed by a compressed one with
+/// the actual root filesystem, so every segment is read and later members
+/// override earlier ones of the same path.
+pub struct Cpio;
+
+impl ArchiveFormat for Cpio {
+    fn name(&self) -> &str {
+        "cpio"
+    }
+
+    fn sniff(&self, source: &Arc<dyn ByteSource>) -> bool {
+        if is_cpio(source.as_ref(), 0) {
+            return true;
+        }
+        // a compressed image, e.g. `initrd.img` that is a gzipped cpio
+        Codec::detect(source.as_ref()).is_some_and(|codec| {
+            let decompressed = DecompressedSource::new(Arc::clone(source), codec);
+            is_cpio(&decompressed, 0)
+        })
+    }
+
+    fn matches_name(&self, name: &str) -> bool {
+        const EXTENSIONS: [&str; 5] = [".cpio", ".cpio.gz", ".cpio.zst", ".cpio.xz", ".cpio.bz2"];
+        has_extension(name, &EXTENSIONS)
+    }
+
+    fn entries(
+        &self,
+        source: &Arc<dyn ByteSource>,
+        _decoding: &Decoding,
+    ) -> io::Result<Vec<Entry>> {
+        let mut entries = Vec::new();
+        match read_segments(source, &mut entries) {
+            // a bad segment at the end shouldn't hide the ones before it
+            Err(e) if !entries.is_empty() => warn!("Stopped reading cpio segments: {}", e),
+            result => result?,
+        }
+        Ok(entries)
+    }
+}
+
+fn is_cpio(source: &dyn ByteSource, offset: u64) -> bool {
+    let mut magic = [0; 6];
+    matches!(source.read_full_at(offset, &mut magic), Ok(6))
+        && [NEWC_MAGIC, CRC_MAGIC, ODC_MAGIC].contains(&&magic)
+}
+
+/// Read every cpio archive in `source`, whether plain or compressed,
+/// skipping the zero padding between them.
+///
+/// This stays clear of `size()` on decompressed data, which takes decoding
+/// it all and fails on padding after the compressed stream.
+fn read_segments(source: &Arc<dyn ByteSource>, entries: &mut Vec<Entry>) -> io::Result<()> {
+    let mut offset = 0;
+    while let Some(start) = skip_zeros(source.as_ref(), offset)? {
+        if is_cpio(source.as_ref(), start) {
+            offset = read_archive(source, start, entries)?;
+            continue;
+        }
+
+        let rest: Arc<dyn ByteSource> = Arc::new(SliceSource::new(
+            Arc::clone(source),
+            start,
+            source.size().saturating_sub(start),
+        ));
+        let Some(codec) = Codec::detect(rest.as_ref()) else {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("no cpio archive at offset {start}"),
+            ));
+        };
+        // a compressed segment runs to the end, but may hold several archives itself
+        debug!(
+            "Found {:?} compressed cpio segment at offset {}",
+            codec, start
+        );
+        let decompressed: Arc<dyn ByteSource> = Arc::new(DecompressedSource::new(rest, codec));
+        return read_segments(&decompressed, entries);
+    }
+    Ok(())
+}
+
+/// Find the first non-zero byte at or after `offset`, if there is one.
+fn skip_zeros(source: &dyn ByteSource, mut offset: u64) -> io::Result<Option<u64>> {
+    let mut buf = [0; 4096];
+    loop {
+        let n = source.read_full_at(offset, &mut buf)?;
+        if let Some(position) = buf[..n].iter().position(|&b| b != 0) {
+            return Ok(Some(offset + position as u64));
+        }
+        if n < buf.len() {
+            return Ok(None);
+        }
+        offset += n as u64;
+    }
+}
+
+struct Header {
+    ino: (u64, u64),
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    nlink: u64,
+    mtime: u64,
+    /// The major and minor numbers of device nodes.
+    rdev: (u32, u32),
+    file_size: u64,
+    name_size: u64,
+    header_size: u64,
+    /// newc and crc pad the name and data to 4 bytes, odc doesn't pad at all.
+    alignment: u64,
+}
+
+/// Read one archive starting at `start` up to its trailer, returning the offset after it.
+fn read_archive(
+    source: &Arc<dyn ByteSource>,
+    start: u64,
+    entries: &mut Vec<Entry>,
+) -> io::Result<u64> {
+    // hard linked files in newc only carry their data with the last link
+    let mut links: HashMap<(u64, u64), Vec<usize>> = HashMap::new();
+
+    let mut offset = start;
+    loop {
+        let header = read_header(source.as_ref(), offset)?;
+        let align = |position: u64| start + (position - start).next_multiple_of(header.alignment);
+
+        let name_offset = offset + header.header_size;
+        let mut name = vec![0; header.name_size as usize];
+        if source.read_full_at(name_offset, &mut name)? < name.len() {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "truncated cpio name"));
+        }
+        if name.last() == Some(&0) {
+            name.pop();
+        }
+        let data_offset = align(name_offset + header.name_size);
+        let next = align(data_offset + header.file_size);
+        if name == TRAILER {
+            return Ok(next);
+        }
+        offset = next;
+
+        let path = String::from_utf8_lossy(&name).into_owned();
+        let data = SliceSource::new(Arc::clone(source), data_offset, header.file_size);
+        let (major, minor) = header.rdev;
+        let special = Special::from_mode(header.mode, major, minor);
+        let (is_dir, content, link_target) = match header.mode & S_IFMT {
+            S_IFDIR => (true, None, None),
+            S_IFREG => (false, Some(Arc::new(data) as Arc<dyn ByteSource>), None),
+            S_IFLNK => {
+                // the data is the link target
+                let mut target = vec![0; header.file_size.min(MAX_NAME_SIZE) as usize];
+                data.read_full_at(0, &mut target)?;
+                let target = String::from_utf8_lossy(&target).into_owned();
+                (false, None, Some(target))
+            }
+            _ if special.is_some() => (false, None, None),
+            _ => {
+                debug!("Skipping cpio member of unknown type: {}", path);
+                continue;
+            }
+        };
+
+        if content.is_some() && header.nlink > 1 {
+            let linked = links.entry(header.ino).or_default();
+            if header.file_size > 0 {
+                for &index in linked.iter() {
+                    entries[index].content = content.clone();
+                }
+                linked.clear();
+            } else {
+                linked.push(entries.len());
+            }
+        }
+
+        entries.push(Entry {
+            path,
+            is_dir,
+            mode: header.mode & 0o7777,
+            uid: header.uid,
+            gid: header.gid,
+            mtime: SystemTime::UNIX_EPOCH
+                .checked_add(Duration::from_secs(header.mtime))
+                .unwrap_or_else(SystemTime::now),
+            content,
+            digest: None,
+            link_target,
+            special,
+            xattrs: Vec::new(),
+        });
+    }
+}
+
+fn read_header(source: &dyn ByteSource, offset: u64) -> io::Result<Header> {
+    let invalid = |what: &str| Error::new(ErrorKind::InvalidData, format!("bad cpio {what}"));
+
+    let mut buf = [0; NEWC_HEADER_SIZE];
+    let n = source.read_full_at(offset, &mut buf)?;
+    let magic = buf.first_chunk::<6>().ok_or_else(|| invalid("header"))?;
+
+    let header = if magic == NEWC_MAGIC || magic == CRC_MAGIC {
+        if n < NEWC_HEADER_SIZE {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "truncated cpio header",
+            ));
+        }
+        // thirteen 8 digit hex fields after the magic
+        let field = |i: usize| {
+            let digits = std::str::from_utf8(&buf[6 + i * 8..14 + i * 8]).ok();
+            digits
+                .and_then(|digits| u64::from_str_radix(digits, 16).ok())
+                .ok_or_else(|| invalid("header field"))
+        };
+        Header {
+            ino: ((field(7)? << 32) | field(8)?, field(0)?),
+            mode: field(1)? as u32,
+            uid: field(2)? as u32,
+            gid: field(3)? as u32,
+            nlink: field(4)?,
+            mtime: field(5)?,
+            rdev: (field(9)? as u32, field(10)? as u32),
+            file_size: field(6)?,
+            name_size: field(11)?,
+            header_size: NEWC_HEADER_SIZE as u64,
+            alignment: 4,
+        }
+    } else if magic == ODC_MAGIC {
+        if n < ODC_HEADER_SIZE {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "truncated cpio header",
+            ));
+        }
+        let field = |start: usize, len: usize| {
+            let digits = std::str::from_utf8(&buf[start..start + len]).ok();
+            digits
+                .and_then(|digits| u64::from_str_radix(digits, 8).ok())
+                .ok_or_else(|| invalid("header field"))
+        };
+        Header {
+            ino: (field(6, 6)?, field(12, 6)?),
+            mode: field(18, 6)? as u32,
+            uid: field(24, 6)? as u32,
+            gid: field(30, 6)? as u32,
+            nlink: field(36, 6)?,
+            mtime: field(48, 11)?,
+            // odc packs the old 16 bit device number into one field
+            rdev: {
+                let rdev = field(42, 6)? as u32;
+                (rdev >> 8, rdev & 0xff)
+            },
+            name_size: field(59, 6)?,
+            file_size: field(65, 11)?,
+            header_size: ODC_HEADER_SIZE as u64,
+            alignment: 1,
+        }
+    } else {
+        return Err(invalid("magic"));
+    };
+
+    if header.name_size > MAX_NAME_SIZE {
+        return Err(invalid("name size"));
+    }
+    Ok(header)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::tartarfs::source::MemorySource;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    /// Build a newc archive of `(path, mode, ino, nlink, data)` members.
+    pub(crate) fn newc(members: &[(&str, u32, u32, u32, &[u8])]) -> Vec<u8> {
+        let mut archive = Vec::new();
+        let trailer = ("TRAILER!!!", 0, 0, 1, &b""[..]);
+        for &(path, mode, ino, nlink, data) in members.iter().chain([&trailer]) {
+            let fields = [ino, mode, 0, 0, nlink, 1_700_000_000, data.len() as u32];
+            archive.extend_from_slice(NEWC_MAGIC);
+            for field in fields
+                .into_iter()
+                .chain([0, 0, 0, 0, path.len() as u32 + 1, 0])
+            {
+                archive.extend(format!("{field:08x}").bytes());
+            }
+            archive.extend(path.bytes());
+            archive.push(0);
+            archive.resize(archive.len().next_multiple_of(4), 0);
+            archive.extend_from_slice(data);
+            archive.resize(archive.len().next_multiple_of(4), 0);
+        }
+        archive
+    }
+
+    fn odc(members: &[(&str, u32, &[u8])]) -> Vec<u8> {
+        let mut archive = Vec::new();
+        let trailer = ("TRAILER!!!", 0, &b""[..]);
+        for (ino, &(path, mode, data)) in members.iter().chain([&trailer]).enumerate() {
+            archive.extend(
+                format!(
+                    "070707{:06o}{:06o}{:06o}{:06o}{:06o}{:06o}{:06o}{:011o}{:06o}{:011o}",
+                    0,
+                    ino,
+                    mode,
+                    0,
+                    0,
+                    1,
+                    0,
+                    1_700_000_000,
+                    path.len() + 1,
+                    data.len()
+                )
+                .bytes(),
+            );
+            archive.extend(path.bytes());
+            archive.push(0);
+            archive.extend_from_slice(data);
+        }
+        archive
+    }
+
+    fn read(entry: &Entry) -> io::Result<Vec<u8>> {
+        let content = entry.content.as_ref().unwrap();
+        let mut buf = vec![0; content.size() as usize];
+        content.read_full_at(0, &mut buf)?;
+        Ok(buf)
+    }
+
+    #[test]
+    fn test_concatenated_segments() -> io::Result<()> {
+        let mut image = newc(&[
+            (".", 0o40755, 1, 2, b""),
+            ("kernel/microcode.bin", 0o100644, 2, 1, b"ucode"),
+            ("etc/release", 0o100644, 3, 1, b"old"),
+        ]);
+        image.resize(image.len().next_multiple_of(512), 0);
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(&newc(&[
+            ("bin", 0o40700, 1, 2, b""),
+            ("bin/sh", 0o100755, 2, 2, b""),
+            ("bin/busybox", 0o100755, 2, 2, b"#!busybox"),
+            ("bin/ls", 0o120777, 3, 1, b"busybox"),
+            ("etc/release", 0o100600, 4, 1, b"new"),
+        ]))?;
+        image.extend(encoder.finish()?);
+        image.extend([0; 100]); // trailing padding after the compressed stream
+
+        let source: Arc<dyn ByteSource> = Arc::new(MemorySource::new(image));
+        assert!(Cpio.sniff(&source));
+        let entries = Cpio.entries(&source, &Decoding::default())?;
+        let paths: Vec<_> = entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                ".",
+                "kernel/microcode.bin",
+                "etc/release",
+                "bin",
+                "bin/sh",
+                "bin/busybox",
+                "bin/ls",
+                "etc/release"
+            ]
+        );
+
+        assert!(entries[3].is_dir);
+        assert_eq!(entries[3].mode, 0o700);
+        assert_eq!(read(&entries[1])?, b"ucode");
+        assert_eq!(read(&entries[4])?, b"#!busybox"); // hard link
+        assert_eq!(entries[6].link_target.as_deref(), Some("busybox"));
+        assert_eq!(read(&entries[7])?, b"new");
+        assert_eq!(entries[7].mode, 0o600);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_odc() -> io::Result<()> {
+        let archive = odc(&[("docs", 0o40755, b""), ("docs/a.txt", 0o100640, b"A")]);
+        let source: Arc<dyn ByteSource> = Arc::new(MemorySource::new(archive));
+        assert!(Cpio.sniff(&source));
+
+        let entries = Cpio.entries(&source, &Decoding::default())?;
+        assert_eq!(entries.len(), 2);
+        assert!(entries[0].is_dir);
+        assert_eq!(entries[1].path, "docs/a.txt");
+        assert_eq!(entries[1].mode, 0o640);
+        assert_eq!(read(&entries[1])?, b"A");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_not_cpio() {
+        let source: Arc<dyn ByteSource> = Arc::new(MemorySource::new(b"hello world".as_slice()));
+        assert!(!Cpio.sniff(&source));
+        assert!(Cpio.entries(&source, &Decoding::default()).is_err());
+    }
+}
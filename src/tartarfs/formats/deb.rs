@@ -0,0 +1,97 @@
+use super::ar::{self, Member};
+use super::{has_extension, ArchiveFormat, Entry, Tar};
+use crate::tartarfs::compression::{decompress, Codec, Decoding};
+use crate::tartarfs::source::ByteSource;
+use std::io::{self, Error, ErrorKind};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tracing::debug;
+
+/// A Debian package: an `ar` archive of `debian-binary`, `control.tar.*`
+/// and `data.tar.*`, shown as `control/` with the maintainer scripts and
+/// `data/` with the files the package installs.
+pub struct Deb;
+
+impl ArchiveFormat for Deb {
+    fn name(&self) -> &str {
+        "deb"
+    }
+
+    fn sniff(&self, source: &Arc<dyn ByteSource>) -> bool {
+        let mut header = [0; 24];
+        matches!(source.read_full_at(0, &mut header), Ok(24))
+            && header.starts_with(ar::MAGIC)
+            && header[8..].starts_with(b"debian-binary")
+    }
+
+    fn matches_name(&self, name: &str) -> bool {
+        has_extension(name, &[".deb", ".udeb", ".ddeb"])
+    }
+
+    fn entries(&self, source: &Arc<dyn ByteSource>, decoding: &Decoding) -> io::Result<Vec<Entry>> {
+        let mut entries = Vec::new();
+        for member in ar::read_members(source)? {
+            let Some(dir) = ["control", "data"]
+                .into_iter()
+                .find(|dir| is_tarball(&member.name, dir))
+            else {
+                debug!("Skipping deb member: {}", member.name);
+                continue;
+            };
+
+            let mut root = Entry::directory(dir.to_string());
+            root.mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(member.mtime);
+            entries.push(root);
+            for mut entry in read_tarball(&member, decoding)? {
+                entry.path = format!("{dir}/{}", entry.path);
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+}
+
+/// Match e.g. `data.tar` or `data.tar.xz`.
+fn is_tarball(name: &str, stem: &str) -> bool {
+    name.strip_prefix(stem)
+        .and_then(|rest| rest.strip_prefix(".tar"))
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+}
+
+fn read_tarball(member: &Member, decoding: &Decoding) -> io::Result<Vec<Entry>> {
+    let tarball: Arc<dyn ByteSource> = match Codec::detect(member.content.as_ref()) {
+        Some(codec) => decompress(Arc::clone(&member.content), codec, &decoding.spool),
+        None => Arc::clone(&member.content),
+    };
+    if !Tar.sniff(&tarball) {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("unsupported deb member {}", member.name),
+        ));
+    }
+    Tar.entries(&tarball, decoding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tartarfs::formats::ar::tests::ar;
+    use crate::tartarfs::source::MemorySource;
+
+    #[test]
+    fn test_is_tarball() {
+        assert!(is_tarball("data.tar", "data"));
+        assert!(is_tarball("data.tar.zst", "data"));
+        assert!(is_tarball("control.tar.gz", "control"));
+        assert!(!is_tarball("data.tarx", "data"));
+        assert!(!is_tarball("control.tar.gz", "data"));
+    }
+
+    #[test]
+    fn test_not_tar() {
+        let deb = ar(&[("debian-binary", b"2.0\n"), ("data.tar", b"nope")]);
+        let source: Arc<dyn ByteSource> = Arc::new(MemorySource::new(deb));
+        assert!(Deb.sniff(&source));
+        assert!(Deb.entries(&source, &Decoding::default()).is_err());
+    }
+}
@@ -0,0 +1,696 @@
+use super::{has_extension, unix_time, ArchiveFormat, Entry, Special};
+use crate::tartarfs::compression::Decoding;
+use crate::tartarfs::source::{ByteSource, ConcatSource, SliceSource};
+use std::collections::HashSet;
+use std::io::{self, Error, ErrorKind};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tracing::debug;
+
+const SECTOR_SIZE: u64 = 2048;
+const FIRST_DESCRIPTOR: u64 = 16;
+const MAX_DESCRIPTORS: u64 = 64;
+const STANDARD_ID: &[u8; 5] = b"CD001";
+
+const FLAG_DIRECTORY: u8 = 0x02;
+const FLAG_MULTI_EXTENT: u8 = 0x80;
+/// Joliet marks its supplementary descriptor with one of these UCS-2 levels.
+const JOLIET_ESCAPES: [&[u8; 3]; 3] = [b"%/@", b"%/C", b"%/E"];
+
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+/// An ISO 9660 CD or DVD image.
+///
+/// Rock Ridge gives POSIX names, modes and symlinks and is used when present;
+/// otherwise Joliet gives long Unicode names, and failing that the plain
+/// `README.TXT;1` style names are shown without their version.
+pub struct Iso9660;
+
+impl ArchiveFormat for Iso9660 {
+    fn name(&self) -> &str {
+        "iso9660"
+    }
+
+    fn sniff(&self, source: &Arc<dyn ByteSource>) -> bool {
+        let mut id = [0; 5];
+        let offset = FIRST_DESCRIPTOR * SECTOR_SIZE + 1;
+        matches!(source.read_full_at(offset, &mut id), Ok(5)) && id == *STANDARD_ID
+    }
+
+    fn matches_name(&self, name: &str) -> bool {
+        has_extension(name, &[".iso"])
+    }
+
+    fn entries(
+        &self,
+        source: &Arc<dyn ByteSource>,
+        _decoding: &Decoding,
+    ) -> io::Result<Vec<Entry>> {
+        let (volume, root) = Volume::open(source)?;
+        let mut entries = Vec::new();
+        let mut visited = HashSet::new();
+        volume.read_directory(&root, "", &mut entries, &mut visited)?;
+        Ok(entries)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Names {
+    Plain,
+    Joliet,
+    /// Rock Ridge, skipping this many bytes at the start of each system use area.
+    RockRidge(usize),
+}
+
+struct Volume {
+    source: Arc<dyn ByteSource>,
+    block_size: u64,
+    names: Names,
+}
+
+/// A directory record, with the bits we use.
+struct Record {
+    extent: u64,
+    size: u64,
+    flags: u8,
+    name: Vec<u8>,
+    mtime: SystemTime,
+    system_use: Vec<u8>,
+}
+
+/// What Rock Ridge says about a record.
+#[derive(Default)]
+struct RockRidge {
+    name: Option<String>,
+    mode: Option<u32>,
+    uid: u32,
+    gid: u32,
+    mtime: Option<SystemTime>,
+    link_target: Option<String>,
+    /// The major and minor numbers of a device node, from `PN`.
+    device: (u32, u32),
+    /// The record is a placeholder for a directory relocated to here, see `CL`.
+    child_link: Option<u64>,
+    /// The record is the relocated directory itself, shown at its `CL` instead.
+    relocated: bool,
+}
+
+impl Volume {
+    /// Read the volume descriptors and pick the directory tree with the best names.
+    fn open(source: &Arc<dyn ByteSource>) -> io::Result<(Self, Record)> {
+        let invalid =
+            |what: &str| Error::new(ErrorKind::InvalidData, format!("bad iso9660 {what}"));
+
+        let (mut primary, mut joliet) = (None, None);
+        for sector in FIRST_DESCRIPTOR..FIRST_DESCRIPTOR + MAX_DESCRIPTORS {
+            let mut descriptor = vec![0; SECTOR_SIZE as usize];
+            source.read_full_at(sector * SECTOR_SIZE, &mut descriptor)?;
+            if &descriptor[1..6] != STANDARD_ID {
+                return Err(invalid("volume descriptor"));
+            }
+            match descriptor[0] {
+                1 => primary = primary.or(Some(descriptor)),
+                2 if JOLIET_ESCAPES.iter().any(|e| descriptor[88..91] == e[..]) => {
+                    joliet = joliet.or(Some(descriptor))
+                }
+                255 => break,
+                _ => {}
+            }
+        }
+        let primary = primary.ok_or_else(|| invalid("primary volume descriptor"))?;
+
+        let root =
+            |descriptor: &[u8]| parse_record(&descriptor[156..190]).ok_or_else(|| invalid("root"));
+        let block_size = u16::from_le_bytes([primary[128], primary[129]]) as u64;
+        let mut volume = Self {
+            source: Arc::clone(source),
+            block_size: if block_size == 0 {
+                SECTOR_SIZE
+            } else {
+                block_size
+            },
+            names: Names::Plain,
+        };
+
+        // Rock Ridge announces itself with an `SP` entry in the root's `.` record
+        let primary_root = root(&primary)?;
+        let records = volume.read_records(&primary_root)?;
+        if let Some(dot) = records.first()
+            && dot.system_use.starts_with(b"SP")
+            && dot.system_use.get(4..6) == Some(&[0xbe, 0xef])
+        {
+            volume.names = Names::RockRidge(dot.system_use[6] as usize);
+            return Ok((volume, primary_root));
+        }
+        if let Some(joliet) = joliet {
+            volume.names = Names::Joliet;
+            return Ok((volume, root(&joliet)?));
+        }
+        Ok((volume, primary_root))
+    }
+
+    /// The records of a directory, including `.` and `..`.
+    fn read_records(&self, directory: &Record) -> io::Result<Vec<Record>> {
+        let mut data = vec![0; directory.size.min(64 * 1024 * 1024) as usize];
+        self.source
+            .read_full_at(directory.extent * self.block_size, &mut data)?;
+
+        let mut records = Vec::new();
+        let mut position = 0;
+        while position < data.len() {
+            let length = data[position] as usize;
+            if length == 0 {
+                // records don't cross sectors, the rest of this one is padding
+                position = (position + 1).next_multiple_of(SECTOR_SIZE as usize);
+                continue;
+            }
+            let Some(record) = data.get(position..position + length).and_then(parse_record) else {
+                break;
+            };
+            records.push(record);
+            position += length;
+        }
+        Ok(records)
+    }
+
+    fn read_directory(
+        &self,
+        directory: &Record,
+        prefix: &str,
+        entries: &mut Vec<Entry>,
+        visited: &mut HashSet<u64>,
+    ) -> io::Result<()> {
+        if !visited.insert(directory.extent) {
+            return Ok(()); // a loop in a broken image
+        }
+
+        let records = self.read_records(directory)?;
+        // a file over 4 GiB is split over records with the multi-extent flag
+        let mut pieces: Vec<Arc<dyn ByteSource>> = Vec::new();
+        for record in records.iter().skip(2) {
+            let rock_ridge = match self.names {
+                Names::RockRidge(skip) => self.rock_ridge(&record.system_use, skip)?,
+                _ => RockRidge::default(),
+            };
+
+            let data = SliceSource::new(
+                Arc::clone(&self.source),
+                record.extent * self.block_size,
+                record.size,
+            );
+            if record.flags & FLAG_MULTI_EXTENT != 0 {
+                pieces.push(Arc::new(data));
+                continue;
+            }
+            if rock_ridge.relocated {
+                continue;
+            }
+
+            let name = match rock_ridge.name.clone() {
+                Some(name) => name,
+                None => self.record_name(&record.name),
+            };
+            if name.is_empty() || name.contains('/') {
+                debug!("Skipping iso9660 record named {:?}", name);
+                pieces.clear();
+                continue;
+            }
+            let path = match prefix {
+                "" => name,
+                prefix => format!("{prefix}/{name}"),
+            };
+
+            let file_type = rock_ridge.mode.map(|mode| mode & S_IFMT);
+            let (major, minor) = rock_ridge.device;
+            let special = rock_ridge
+                .mode
+                .and_then(|mode| Special::from_mode(mode, major, minor));
+            let is_dir = match file_type {
+                Some(S_IFDIR) => true,
+                Some(S_IFREG | S_IFLNK) | None => {
+                    record.flags & FLAG_DIRECTORY != 0 || rock_ridge.child_link.is_some()
+                }
+                Some(_) if special.is_some() => false,
+                Some(_) => {
+                    debug!("Skipping iso9660 file of unknown type: {}", path);
+                    pieces.clear();
+                    continue;
+                }
+            };
+
+            let mut entry = if is_dir {
+                Entry::directory(path.clone())
+            } else if let Some(special) = special {
+                Entry::special(path.clone(), special)
+            } else if let Some(target) = rock_ridge.link_target {
+                Entry::symlink(path.clone(), target)
+            } else {
+                pieces.push(Arc::new(data));
+                let content: Arc<dyn ByteSource> = match pieces.len() {
+                    1 => pieces.remove(0),
+                    _ => Arc::new(ConcatSource::new(std::mem::take(&mut pieces))),
+                };
+                Entry::file(path.clone(), content)
+            };
+            pieces.clear();
+            entry.mtime = rock_ridge.mtime.unwrap_or(record.mtime);
+            if let Some(mode) = rock_ridge.mode {
+                entry.mode = mode & 0o7777;
+                entry.uid = rock_ridge.uid;
+                entry.gid = rock_ridge.gid;
+            }
+            entries.push(entry);
+
+            if !is_dir {
+                continue;
+            }
+            let relocated = match rock_ridge.child_link {
+                Some(extent) => {
+                    // the size is in the `.` record at the new place
+                    let mut dot = [0; 34];
+                    self.source
+                        .read_full_at(extent * self.block_size, &mut dot)?;
+                    let Some(dot) = parse_record(&dot) else {
+                        debug!("Skipping broken relocated directory: {}", path);
+                        continue;
+                    };
+                    Some(dot)
+                }
+                None => None,
+            };
+            let directory = relocated.as_ref().unwrap_or(record);
+            self.read_directory(directory, &path, entries, visited)?;
+        }
+        Ok(())
+    }
+
+    fn record_name(&self, raw: &[u8]) -> String {
+        let name = match self.names {
+            Names::Joliet => {
+                let units: Vec<u16> = raw
+                    .chunks_exact(2)
+                    .map(|unit| u16::from_be_bytes([unit[0], unit[1]]))
+                    .collect();
+                String::from_utf16_lossy(&units)
+            }
+            _ => String::from_utf8_lossy(raw).into_owned(),
+        };
+        // `README.TXT;1` becomes `README.TXT`, `MAKEFILE.;1` becomes `MAKEFILE`
+        let name = match name.rsplit_once(';') {
+            Some((name, version)) if version.chars().all(|c| c.is_ascii_digit()) => name,
+            _ => &name,
+        };
+        match name.strip_suffix('.') {
+            Some(stem) if !stem.is_empty() && self.names != Names::Joliet => stem.to_string(),
+            _ => name.to_string(),
+        }
+    }
+
+    /// Collect the Rock Ridge entries of a system use area, following `CE` continuations.
+    fn rock_ridge(&self, system_use: &[u8], skip: usize) -> io::Result<RockRidge> {
+        let mut info = RockRidge::default();
+        let mut name = String::new();
+        let mut target = String::new();
+        let mut continues = false;
+
+        let mut area = system_use.get(skip..).unwrap_or_default().to_vec();
+        for _ in 0..16 {
+            let mut continuation = None;
+            let mut position = 0;
+            while position + 4 <= area.len() {
+                let length = area[position + 2] as usize;
+                let Some(entry) = area
+                    .get(position..position + length)
+                    .filter(|_| length >= 4)
+                else {
+                    break;
+                };
+                position += length;
+
+                let le32 = |at: usize| {
+                    let bytes = entry.get(at..at + 4)?;
+                    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+                };
+                match &entry[..2] {
+                    b"PX" => {
+                        info.mode = le32(4);
+                        info.uid = le32(20).unwrap_or(0);
+                        info.gid = le32(28).unwrap_or(0);
+                    }
+                    b"NM" if length > 4 => {
+                        let flags = entry[4];
+                        if flags & 0b110 == 0 {
+                            name.push_str(&String::from_utf8_lossy(&entry[5..]));
+                        }
+                    }
+                    b"SL" if length > 4 => {
+                        read_link_components(&entry[5..], &mut target, &mut continues);
+                        info.link_target = Some(target.clone());
+                    }
+                    b"PN" => {
+                        let (high, low) = (le32(4).unwrap_or(0), le32(12).unwrap_or(0));
+                        // a zero high half means the old 16 bit encoding in the low half
+                        info.device = match high {
+                            0 => (low >> 8, low & 0xff),
+                            _ => (high, low),
+                        };
+                    }
+                    b"TF" if length > 4 => info.mtime = modification_time(entry),
+                    b"CL" => info.child_link = le32(4).map(u64::from),
+                    b"RE" => info.relocated = true,
+                    b"CE" => {
+                        continuation = le32(4).zip(le32(12)).zip(le32(20));
+                    }
+                    b"ST" => break,
+                    _ => {}
+                }
+            }
+
+            let Some(((block, offset), length)) = continuation else {
+                break;
+            };
+            area = vec![0; length.min(SECTOR_SIZE as u32) as usize];
+            let offset = block as u64 * self.block_size + offset as u64;
+            self.source.read_full_at(offset, &mut area)?;
+        }
+
+        if !name.is_empty() {
+            info.name = Some(name);
+        }
+        Ok(info)
+    }
+}
+
+fn parse_record(bytes: &[u8]) -> Option<Record> {
+    let length = *bytes.first()? as usize;
+    let name_length = *bytes.get(32)? as usize;
+    let name = bytes.get(33..33 + name_length)?.to_vec();
+    // a padding byte keeps the system use area at an even offset
+    let system_use_start = 33 + name_length + (1 - name_length % 2);
+    let le32 = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap()) as u64;
+    Some(Record {
+        extent: le32(2),
+        size: le32(10),
+        flags: bytes[25],
+        name,
+        mtime: short_time(&bytes[18..25]),
+        system_use: bytes
+            .get(system_use_start..length)
+            .unwrap_or_default()
+            .to_vec(),
+    })
+}
+
+/// Append the components of an `SL` entry to `target`.
+fn read_link_components(mut components: &[u8], target: &mut String, continues: &mut bool) {
+    while let [flags, length, rest @ ..] = components {
+        let length = (*length as usize).min(rest.len());
+        let text = match flags & 0b1110 {
+            0b0010 => ".".into(),
+            0b0100 => "..".into(),
+            0b1000 => "/".into(),
+            _ => String::from_utf8_lossy(&rest[..length]),
+        };
+        if text == "/" {
+            target.clear();
+        } else if !*continues && !target.is_empty() && !target.ends_with('/') {
+            target.push('/');
+        }
+        target.push_str(&text);
+        *continues = flags & 1 != 0;
+        components = &rest[length..];
+    }
+}
+
+/// The modification time of a `TF` entry, if it has one.
+fn modification_time(entry: &[u8]) -> Option<SystemTime> {
+    const CREATION: u8 = 1;
+    const MODIFY: u8 = 2;
+    const LONG_FORM: u8 = 0x80;
+
+    let flags = entry[4];
+    if flags & MODIFY == 0 {
+        return None;
+    }
+    let width = if flags & LONG_FORM != 0 { 17 } else { 7 };
+    let start = 5 + if flags & CREATION != 0 { width } else { 0 };
+    let stamp = entry.get(start..start + width)?;
+    Some(if width == 7 {
+        short_time(stamp)
+    } else {
+        long_time(stamp)?
+    })
+}
+
+/// A 7 byte date: years since 1900, month, day, hour, minute, second and
+/// the offset from UTC in 15 minute steps.
+fn short_time(stamp: &[u8]) -> SystemTime {
+    let [year, month, day, hours, minutes, seconds, offset] = stamp[..7] else {
+        return SystemTime::UNIX_EPOCH;
+    };
+    let local = unix_time(
+        1900 + year as i64,
+        (month as i64).clamp(1, 12),
+        (day as i64).max(1),
+        hours as i64,
+        minutes as i64,
+        seconds as i64,
+    );
+    let utc = local - offset as i8 as i64 * 15 * 60;
+    SystemTime::UNIX_EPOCH + Duration::from_secs(utc.max(0) as u64)
+}
+
+/// A 17 byte date: `YYYYMMDDHHMMSScc` digits and the offset from UTC.
+fn long_time(stamp: &[u8]) -> Option<SystemTime> {
+    let digits = std::str::from_utf8(&stamp[..14]).ok()?;
+    let field = |range: std::ops::Range<usize>| digits.get(range)?.parse::<i64>().ok();
+    let local = unix_time(
+        field(0..4)?,
+        field(4..6)?.clamp(1, 12),
+        field(6..8)?.max(1),
+        field(8..10)?,
+        field(10..12)?,
+        field(12..14)?,
+    );
+    let utc = local - stamp[16] as i8 as i64 * 15 * 60;
+    Some(SystemTime::UNIX_EPOCH + Duration::from_secs(utc.max(0) as u64))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::tartarfs::source::MemorySource;
+
+    const DATE: [u8; 7] = [124, 2, 29, 13, 37, 42, 4]; // 2024-02-29 13:37:42 +01:00
+
+    fn both_endian32(value: u32) -> Vec<u8> {
+        [value.to_le_bytes(), value.to_be_bytes()].concat()
+    }
+
+    fn record(name: &[u8], extent: u32, size: u32, flags: u8, system_use: &[u8]) -> Vec<u8> {
+        let mut record = vec![0, 0];
+        record.extend(both_endian32(extent));
+        record.extend(both_endian32(size));
+        record.extend(DATE);
+        record.extend([flags, 0, 0, 1, 0, 0, 1, name.len() as u8]);
+        record.extend(name);
+        if name.len().is_multiple_of(2) {
+            record.push(0);
+        }
+        record.extend(system_use);
+        record[0] = record.len() as u8;
+        record
+    }
+
+    fn rock_ridge(name: &str, mode: u32, link: Option<&[&str]>) -> Vec<u8> {
+        let mut entries = vec![b'N', b'M', 5 + name.len() as u8, 1, 0];
+        entries.extend(name.bytes());
+        entries.extend([b'P', b'X', 36, 1]);
+        for field in [mode, 1, 1000, 100] {
+            entries.extend(both_endian32(field));
+        }
+        if let Some(components) = link {
+            let mut sl = vec![b'S', b'L', 0, 1, 0];
+            for component in components {
+                sl.extend([0, component.len() as u8]);
+                sl.extend(component.bytes());
+            }
+            sl[2] = sl.len() as u8;
+            entries.extend(sl);
+        }
+        entries
+    }
+
+    fn sector(records: &[Vec<u8>]) -> Vec<u8> {
+        let mut sector = records.concat();
+        sector.resize(SECTOR_SIZE as usize, 0);
+        sector
+    }
+
+    fn descriptor(kind: u8, root: &[u8], escape: &[u8]) -> Vec<u8> {
+        let mut descriptor = vec![kind];
+        descriptor.extend(STANDARD_ID);
+        descriptor.push(1);
+        descriptor.resize(88, 0);
+        descriptor.extend(escape);
+        descriptor.resize(128, 0);
+        descriptor.extend([0, 8, 8, 0]); // 2048 byte blocks
+        descriptor.resize(156, 0);
+        descriptor.extend(root);
+        descriptor.resize(SECTOR_SIZE as usize, 0);
+        descriptor
+    }
+
+    /// An image with `docs/readme.txt` and a `link` to it, named through
+    /// Rock Ridge, Joliet, both or neither.
+    pub(crate) fn iso(with_rock_ridge: bool, with_joliet: bool) -> Vec<u8> {
+        let (root, docs, joliet_root, joliet_docs, data) = (19, 20, 21, 22, 23);
+        let text = b"Hello from ISO!";
+        let sector_size = SECTOR_SIZE as u32;
+        let rr = |name, mode, link| match with_rock_ridge {
+            true => rock_ridge(name, mode, link),
+            false => Vec::new(),
+        };
+        let sp = match with_rock_ridge {
+            true => vec![b'S', b'P', 7, 1, 0xbe, 0xef, 0],
+            false => Vec::new(),
+        };
+
+        let mut image = vec![0; 16 * SECTOR_SIZE as usize];
+        let root_record = record(&[0], root, sector_size, FLAG_DIRECTORY, &[]);
+        image.extend(descriptor(1, &root_record, &[]));
+        let joliet_record = record(&[0], joliet_root, sector_size, FLAG_DIRECTORY, &[]);
+        if with_joliet {
+            image.extend(descriptor(2, &joliet_record, b"%/E"));
+        }
+        image.extend(descriptor(255, &[], &[]));
+        image.resize(root as usize * SECTOR_SIZE as usize, 0);
+
+        image.extend(sector(&[
+            record(&[0], root, sector_size, FLAG_DIRECTORY, &sp),
+            record(&[1], root, sector_size, FLAG_DIRECTORY, &[]),
+            record(
+                b"DOCS",
+                docs,
+                sector_size,
+                FLAG_DIRECTORY,
+                &rr("docs", 0o40750, None),
+            ),
+            record(
+                b"LINK.;1",
+                0,
+                0,
+                0,
+                &rr("link", 0o120777, Some(&["docs", "readme.txt"])),
+            ),
+        ]));
+        image.extend(sector(&[
+            record(&[0], docs, sector_size, FLAG_DIRECTORY, &[]),
+            record(&[1], root, sector_size, FLAG_DIRECTORY, &[]),
+            record(
+                b"README.TXT;1",
+                data,
+                text.len() as u32,
+                0,
+                &rr("readme.txt", 0o100640, None),
+            ),
+        ]));
+
+        let ucs2 =
+            |name: &str| -> Vec<u8> { name.encode_utf16().flat_map(u16::to_be_bytes).collect() };
+        image.extend(sector(&[
+            record(&[0], joliet_root, sector_size, FLAG_DIRECTORY, &[]),
+            record(&[1], joliet_root, sector_size, FLAG_DIRECTORY, &[]),
+            record(
+                &ucs2("Documents"),
+                joliet_docs,
+                sector_size,
+                FLAG_DIRECTORY,
+                &[],
+            ),
+        ]));
+        image.extend(sector(&[
+            record(&[0], joliet_docs, sector_size, FLAG_DIRECTORY, &[]),
+            record(&[1], joliet_root, sector_size, FLAG_DIRECTORY, &[]),
+            record(
+                &ucs2("Read me, Jöliet.txt;1"),
+                data,
+                text.len() as u32,
+                0,
+                &[],
+            ),
+        ]));
+        image.extend(sector(&[text.to_vec()]));
+        image
+    }
+
+    fn paths(image: Vec<u8>) -> io::Result<Vec<(String, bool, u32)>> {
+        let source: Arc<dyn ByteSource> = Arc::new(MemorySource::new(image));
+        assert!(Iso9660.sniff(&source));
+        let entries = Iso9660.entries(&source, &Decoding::default())?;
+        Ok(entries
+            .into_iter()
+            .map(|e| (e.path, e.is_dir, e.mode))
+            .collect())
+    }
+
+    #[test]
+    fn test_names() -> io::Result<()> {
+        let expected = |names: [&str; 2], dir_mode| {
+            vec![
+                (names[0].to_string(), true, dir_mode),
+                (names[1].to_string(), false, 0o644),
+            ]
+        };
+        let mut rock_ridge = paths(iso(true, true))?;
+        rock_ridge.sort();
+        assert_eq!(
+            rock_ridge,
+            [
+                ("docs".to_string(), true, 0o750),
+                ("docs/readme.txt".to_string(), false, 0o640),
+                ("link".to_string(), false, 0o777),
+            ]
+        );
+        assert_eq!(
+            paths(iso(false, true))?,
+            expected(["Documents", "Documents/Read me, Jöliet.txt"], 0o755)
+        );
+        let plain = paths(iso(false, false))?;
+        assert_eq!(plain[..2], expected(["DOCS", "DOCS/README.TXT"], 0o755)[..]);
+        assert_eq!(plain[2], ("LINK".to_string(), false, 0o644));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_link_components() {
+        let (mut target, mut continues) = (String::new(), false);
+        let components = [
+            &[8, 0][..],
+            &[0, 3],
+            b"usr",
+            &[1, 3],
+            b"lib",
+            &[0, 2],
+            b"64",
+        ]
+        .concat();
+        read_link_components(&components, &mut target, &mut continues);
+        assert_eq!(target, "/usr/lib64");
+
+        let (mut target, mut continues) = (String::new(), false);
+        read_link_components(&[4, 0, 0, 1, b'x'], &mut target, &mut continues);
+        assert_eq!(target, "../x");
+    }
+
+    #[test]
+    fn test_times() {
+        let expected = SystemTime::UNIX_EPOCH + Duration::from_secs(1_709_210_262);
+        assert_eq!(short_time(&DATE), expected);
+        assert_eq!(long_time(b"2024022913374200\x04"), Some(expected));
+    }
+}
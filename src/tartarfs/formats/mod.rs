@@ -0,0 +1,406 @@
+//! Archive format backends.
+//!
+//! A backend turns the bytes of an archive into a list of [`Entry`]s; the
+//! filesystem takes care of indexing them and serving `lookup`, `getattr`,
+//! `readdir` and `read`. Implement [`ArchiveFormat`] and add it to
+//! [`Formats`] to mount formats of your own.
+
+pub(crate) mod apk;
+pub(crate) mod ar;
+pub(crate) mod conda;
+pub(crate) mod cpio;
+mod deb;
+pub(crate) mod iso;
+pub(crate) mod oci;
+pub(crate) mod rpm;
+pub(crate) mod sevenz;
+pub(crate) mod squashfs;
+pub(crate) mod stargz;
+mod tar;
+pub(crate) mod warc;
+mod zip;
+
+pub use self::apk::Apk;
+pub use self::ar::Ar;
+pub use self::conda::Conda;
+pub use self::cpio::Cpio;
+pub use self::deb::Deb;
+pub use self::iso::Iso9660;
+pub use self::oci::Oci;
+pub use self::rpm::Rpm;
+pub use self::sevenz::SevenZip;
+pub use self::squashfs::SquashFs;
+pub use self::stargz::Stargz;
+pub use self::tar::{CompressedTar, Tar};
+pub use self::warc::Warc;
+pub use self::zip::Zip;
+
+use super::compression::Decoding;
+use super::digest::Digest;
+use super::source::ByteSource;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// A format backend, e.g. tar or zip.
+pub trait ArchiveFormat: Send + Sync {
+    /// A short name for logs, e.g. `zip`.
+    fn name(&self) -> &str;
+
+    /// Check the magic bytes, without reading more of `source` than needed.
+    fn sniff(&self, source: &Arc<dyn ByteSource>) -> bool;
+
+    /// Whether an archive member named like this should be tried as a nested
+    /// archive, e.g. `lib/app.jar`; checked before sniffing it.
+    fn matches_name(&self, _name: &str) -> bool {
+        false
+    }
+
+    /// List every member of the archive along with a reader for its data,
+    /// decoding compressed streams in it as `decoding` says.
+    fn entries(&self, source: &Arc<dyn ByteSource>, decoding: &Decoding) -> io::Result<Vec<Entry>>;
+
+    /// Whether a directory holds an archive in this format, like an OCI
+    /// image layout; most formats only come as files.
+    fn sniff_dir(&self, _path: &Path) -> bool {
+        false
+    }
+
+    /// List every member of an archive laid out as a directory.
+    fn dir_entries(&self, _path: &Path, _decoding: &Decoding) -> io::Result<Vec<Entry>> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{} archives don't come as directories", self.name()),
+        ))
+    }
+
+    /// How this backend is configured, e.g. `layers` for one that also lists
+    /// image layers, as far as it changes the entries listed; indexes saved
+    /// with other settings don't get loaded.
+    fn settings(&self) -> String {
+        String::new()
+    }
+}
+
+/// One member of an archive, in whatever format it came from.
+#[derive(Clone)]
+pub struct Entry {
+    /// The member path as stored, e.g. `./docs/index.md`; it gets normalized.
+    pub path: String,
+    pub is_dir: bool,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub mtime: SystemTime,
+    /// The member's data; `None` for directories.
+    pub content: Option<Arc<dyn ByteSource>>,
+    /// What the data should hash to, for archives that record it.
+    pub digest: Option<Digest>,
+    /// Where a symbolic link points; its `content` is `None`.
+    pub link_target: Option<String>,
+    /// Set for device nodes, FIFOs and sockets, which have no `content`.
+    pub special: Option<Special>,
+    /// Extended attributes as full names like `user.comment`, with values.
+    pub xattrs: Vec<(String, Vec<u8>)>,
+}
+
+/// The kinds of file that are neither regular files, directories nor links.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Special {
+    CharDevice { major: u32, minor: u32 },
+    BlockDevice { major: u32, minor: u32 },
+    Fifo,
+    Socket,
+}
+
+impl Special {
+    /// Pick the kind from the file type bits of a Unix `st_mode`.
+    pub fn from_mode(mode: u32, major: u32, minor: u32) -> Option<Self> {
+        match mode & 0o170000 {
+            0o020000 => Some(Special::CharDevice { major, minor }),
+            0o060000 => Some(Special::BlockDevice { major, minor }),
+            0o010000 => Some(Special::Fifo),
+            0o140000 => Some(Special::Socket),
+            _ => None,
+        }
+    }
+}
+
+impl Entry {
+    pub fn file(path: String, content: Arc<dyn ByteSource>) -> Self {
+        Self {
+            path,
+            is_dir: false,
+            mode: 0o644,
+            uid: 1000,
+            gid: 1000,
+            mtime: SystemTime::now(),
+            content: Some(content),
+            digest: None,
+            link_target: None,
+            special: None,
+            xattrs: Vec::new(),
+        }
+    }
+
+    pub fn symlink(path: String, target: String) -> Self {
+        Self {
+            path,
+            is_dir: false,
+            mode: 0o777,
+            uid: 1000,
+            gid: 1000,
+            mtime: SystemTime::now(),
+            content: None,
+            digest: None,
+            link_target: Some(target),
+            special: None,
+            xattrs: Vec::new(),
+        }
+    }
+
+    pub fn special(path: String, special: Special) -> Self {
+        Self {
+            path,
+            is_dir: false,
+            mode: 0o644,
+            uid: 1000,
+            gid: 1000,
+            mtime: SystemTime::now(),
+            content: None,
+            digest: None,
+            link_target: None,
+            special: Some(special),
+            xattrs: Vec::new(),
+        }
+    }
+
+    pub fn directory(path: String) -> Self {
+        Self {
+            path,
+            is_dir: true,
+            mode: 0o755,
+            uid: 1000,
+            gid: 1000,
+            mtime: SystemTime::now(),
+            content: None,
+            digest: None,
+            link_target: None,
+            special: None,
+            xattrs: Vec::new(),
+        }
+    }
+}
+
+/// The format backends to try, in order of preference.
+#[derive(Clone)]
+pub struct Formats {
+    formats: Vec<Arc<dyn ArchiveFormat>>,
+}
+
+impl Formats {
+    /// Add a backend that is tried before the ones already registered.
+    pub fn register(&mut self, format: Arc<dyn ArchiveFormat>) {
+        self.formats.insert(0, format);
+    }
+
+    /// Move the backend with this name to the front, e.g. `ar` to see the
+    /// outer members of a `.deb`; false if there is no such backend.
+    pub fn prefer(&mut self, name: &str) -> bool {
+        let Some(index) = self.formats.iter().position(|f| f.name() == name) else {
+            return false;
+        };
+        let format = self.formats.remove(index);
+        self.formats.insert(0, format);
+        true
+    }
+
+    /// Swap the backend of the same name for this one, e.g. one configured
+    /// differently; false if there is no such backend.
+    pub fn replace(&mut self, format: Arc<dyn ArchiveFormat>) -> bool {
+        let Some(index) = self.formats.iter().position(|f| f.name() == format.name()) else {
+            return false;
+        };
+        self.formats[index] = format;
+        true
+    }
+
+    /// The names of the registered backends, in order of preference.
+    pub fn names(&self) -> Vec<&str> {
+        self.formats.iter().map(|format| format.name()).collect()
+    }
+
+    /// Pick the backend for a whole archive, falling back to plain tar
+    /// so that whatever it is at least gets a readable error.
+    pub fn detect(&self, source: &Arc<dyn ByteSource>) -> Arc<dyn ArchiveFormat> {
+        self.formats
+            .iter()
+            .find(|format| format.sniff(source))
+            .cloned()
+            .unwrap_or_else(|| Arc::new(Tar))
+    }
+
+    /// Pick the backend for an archive laid out as a directory, if any.
+    pub fn detect_dir(&self, path: &Path) -> Option<Arc<dyn ArchiveFormat>> {
+        self.formats
+            .iter()
+            .find(|format| format.sniff_dir(path))
+            .cloned()
+    }
+
+    /// Pick the backend for an archive member that should be browsable as
+    /// a directory, going by its name first so that ordinary files are left alone.
+    pub fn detect_nested(
+        &self,
+        name: &str,
+        source: &Arc<dyn ByteSource>,
+    ) -> Option<Arc<dyn ArchiveFormat>> {
+        let name = name.to_lowercase();
+        self.formats
+            .iter()
+            .filter(|format| format.matches_name(&name))
+            .find(|format| format.sniff(source))
+            .cloned()
+    }
+}
+
+impl Default for Formats {
+    fn default() -> Self {
+        Self {
+            formats: vec![
+                Arc::new(Conda),
+                Arc::new(Zip),
+                Arc::new(Deb),
+                Arc::new(Rpm),
+                Arc::new(Iso9660),
+                Arc::new(SquashFs),
+                Arc::new(SevenZip),
+                Arc::new(Warc::default()),
+                Arc::new(Apk),
+                Arc::new(Stargz),
+                Arc::new(CompressedTar),
+                Arc::new(Oci::default()),
+                Arc::new(Cpio),
+                Arc::new(Ar),
+                Arc::new(Tar),
+            ],
+        }
+    }
+}
+
+impl fmt::Debug for Formats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let formats = self.formats.iter().map(|format| match format.settings() {
+            settings if settings.is_empty() => format.name().to_string(),
+            settings => format!("{}({settings})", format.name()),
+        });
+        f.debug_list().entries(formats).finish()
+    }
+}
+
+/// Check whether a lowercased name ends with one of the extensions.
+pub(crate) fn has_extension(name: &str, extensions: &[&str]) -> bool {
+    extensions.iter().any(|extension| name.ends_with(extension))
+}
+
+/// Seconds since the epoch for a proleptic Gregorian date and time in UTC.
+pub(crate) fn unix_time(
+    year: i64,
+    month: i64,
+    day: i64,
+    hours: i64,
+    minutes: i64,
+    seconds: i64,
+) -> i64 {
+    let (y, m) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = y.div_euclid(400);
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * m + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    days * 86_400 + hours * 3600 + minutes * 60 + seconds
+}
+
+/// Parse an RFC 3339 timestamp in UTC like `2024-03-01T12:30:00Z`,
+/// ignoring fractions of a second.
+pub(crate) fn parse_timestamp(text: &str) -> Option<SystemTime> {
+    let number = |range: std::ops::Range<usize>| text.get(range)?.parse::<i64>().ok();
+    let seconds = unix_time(
+        number(0..4)?,
+        number(5..7)?,
+        number(8..10)?,
+        number(11..13)?,
+        number(14..16)?,
+        number(17..19).unwrap_or(0),
+    );
+    SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(seconds.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::SliceSource;
+    use crate::test_utils::TestSetup;
+    use crate::Options;
+    use std::fs;
+    use tempfile::TempDir;
+
+    /// A made up format: a `TOY` line, then one `path=contents` line per file.
+    struct Toy;
+
+    impl ArchiveFormat for Toy {
+        fn name(&self) -> &str {
+            "toy"
+        }
+
+        fn sniff(&self, source: &Arc<dyn ByteSource>) -> bool {
+            let mut magic = [0; 4];
+            matches!(source.read_full_at(0, &mut magic), Ok(4)) && magic == *b"TOY\n"
+        }
+
+        fn entries(
+            &self,
+            source: &Arc<dyn ByteSource>,
+            _decoding: &Decoding,
+        ) -> io::Result<Vec<Entry>> {
+            let mut text = vec![0; source.size() as usize];
+            source.read_full_at(0, &mut text)?;
+
+            let mut entries = Vec::new();
+            let mut offset = 4;
+            for line in text[4..].split_inclusive(|&b| b == b'\n') {
+                let equals = line.iter().position(|&b| b == b'=').unwrap_or(line.len());
+                let path = String::from_utf8_lossy(&line[..equals]).into_owned();
+                let start = offset + equals as u64 + 1;
+                let size = line.len().saturating_sub(equals + 2) as u64;
+                let content = SliceSource::new(Arc::clone(source), start, size);
+                entries.push(Entry::file(path, Arc::new(content)));
+                offset += line.len() as u64;
+            }
+            Ok(entries)
+        }
+    }
+
+    #[test]
+    fn test_custom_format() -> io::Result<()> {
+        let source_dir = TempDir::new()?;
+        let archive_path = source_dir.path().join("files.toy");
+        fs::write(&archive_path, "TOY\nhello.txt=Hello!\nsub/dir/a.txt=A\n")?;
+
+        let mut options = Options::default();
+        options.formats.register(Arc::new(Toy));
+        let mounted = TestSetup::from_archive(&archive_path, options)?;
+        mounted.assert_is_file("hello.txt", Some(0o644), Some("Hello!"));
+        mounted.assert_is_dir("sub/dir", None);
+        mounted.assert_is_file("sub/dir/a.txt", None, Some("A"));
+
+        Ok(())
+    }
+}
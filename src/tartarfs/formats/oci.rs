@@ -0,0 +1,504 @@
+use super::{ArchiveFormat, CompressedTar, Entry, Stargz, Tar};
+use crate::tartarfs::compression::Decoding;
+use crate::tartarfs::index::normalize_path;
+use crate::tartarfs::source::{ByteSource, FileSource};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Error, ErrorKind};
+use std::path::Path;
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+/// Where each layer is shown as it is when [`Oci::layers`] is set,
+/// e.g. `.layers/sha256:4f.../etc/hosts`.
+pub const LAYERS_DIR: &str = ".layers";
+const WHITEOUT_PREFIX: &str = ".wh.";
+const OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+/// Indexes pointing at indexes pointing at manifests; more is surely a loop.
+const MAX_INDEX_DEPTH: u32 = 4;
+
+/// A container image: an OCI image layout, as a directory or a tarball,
+/// or a tarball written by `docker save`.
+///
+/// The image shows up as the root filesystem a container would see, with
+/// the layers applied in manifest order. Whiteouts like `.wh.passwd` hide
+/// that file of the layers below, and `.wh..wh..opq` everything else in
+/// its directory. Images for several platforms show the one for this host.
+#[derive(Default)]
+pub struct Oci {
+    /// Also show each layer unmerged, whiteouts and all, under [`LAYERS_DIR`].
+    pub layers: bool,
+}
+
+impl ArchiveFormat for Oci {
+    fn name(&self) -> &str {
+        "oci"
+    }
+
+    fn settings(&self) -> String {
+        match self.layers {
+            true => "layers".into(),
+            false => String::new(),
+        }
+    }
+
+    /// Check for a tarball starting like image tarballs do; the manifest
+    /// tends to come at the end, where it would take reading all the headers.
+    fn sniff(&self, source: &Arc<dyn ByteSource>) -> bool {
+        let mut header = [0; 512];
+        if !Tar.sniff(source) || source.read_full_at(0, &mut header).is_err() {
+            return false;
+        }
+        let Ok(name) = tar::Header::from_byte_slice(&header).path() else {
+            return false;
+        };
+        let name = normalize_path(&name.to_string_lossy());
+        let first = name.split('/').next().unwrap_or_default();
+        // older `docker save` starts with a directory or config named by an ID
+        let id = first.strip_suffix(".json").unwrap_or(first);
+        [
+            "blobs",
+            "oci-layout",
+            "index.json",
+            "manifest.json",
+            "repositories",
+        ]
+        .contains(&first)
+            || (id.len() == 64 && id.bytes().all(|b| b.is_ascii_hexdigit()))
+    }
+
+    fn entries(&self, source: &Arc<dyn ByteSource>, decoding: &Decoding) -> io::Result<Vec<Entry>> {
+        let blobs: HashMap<String, Arc<dyn ByteSource>> = Tar
+            .entries(source, decoding)?
+            .into_iter()
+            .filter_map(|entry| Some((normalize_path(&entry.path), entry.content?)))
+            .collect();
+        let open = |name: &str| {
+            let blob = blobs.get(name).cloned();
+            blob.ok_or_else(|| Error::new(ErrorKind::NotFound, format!("no {name} in the image")))
+        };
+        self.image_entries(&open, decoding)
+    }
+
+    fn sniff_dir(&self, path: &Path) -> bool {
+        path.join("manifest.json").is_file()
+            || (path.join("oci-layout").is_file() && path.join("index.json").is_file())
+    }
+
+    fn dir_entries(&self, path: &Path, decoding: &Decoding) -> io::Result<Vec<Entry>> {
+        let open = |name: &str| -> io::Result<Arc<dyn ByteSource>> {
+            Ok(Arc::new(FileSource::open(path.join(name))?))
+        };
+        self.image_entries(&open, decoding)
+    }
+}
+
+/// Opens a file of the image by its path, like `blobs/sha256/4f...`.
+type Open<'a> = &'a dyn Fn(&str) -> io::Result<Arc<dyn ByteSource>>;
+
+/// A layer tarball, possibly compressed.
+struct Layer {
+    digest: String,
+    blob: Arc<dyn ByteSource>,
+}
+
+impl Oci {
+    fn image_entries(&self, open: Open, decoding: &Decoding) -> io::Result<Vec<Entry>> {
+        let layers = match open("manifest.json") {
+            Ok(manifest) => docker_layers(open, &manifest)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                let index: Manifest = read_json(&open("index.json")?)?;
+                oci_layers(open, index, 0)?
+            }
+            Err(e) => return Err(e),
+        };
+
+        let mut merged = Merged::default();
+        let mut entries = Vec::new();
+        for layer in layers {
+            // eStargz and zstd:chunked layers have a TOC, which saves decoding them
+            let layer_entries = if Stargz.sniff(&layer.blob) {
+                Stargz.entries(&layer.blob, decoding)?
+            } else if CompressedTar.sniff(&layer.blob) {
+                CompressedTar.entries(&layer.blob, decoding)?
+            } else {
+                Tar.entries(&layer.blob, decoding)?
+            };
+            debug!("Layer {} has {} entries", layer.digest, layer_entries.len());
+            if self.layers {
+                let dir = format!("{LAYERS_DIR}/{}", layer.digest);
+                entries.extend(layer_entries.iter().cloned().map(|mut entry| {
+                    entry.path = format!("{dir}/{}", entry.path);
+                    entry
+                }));
+            }
+            merged.apply(layer_entries);
+        }
+        entries.extend(merged.entries.into_values());
+        Ok(entries)
+    }
+}
+
+/// `manifest.json` of `docker save`, one item per image.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DockerImage {
+    layers: Vec<String>,
+}
+
+fn docker_layers(open: Open, manifest: &Arc<dyn ByteSource>) -> io::Result<Vec<Layer>> {
+    let images: Vec<DockerImage> = read_json(manifest)?;
+    let Some(image) = images.into_iter().next() else {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "no image in manifest.json",
+        ));
+    };
+    if let Some(path) = image.layers.iter().find(|path| !is_relative(path)) {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("bad layer path {path}"),
+        ));
+    }
+    image
+        .layers
+        .into_iter()
+        .map(|path| {
+            let blob = open(&path)?;
+            // `blobs/sha256/4f...` since Docker 25, `<layer ID>/layer.tar` before
+            let digest = match path.strip_prefix("blobs/") {
+                Some(digest) => digest.replacen('/', ":", 1),
+                None => path.split('/').next().unwrap_or_default().to_string(),
+            };
+            Ok(Layer { digest, blob })
+        })
+        .collect()
+}
+
+/// An image index or manifest; the former lists manifests, the latter layers.
+#[derive(Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    manifests: Vec<Descriptor>,
+    #[serde(default)]
+    layers: Vec<Descriptor>,
+}
+
+#[derive(Deserialize)]
+struct Descriptor {
+    digest: String,
+    platform: Option<Platform>,
+}
+
+#[derive(Deserialize)]
+struct Platform {
+    os: String,
+    architecture: String,
+}
+
+fn oci_layers(open: Open, index: Manifest, depth: u32) -> io::Result<Vec<Layer>> {
+    if depth > MAX_INDEX_DEPTH {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "image indexes nest too deep",
+        ));
+    }
+    if index.manifests.is_empty() {
+        return index
+            .layers
+            .into_iter()
+            .map(|layer| {
+                let blob = open(&blob_path(&layer.digest)?)?;
+                Ok(Layer {
+                    digest: layer.digest,
+                    blob,
+                })
+            })
+            .collect();
+    }
+
+    let Some(descriptor) = pick_manifest(&index.manifests) else {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "no manifest in image index",
+        ));
+    };
+    let manifest: Manifest = read_json(&open(&blob_path(&descriptor.digest)?)?)?;
+    oci_layers(open, manifest, depth + 1)
+}
+
+/// Pick the manifest for this host, or failing that the first one that
+/// isn't an attestation, which are listed with an `unknown` platform.
+fn pick_manifest(manifests: &[Descriptor]) -> Option<&Descriptor> {
+    let architecture = match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        "x86" => "386",
+        arch => arch,
+    };
+    let is_host =
+        |platform: &Platform| platform.os == "linux" && platform.architecture == architecture;
+    manifests
+        .iter()
+        .find(|d| d.platform.as_ref().is_some_and(is_host))
+        .or_else(|| {
+            let mut known = manifests
+                .iter()
+                .filter(|d| d.platform.as_ref().is_none_or(|p| p.os != "unknown"));
+            known.next()
+        })
+        .or_else(|| manifests.first())
+}
+
+/// `sha256:4f...` is at `blobs/sha256/4f...`.
+fn blob_path(digest: &str) -> io::Result<String> {
+    match digest.split_once(':') {
+        Some((algorithm, hex))
+            if is_relative(algorithm) && is_relative(hex) && !hex.contains('/') =>
+        {
+            Ok(format!("blobs/{algorithm}/{hex}"))
+        }
+        _ => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("bad digest {digest}"),
+        )),
+    }
+}
+
+/// Whether a path stays inside the image, so that a manifest can't point a
+/// directory image at files elsewhere.
+fn is_relative(path: &str) -> bool {
+    !path.is_empty() && !path.starts_with('/') && path.split('/').all(|part| part != "..")
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(source: &Arc<dyn ByteSource>) -> io::Result<T> {
+    let mut json = vec![0; source.size() as usize];
+    source.read_full_at(0, &mut json)?;
+    serde_json::from_slice(&json).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+/// The layers applied so far, by normalized path.
+#[derive(Default)]
+struct Merged {
+    entries: BTreeMap<String, Entry>,
+}
+
+impl Merged {
+    fn apply(&mut self, layer: Vec<Entry>) {
+        let layer: Vec<(String, Entry)> = layer
+            .into_iter()
+            .map(|entry| (normalize_path(&entry.path), entry))
+            .filter(|(path, _)| !path.is_empty())
+            .collect();
+
+        // whiteouts only hide what the layers below have, wherever they are in the tarball
+        for (path, _) in &layer {
+            let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+            if name == OPAQUE_WHITEOUT {
+                self.remove_below(dir);
+            } else if let Some(hidden) = name.strip_prefix(WHITEOUT_PREFIX) {
+                let hidden = match dir {
+                    "" => hidden.to_string(),
+                    dir => format!("{dir}/{hidden}"),
+                };
+                self.entries.remove(&hidden);
+                self.remove_below(&hidden);
+            }
+        }
+
+        for (path, mut entry) in layer {
+            let name = path.rsplit('/').next().unwrap_or_default();
+            if name.starts_with(WHITEOUT_PREFIX) {
+                continue;
+            }
+            // a file in place of a directory hides what was in it, and a
+            // directory in place of a file replaces it
+            if !entry.is_dir {
+                self.remove_below(&path);
+            }
+            for (i, _) in path.match_indices('/') {
+                if self
+                    .entries
+                    .get(&path[..i])
+                    .is_some_and(|parent| !parent.is_dir)
+                {
+                    warn!("Layer replaces file {} with a directory", &path[..i]);
+                    self.entries.remove(&path[..i]);
+                }
+            }
+            entry.path = path.clone();
+            self.entries.insert(path, entry);
+        }
+    }
+
+    /// Remove everything inside a directory, but not the directory itself.
+    fn remove_below(&mut self, dir: &str) {
+        if dir.is_empty() {
+            self.entries.clear();
+            return;
+        }
+        // paths inside `dir` sort from `dir/` up to `dir0`, `0` following `/`
+        let mut below = self.entries.split_off(&format!("{dir}/"));
+        let mut after = below.split_off(&format!("{dir}0"));
+        self.entries.append(&mut after);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::tartarfs::source::MemorySource;
+    use sha2::{Digest, Sha256};
+
+    /// A layer tarball; files with no text are directories.
+    pub(crate) fn layer(files: &[(&str, Option<&str>)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for &(path, text) in files {
+            let mut header = tar::Header::new_gnu();
+            match text {
+                Some(text) => {
+                    header.set_size(text.len() as u64);
+                    header.set_mode(0o644);
+                    header.set_entry_type(tar::EntryType::Regular);
+                    builder
+                        .append_data(&mut header, path, text.as_bytes())
+                        .unwrap();
+                }
+                None => {
+                    header.set_size(0);
+                    header.set_mode(0o755);
+                    header.set_entry_type(tar::EntryType::Directory);
+                    builder.append_data(&mut header, path, &[][..]).unwrap();
+                }
+            }
+        }
+        builder.into_inner().unwrap()
+    }
+
+    /// Two layers: the second deletes `etc/shadow`, empties `var/cache` and
+    /// changes `etc/hosts`.
+    pub(crate) fn layers() -> Vec<Vec<u8>> {
+        vec![
+            layer(&[
+                ("etc/", None),
+                ("etc/hosts", Some("127.0.0.1 old\n")),
+                ("etc/shadow", Some("secret")),
+                ("var/cache/", None),
+                ("var/cache/old", Some("stale")),
+                ("bin", Some("was a file")),
+            ]),
+            layer(&[
+                ("etc/hosts", Some("127.0.0.1 new\n")),
+                ("etc/.wh.shadow", Some("")),
+                ("var/cache/new", Some("fresh")),
+                ("var/cache/.wh..wh..opq", Some("")),
+                ("bin/sh", Some("#!")),
+            ]),
+        ]
+    }
+
+    /// Put files in a tarball, or into a directory if one is given.
+    pub(crate) fn image(files: Vec<(String, Vec<u8>)>, dir: Option<&Path>) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, data) in files {
+            if let Some(dir) = dir {
+                let path = dir.join(&path);
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(path, &data).unwrap();
+                continue;
+            }
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            builder.append_data(&mut header, path, &data[..]).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    /// An OCI image layout holding an index for two platforms.
+    pub(crate) fn oci_layout(dir: Option<&Path>) -> Vec<u8> {
+        let mut files = vec![(
+            "oci-layout".to_string(),
+            br#"{"imageLayoutVersion": "1.0.0"}"#.to_vec(),
+        )];
+        let mut blob = |data: Vec<u8>| {
+            let hex: String = Sha256::digest(&data)
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect();
+            files.push((format!("blobs/sha256/{hex}"), data));
+            format!("sha256:{hex}")
+        };
+        let layers: Vec<String> = layers().into_iter().map(&mut blob).collect();
+        let layers = layers
+            .iter()
+            .map(|digest| format!(r#"{{"digest": "{digest}"}}"#));
+        let manifest = format!(
+            r#"{{"layers": [{}]}}"#,
+            layers.collect::<Vec<_>>().join(", ")
+        );
+        let manifest = blob(manifest.into_bytes());
+        let other = blob(br#"{"layers": []}"#.to_vec());
+        let index = format!(
+            r#"{{"manifests": [
+                {{"digest": "{other}", "platform": {{"os": "unknown", "architecture": "unknown"}}}},
+                {{"digest": "{manifest}"}}
+            ]}}"#
+        );
+        let nested = blob(index.into_bytes());
+        let index = format!(r#"{{"manifests": [{{"digest": "{nested}"}}]}}"#);
+        files.push(("index.json".to_string(), index.into_bytes()));
+        image(files, dir)
+    }
+
+    fn paths(entries: &[Entry]) -> Vec<&str> {
+        entries.iter().map(|e| e.path.as_str()).collect()
+    }
+
+    #[test]
+    fn test_whiteouts() -> io::Result<()> {
+        let mut files: Vec<_> = layers()
+            .into_iter()
+            .enumerate()
+            .map(|(i, layer)| (format!("{i:064}/layer.tar"), layer))
+            .collect();
+        let manifest = r#"[{"Config": "x.json", "Layers": ["0000000000000000000000000000000000000000000000000000000000000000/layer.tar", "0000000000000000000000000000000000000000000000000000000000000001/layer.tar"]}]"#;
+        files.push(("manifest.json".to_string(), manifest.as_bytes().to_vec()));
+        let source: Arc<dyn ByteSource> = Arc::new(MemorySource::new(image(files, None)));
+        assert!(Oci::default().sniff(&source));
+
+        let entries = Oci::default().entries(&source, &Decoding::default())?;
+        assert_eq!(
+            paths(&entries),
+            ["bin/sh", "etc", "etc/hosts", "var/cache", "var/cache/new"]
+        );
+        let content = entries[2].content.as_ref().unwrap();
+        let mut hosts = vec![0; content.size() as usize];
+        content.read_full_at(0, &mut hosts)?;
+        assert_eq!(hosts, b"127.0.0.1 new\n");
+
+        let entries = Oci { layers: true }.entries(&source, &Decoding::default())?;
+        let first = format!("{LAYERS_DIR}/{:064}/etc/shadow", 0);
+        assert!(paths(&entries).contains(&first.as_str()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_oci_layout() -> io::Result<()> {
+        let source: Arc<dyn ByteSource> = Arc::new(MemorySource::new(oci_layout(None)));
+        assert!(Oci::default().sniff(&source));
+        let entries = Oci::default().entries(&source, &Decoding::default())?;
+        assert_eq!(
+            paths(&entries),
+            ["bin/sh", "etc", "etc/hosts", "var/cache", "var/cache/new"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_blob_path() {
+        assert_eq!(blob_path("sha256:ab").unwrap(), "blobs/sha256/ab");
+        assert!(blob_path("sha256:../../etc/passwd").is_err());
+        assert!(blob_path("ab").is_err());
+    }
+}
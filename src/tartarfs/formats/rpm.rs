@@ -0,0 +1,510 @@
+use super::{has_extension, ArchiveFormat, Cpio, Entry};
+use crate::tartarfs::compression::{decompress, Codec, Decoding};
+use crate::tartarfs::digest::{Algorithm, Digest};
+use crate::tartarfs::index::normalize_path;
+use crate::tartarfs::source::{ByteSource, MemorySource, SliceSource};
+use std::collections::HashMap;
+use std::io::{self, Error, ErrorKind};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+const LEAD_SIZE: u64 = 96;
+const LEAD_MAGIC: [u8; 4] = [0xed, 0xab, 0xee, 0xdb];
+const HEADER_MAGIC: [u8; 3] = [0x8e, 0xad, 0xe8];
+const MAX_HEADER_SIZE: u64 = 256 * 1024 * 1024;
+
+/// Where the header tags show up as files, next to the payload.
+pub const METADATA_DIR: &str = ".rpm";
+
+mod tag {
+    pub const NAME: u32 = 1000;
+    pub const VERSION: u32 = 1001;
+    pub const RELEASE: u32 = 1002;
+    pub const EPOCH: u32 = 1003;
+    pub const SUMMARY: u32 = 1004;
+    pub const DESCRIPTION: u32 = 1005;
+    pub const BUILDTIME: u32 = 1006;
+    pub const BUILDHOST: u32 = 1007;
+    pub const SIZE: u32 = 1009;
+    pub const VENDOR: u32 = 1011;
+    pub const LICENSE: u32 = 1014;
+    pub const PACKAGER: u32 = 1015;
+    pub const GROUP: u32 = 1016;
+    pub const URL: u32 = 1020;
+    pub const OS: u32 = 1021;
+    pub const ARCH: u32 = 1022;
+    pub const PREIN: u32 = 1023;
+    pub const POSTIN: u32 = 1024;
+    pub const PREUN: u32 = 1025;
+    pub const POSTUN: u32 = 1026;
+    pub const OLDFILENAMES: u32 = 1027;
+    pub const FILEDIGESTS: u32 = 1035;
+    pub const SOURCERPM: u32 = 1044;
+    pub const PROVIDENAME: u32 = 1047;
+    pub const REQUIREFLAGS: u32 = 1048;
+    pub const REQUIRENAME: u32 = 1049;
+    pub const REQUIREVERSION: u32 = 1050;
+    pub const CONFLICTFLAGS: u32 = 1053;
+    pub const CONFLICTNAME: u32 = 1054;
+    pub const CONFLICTVERSION: u32 = 1055;
+    pub const OBSOLETENAME: u32 = 1090;
+    pub const PROVIDEFLAGS: u32 = 1112;
+    pub const PROVIDEVERSION: u32 = 1113;
+    pub const OBSOLETEFLAGS: u32 = 1114;
+    pub const OBSOLETEVERSION: u32 = 1115;
+    pub const DIRINDEXES: u32 = 1116;
+    pub const BASENAMES: u32 = 1117;
+    pub const DIRNAMES: u32 = 1118;
+    pub const PAYLOADCOMPRESSOR: u32 = 1125;
+    pub const PRETRANS: u32 = 1151;
+    pub const POSTTRANS: u32 = 1152;
+    pub const FILEDIGESTALGO: u32 = 5011;
+}
+
+/// Header tags shown as one file each, one value per line.
+const TEXT_TAGS: [(&str, u32); 18] = [
+    ("name", tag::NAME),
+    ("version", tag::VERSION),
+    ("release", tag::RELEASE),
+    ("epoch", tag::EPOCH),
+    ("arch", tag::ARCH),
+    ("os", tag::OS),
+    ("summary", tag::SUMMARY),
+    ("description", tag::DESCRIPTION),
+    ("license", tag::LICENSE),
+    ("group", tag::GROUP),
+    ("url", tag::URL),
+    ("vendor", tag::VENDOR),
+    ("packager", tag::PACKAGER),
+    ("buildhost", tag::BUILDHOST),
+    ("buildtime", tag::BUILDTIME),
+    ("sourcerpm", tag::SOURCERPM),
+    ("size", tag::SIZE),
+    ("payloadcompressor", tag::PAYLOADCOMPRESSOR),
+];
+
+/// Install scriptlets, named like the `rpm --queryformat` tags.
+const SCRIPT_TAGS: [(&str, u32); 6] = [
+    ("pretrans", tag::PRETRANS),
+    ("prein", tag::PREIN),
+    ("postin", tag::POSTIN),
+    ("preun", tag::PREUN),
+    ("postun", tag::POSTUN),
+    ("posttrans", tag::POSTTRANS),
+];
+
+/// Dependency lists as `(file, name tag, flags tag, version tag)`.
+const DEPENDENCY_TAGS: [(&str, u32, u32, u32); 4] = [
+    (
+        "requires",
+        tag::REQUIRENAME,
+        tag::REQUIREFLAGS,
+        tag::REQUIREVERSION,
+    ),
+    (
+        "provides",
+        tag::PROVIDENAME,
+        tag::PROVIDEFLAGS,
+        tag::PROVIDEVERSION,
+    ),
+    (
+        "conflicts",
+        tag::CONFLICTNAME,
+        tag::CONFLICTFLAGS,
+        tag::CONFLICTVERSION,
+    ),
+    (
+        "obsoletes",
+        tag::OBSOLETENAME,
+        tag::OBSOLETEFLAGS,
+        tag::OBSOLETEVERSION,
+    ),
+];
+
+/// An RPM package: the files of its compressed cpio payload, plus the
+/// header tags as files under [`METADATA_DIR`], e.g. `.rpm/version`,
+/// `.rpm/postin` and `.rpm/filedigests` in the format of `sha256sum`.
+pub struct Rpm;
+
+impl ArchiveFormat for Rpm {
+    fn name(&self) -> &str {
+        "rpm"
+    }
+
+    fn sniff(&self, source: &Arc<dyn ByteSource>) -> bool {
+        let mut magic = [0; 4];
+        matches!(source.read_full_at(0, &mut magic), Ok(4)) && magic == LEAD_MAGIC
+    }
+
+    fn matches_name(&self, name: &str) -> bool {
+        has_extension(name, &[".rpm"])
+    }
+
+    fn entries(&self, source: &Arc<dyn ByteSource>, decoding: &Decoding) -> io::Result<Vec<Entry>> {
+        // the signature header comes first, padded to 8 bytes
+        let signature = Header::read(source.as_ref(), LEAD_SIZE)?;
+        let header_offset = (LEAD_SIZE + signature.size).next_multiple_of(8);
+        let header = Header::read(source.as_ref(), header_offset)?;
+        let payload_offset = header_offset + header.size;
+
+        let mut entries = header.metadata_entries();
+
+        let payload: Arc<dyn ByteSource> = Arc::new(SliceSource::new(
+            Arc::clone(source),
+            payload_offset,
+            source.size().saturating_sub(payload_offset),
+        ));
+        let payload: Arc<dyn ByteSource> = match Codec::detect(payload.as_ref()) {
+            Some(codec) => decompress(payload, codec, &decoding.spool),
+            None => payload,
+        };
+        if !Cpio.sniff(&payload) {
+            let compressor = header.string(tag::PAYLOADCOMPRESSOR).unwrap_or("unknown");
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("unsupported rpm payload compression {compressor}"),
+            ));
+        }
+
+        let mut digests = header.file_digests();
+        for mut entry in Cpio.entries(&payload, decoding)? {
+            entry.digest = digests.remove(&normalize_path(&entry.path));
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+}
+
+enum Value {
+    Int(Vec<u64>),
+    Str(Vec<String>),
+    Bin,
+}
+
+/// An RPM header structure: an index of tags into a data store.
+struct Header {
+    tags: HashMap<u32, Value>,
+    /// The size of the whole structure in the package.
+    size: u64,
+}
+
+impl Header {
+    fn read(source: &dyn ByteSource, offset: u64) -> io::Result<Self> {
+        let invalid = |what: &str| Error::new(ErrorKind::InvalidData, format!("bad rpm {what}"));
+
+        let mut intro = [0; 16];
+        if source.read_full_at(offset, &mut intro)? < 16 || intro[..3] != HEADER_MAGIC {
+            return Err(invalid("header"));
+        }
+        let count = u32::from_be_bytes(intro[8..12].try_into().unwrap()) as u64;
+        let store_size = u32::from_be_bytes(intro[12..16].try_into().unwrap()) as u64;
+        let size = 16 + count * 16 + store_size;
+        if size > MAX_HEADER_SIZE {
+            return Err(invalid("header size"));
+        }
+
+        let mut data = vec![0; (size - 16) as usize];
+        if source.read_full_at(offset + 16, &mut data)? < data.len() {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "truncated rpm header"));
+        }
+        let (index, store) = data.split_at((count * 16) as usize);
+
+        let mut tags = HashMap::new();
+        for entry in index.chunks_exact(16) {
+            let field = |i: usize| u32::from_be_bytes(entry[i * 4..i * 4 + 4].try_into().unwrap());
+            let (tag, kind, offset, count) = (field(0), field(1), field(2) as usize, field(3));
+            let bytes = store.get(offset..).ok_or_else(|| invalid("tag offset"))?;
+
+            let int = |width: usize| {
+                let values = bytes.chunks_exact(width).take(count as usize);
+                let values: Vec<u64> = values
+                    .map(|value| value.iter().fold(0, |n, &b| (n << 8) | b as u64))
+                    .collect();
+                (values.len() == count as usize).then_some(Value::Int(values))
+            };
+            let value = match kind {
+                2 => int(1),
+                3 => int(2),
+                4 => int(4),
+                5 => int(8),
+                // STRING, STRING_ARRAY and I18NSTRING, whose first string is the default
+                6..=9 if kind != 7 => {
+                    let strings: Vec<String> = bytes
+                        .split(|&b| b == 0)
+                        .take(count as usize)
+                        .map(|s| String::from_utf8_lossy(s).into_owned())
+                        .collect();
+                    (strings.len() == count as usize).then_some(Value::Str(strings))
+                }
+                _ => Some(Value::Bin),
+            };
+            tags.insert(tag, value.ok_or_else(|| invalid("tag data"))?);
+        }
+        Ok(Self { tags, size })
+    }
+
+    fn strings(&self, tag: u32) -> &[String] {
+        match self.tags.get(&tag) {
+            Some(Value::Str(strings)) => strings,
+            _ => &[],
+        }
+    }
+
+    fn string(&self, tag: u32) -> Option<&str> {
+        self.strings(tag).first().map(String::as_str)
+    }
+
+    fn ints(&self, tag: u32) -> &[u64] {
+        match self.tags.get(&tag) {
+            Some(Value::Int(ints)) => ints,
+            _ => &[],
+        }
+    }
+
+    /// A tag's values, one per line.
+    fn text(&self, tag: u32) -> Option<String> {
+        let lines: Vec<String> = match self.tags.get(&tag)? {
+            Value::Int(ints) => ints.iter().map(u64::to_string).collect(),
+            Value::Str(strings) => strings.clone(),
+            Value::Bin => return None,
+        };
+        Some(lines.iter().map(|line| format!("{line}\n")).collect())
+    }
+
+    /// Dependencies like `glibc >= 2.34`, one per line.
+    fn dependencies(&self, name: u32, flags: u32, version: u32) -> Option<String> {
+        let names = self.strings(name);
+        if names.is_empty() {
+            return None;
+        }
+        let (flags, versions) = (self.ints(flags), self.strings(version));
+        let lines = names.iter().enumerate().map(|(i, name)| {
+            let flags = flags.get(i).copied().unwrap_or(0);
+            let operator = match flags & 0b1110 {
+                0b0010 => "<",
+                0b0100 => ">",
+                0b1000 => "=",
+                0b1010 => "<=",
+                0b1100 => ">=",
+                _ => "",
+            };
+            match versions.get(i) {
+                Some(version) if !version.is_empty() && !operator.is_empty() => {
+                    format!("{name} {operator} {version}\n")
+                }
+                _ => format!("{name}\n"),
+            }
+        });
+        Some(lines.collect())
+    }
+
+    /// Every file path in the payload, absolute like `/usr/bin/hello`.
+    fn file_paths(&self) -> Vec<String> {
+        let (dirs, indexes) = (self.strings(tag::DIRNAMES), self.ints(tag::DIRINDEXES));
+        let basenames = self.strings(tag::BASENAMES);
+        if basenames.is_empty() {
+            return self.strings(tag::OLDFILENAMES).to_vec();
+        }
+        let dir = |i: usize| {
+            let index = *indexes.get(i)? as usize;
+            dirs.get(index).map(String::as_str)
+        };
+        basenames
+            .iter()
+            .enumerate()
+            .map(|(i, name)| format!("{}{name}", dir(i).unwrap_or("")))
+            .collect()
+    }
+
+    fn digest_algorithm(&self) -> Option<Algorithm> {
+        // the OpenPGP hash algorithm ids, MD5 when not recorded
+        match self.ints(tag::FILEDIGESTALGO).first().copied().unwrap_or(1) {
+            1 => Some(Algorithm::Md5),
+            2 => Some(Algorithm::Sha1),
+            8 => Some(Algorithm::Sha256),
+            9 => Some(Algorithm::Sha384),
+            10 => Some(Algorithm::Sha512),
+            11 => Some(Algorithm::Sha224),
+            _ => None,
+        }
+    }
+
+    /// `(normalized path, hex digest)` of every regular file.
+    fn hex_digests(&self) -> Vec<(String, &str)> {
+        let paths = self.file_paths();
+        let digests = self.strings(tag::FILEDIGESTS);
+        paths
+            .into_iter()
+            .zip(digests)
+            .filter(|(_, digest)| !digest.is_empty())
+            .map(|(path, digest)| (normalize_path(&path), digest.as_str()))
+            .collect()
+    }
+
+    fn file_digests(&self) -> HashMap<String, Digest> {
+        let Some(algorithm) = self.digest_algorithm() else {
+            return HashMap::new();
+        };
+        self.hex_digests()
+            .into_iter()
+            .filter_map(|(path, hex)| Some((path, Digest::from_hex(algorithm, hex)?)))
+            .collect()
+    }
+
+    fn metadata_entries(&self) -> Vec<Entry> {
+        let mtime = self
+            .ints(tag::BUILDTIME)
+            .first()
+            .and_then(|&t| SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(t)))
+            .unwrap_or_else(SystemTime::now);
+
+        let mut files: Vec<(&str, String, u32)> = Vec::new();
+        for (name, tag) in TEXT_TAGS {
+            files.extend(self.text(tag).map(|text| (name, text, 0o644)));
+        }
+        for (name, tag) in SCRIPT_TAGS {
+            files.extend(self.text(tag).map(|text| (name, text, 0o755)));
+        }
+        for (name, names, flags, versions) in DEPENDENCY_TAGS {
+            let text = self.dependencies(names, flags, versions);
+            files.extend(text.map(|text| (name, text, 0o644)));
+        }
+        // relative paths, so that `sha256sum -c .rpm/filedigests` works from the mount root
+        let digests: String = self
+            .hex_digests()
+            .into_iter()
+            .map(|(path, digest)| format!("{digest}  {path}\n"))
+            .collect();
+        if !digests.is_empty() {
+            files.push(("filedigests", digests, 0o644));
+        }
+
+        let mut dir = Entry::directory(METADATA_DIR.to_string());
+        dir.mtime = mtime;
+        let mut entries = vec![dir];
+        for (name, text, mode) in files {
+            let content = Arc::new(MemorySource::new(text.into_bytes()));
+            let mut entry = Entry::file(format!("{METADATA_DIR}/{name}"), content);
+            entry.mode = mode;
+            entry.mtime = mtime;
+            entries.push(entry);
+        }
+        entries
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::tartarfs::formats::cpio::tests::newc;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    pub(crate) enum TestValue<'a> {
+        Int(&'a [u32]),
+        Str(&'a [&'a str]),
+    }
+
+    fn header(tags: &[(u32, TestValue)]) -> Vec<u8> {
+        let (mut index, mut store) = (Vec::new(), Vec::new());
+        for (tag, value) in tags {
+            let (kind, count) = match value {
+                TestValue::Int(ints) => {
+                    store.resize(store.len().next_multiple_of(4), 0);
+                    (4u32, ints.len())
+                }
+                TestValue::Str(strings) => (8u32, strings.len()),
+            };
+            for field in [*tag, kind, store.len() as u32, count as u32] {
+                index.extend(field.to_be_bytes());
+            }
+            match value {
+                TestValue::Int(ints) => ints.iter().for_each(|i| store.extend(i.to_be_bytes())),
+                TestValue::Str(strings) => strings.iter().for_each(|s| {
+                    store.extend(s.bytes());
+                    store.push(0);
+                }),
+            }
+        }
+
+        let mut header = vec![0x8e, 0xad, 0xe8, 0x01, 0, 0, 0, 0];
+        header.extend((tags.len() as u32).to_be_bytes());
+        header.extend((store.len() as u32).to_be_bytes());
+        header.extend(index);
+        header.extend(store);
+        header
+    }
+
+    /// Build a package of the given header tags and payload members.
+    pub(crate) fn rpm(tags: &[(u32, TestValue)], files: &[(&str, u32, &[u8])]) -> Vec<u8> {
+        let mut package = LEAD_MAGIC.to_vec();
+        package.resize(LEAD_SIZE as usize, 0);
+        package.extend(header(&[(1000, TestValue::Int(&[0]))])); // signature
+        package.resize(package.len().next_multiple_of(8), 0);
+        package.extend(header(tags));
+
+        let members: Vec<_> = files
+            .iter()
+            .enumerate()
+            .map(|(i, &(path, mode, data))| (path, mode, i as u32 + 1, 1, data))
+            .collect();
+        let mut payload = GzEncoder::new(package, Compression::fast());
+        payload.write_all(&newc(&members)).unwrap();
+        payload.finish().unwrap()
+    }
+
+    #[test]
+    fn test_read_package() -> io::Result<()> {
+        let md5_hello = "5d41402abc4b2a76b9719d911017c592";
+        let package = rpm(
+            &[
+                (tag::NAME, TestValue::Str(&["hello"])),
+                (tag::EPOCH, TestValue::Int(&[2])),
+                (tag::POSTIN, TestValue::Str(&["/sbin/ldconfig"])),
+                (
+                    tag::REQUIRENAME,
+                    TestValue::Str(&["glibc", "rtld(GNU_HASH)"]),
+                ),
+                (tag::REQUIREFLAGS, TestValue::Int(&[0b1100, 0])),
+                (tag::REQUIREVERSION, TestValue::Str(&["2.34", ""])),
+                (tag::DIRINDEXES, TestValue::Int(&[0, 1])),
+                (tag::BASENAMES, TestValue::Str(&["bin", "hello"])),
+                (tag::DIRNAMES, TestValue::Str(&["/usr/", "/usr/bin/"])),
+                (tag::FILEDIGESTS, TestValue::Str(&["", md5_hello])),
+            ],
+            &[
+                ("./usr/bin", 0o40755, b""),
+                ("./usr/bin/hello", 0o100755, b"hello"),
+            ],
+        );
+        let source: Arc<dyn ByteSource> = Arc::new(MemorySource::new(package));
+        assert!(Rpm.sniff(&source));
+
+        let entries = Rpm.entries(&source, &Decoding::default())?;
+        let text = |path: &str| -> io::Result<String> {
+            let entry = entries.iter().find(|e| e.path == path).unwrap();
+            let content = entry.content.as_ref().unwrap();
+            let mut buf = vec![0; content.size() as usize];
+            content.read_full_at(0, &mut buf)?;
+            Ok(String::from_utf8_lossy(&buf).into_owned())
+        };
+        assert_eq!(text(".rpm/name")?, "hello\n");
+        assert_eq!(text(".rpm/epoch")?, "2\n");
+        assert_eq!(text(".rpm/postin")?, "/sbin/ldconfig\n");
+        assert_eq!(text(".rpm/requires")?, "glibc >= 2.34\nrtld(GNU_HASH)\n");
+        assert_eq!(
+            text(".rpm/filedigests")?,
+            format!("{md5_hello}  usr/bin/hello\n")
+        );
+        assert_eq!(text("./usr/bin/hello")?, "hello");
+
+        let hello = entries
+            .iter()
+            .find(|e| e.path == "./usr/bin/hello")
+            .unwrap();
+        assert_eq!(hello.digest, Digest::from_hex(Algorithm::Md5, md5_hello));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,966 @@
+use super::{has_extension, ArchiveFormat, Entry};
+use crate::tartarfs::compression::{Codec, Decoding, DecompressedSource};
+use crate::tartarfs::source::{ByteSource, CachedSource, MemorySource, SliceSource};
+use std::io::{self, Error, ErrorKind};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tracing::warn;
+
+const SIGNATURE: &[u8; 6] = b"7z\xbc\xaf\x27\x1c";
+const SIGNATURE_HEADER_SIZE: u64 = 32;
+/// Bigger headers than this are surely broken rather than real.
+const MAX_HEADER_SIZE: u64 = 256 * 1024 * 1024;
+
+// property ids of the header database
+const END: u8 = 0x00;
+const HEADER: u8 = 0x01;
+const ARCHIVE_PROPERTIES: u8 = 0x02;
+const ADDITIONAL_STREAMS_INFO: u8 = 0x03;
+const MAIN_STREAMS_INFO: u8 = 0x04;
+const FILES_INFO: u8 = 0x05;
+const PACK_INFO: u8 = 0x06;
+const UNPACK_INFO: u8 = 0x07;
+const SUBSTREAMS_INFO: u8 = 0x08;
+const SIZE: u8 = 0x09;
+const CRC: u8 = 0x0a;
+const FOLDER: u8 = 0x0b;
+const CODERS_UNPACK_SIZE: u8 = 0x0c;
+const NUM_UNPACK_STREAM: u8 = 0x0d;
+const EMPTY_STREAM: u8 = 0x0e;
+const EMPTY_FILE: u8 = 0x0f;
+const ANTI: u8 = 0x10;
+const NAME: u8 = 0x11;
+const MTIME: u8 = 0x14;
+const WIN_ATTRIBUTES: u8 = 0x15;
+const ENCODED_HEADER: u8 = 0x17;
+
+const ATTRIBUTE_READ_ONLY: u32 = 0x01;
+const ATTRIBUTE_DIRECTORY: u32 = 0x10;
+/// p7zip's flag for a Unix mode in the high 16 bits of the attributes.
+const ATTRIBUTE_UNIX_EXTENSION: u32 = 0x8000;
+
+/// Seconds from 1601, where Windows file times start, to 1970.
+const FILETIME_UNIX_EPOCH: u64 = 11_644_473_600;
+
+/// A 7-Zip archive using LZMA, LZMA2, deflate, bzip2, zstd or no compression.
+///
+/// Files in a solid block are slices of the block's decoded stream, which is
+/// decoded on first read and cached, so that reading the files of a block in
+/// turn doesn't decode it from the start each time.
+pub struct SevenZip;
+
+impl ArchiveFormat for SevenZip {
+    fn name(&self) -> &str {
+        "7z"
+    }
+
+    fn sniff(&self, source: &Arc<dyn ByteSource>) -> bool {
+        let mut signature = [0; 6];
+        matches!(source.read_full_at(0, &mut signature), Ok(6)) && signature == *SIGNATURE
+    }
+
+    fn matches_name(&self, name: &str) -> bool {
+        has_extension(name, &[".7z"])
+    }
+
+    fn entries(
+        &self,
+        source: &Arc<dyn ByteSource>,
+        _decoding: &Decoding,
+    ) -> io::Result<Vec<Entry>> {
+        let Some(header) = read_header(source)? else {
+            return Ok(Vec::new()); // an empty archive
+        };
+        let mut cursor = Cursor::new(&header);
+        let (streams, files) = parse_header(&mut cursor)?;
+        Ok(into_entries(source, &streams, files))
+    }
+}
+
+fn invalid(what: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("bad 7z {what}"))
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = flate2::Crc::new();
+    crc.update(data);
+    crc.sum()
+}
+
+/// Read the header database, decoding it first if it's compressed.
+fn read_header(source: &Arc<dyn ByteSource>) -> io::Result<Option<Vec<u8>>> {
+    let mut signature_header = [0; SIGNATURE_HEADER_SIZE as usize];
+    source.read_full_at(0, &mut signature_header)?;
+    if signature_header[..6] != *SIGNATURE {
+        return Err(invalid("signature"));
+    }
+    let field = |at: usize| u64::from_le_bytes(signature_header[at..at + 8].try_into().unwrap());
+    if crc32(&signature_header[12..])
+        != u32::from_le_bytes(signature_header[8..12].try_into().unwrap())
+    {
+        return Err(invalid("start header checksum"));
+    }
+    let (offset, size) = (field(12), field(20));
+    if size == 0 {
+        return Ok(None);
+    }
+    if size > MAX_HEADER_SIZE {
+        return Err(invalid("header size"));
+    }
+
+    let mut header = vec![0; size as usize];
+    let position = SIGNATURE_HEADER_SIZE
+        .checked_add(offset)
+        .ok_or_else(|| invalid("header offset"))?;
+    if source.read_full_at(position, &mut header)? < header.len() {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "truncated 7z header"));
+    }
+    if crc32(&header) != u32::from_le_bytes(signature_header[28..32].try_into().unwrap()) {
+        return Err(invalid("header checksum"));
+    }
+
+    // 7-Zip compresses the header by default, describing it with a header of its own
+    while header.first() == Some(&ENCODED_HEADER) {
+        let mut cursor = Cursor::new(&header[1..]);
+        let streams = parse_streams(&mut cursor)?;
+        let folder = streams
+            .folders
+            .first()
+            .ok_or_else(|| invalid("encoded header"))?;
+        if folder.unpack_size > MAX_HEADER_SIZE {
+            return Err(invalid("header size"));
+        }
+        let decoded = folder_source(source, &streams, 0)?;
+        let mut decoded_header = vec![0; folder.unpack_size as usize];
+        if decoded.read_full_at(0, &mut decoded_header)? < decoded_header.len() {
+            return Err(invalid("encoded header"));
+        }
+        header = decoded_header;
+    }
+    Ok(Some(header))
+}
+
+/// The packed streams and the folders, or solid blocks, decoded from them.
+#[derive(Default)]
+struct Streams {
+    /// Where the first packed stream starts, after the signature header.
+    pack_position: u64,
+    pack_sizes: Vec<u64>,
+    folders: Vec<Folder>,
+    /// The sizes of the files in each folder, in order.
+    substreams: Vec<Vec<u64>>,
+}
+
+struct Folder {
+    coders: Vec<Coder>,
+    packed_streams: usize,
+    /// The size of the folder's final output.
+    unpack_size: u64,
+    has_crc: bool,
+}
+
+struct Coder {
+    method: Vec<u8>,
+    properties: Vec<u8>,
+}
+
+/// What the files part of the header says about one file.
+#[derive(Default, Clone)]
+struct File {
+    name: String,
+    has_stream: bool,
+    is_dir: bool,
+    is_anti: bool,
+    mtime: Option<u64>,
+    attributes: Option<u32>,
+}
+
+fn parse_header(cursor: &mut Cursor) -> io::Result<(Streams, Vec<File>)> {
+    if cursor.u8()? != HEADER {
+        return Err(invalid("header"));
+    }
+    let mut streams = Streams::default();
+    let mut files = Vec::new();
+    loop {
+        match cursor.u8()? {
+            ARCHIVE_PROPERTIES => {
+                while cursor.u8()? != END {
+                    let size = cursor.count()?;
+                    cursor.skip(size)?;
+                }
+            }
+            ADDITIONAL_STREAMS_INFO => {
+                parse_streams(cursor)?;
+            }
+            MAIN_STREAMS_INFO => streams = parse_streams(cursor)?,
+            FILES_INFO => files = parse_files(cursor)?,
+            END => return Ok((streams, files)),
+            _ => return Err(invalid("header property")),
+        }
+    }
+}
+
+fn parse_streams(cursor: &mut Cursor) -> io::Result<Streams> {
+    let mut streams = Streams::default();
+    let mut substreams = None;
+    loop {
+        match cursor.u8()? {
+            PACK_INFO => {
+                streams.pack_position = cursor.number()?;
+                let count = cursor.count()?;
+                loop {
+                    match cursor.u8()? {
+                        SIZE => {
+                            streams.pack_sizes = (0..count)
+                                .map(|_| cursor.number())
+                                .collect::<io::Result<_>>()?
+                        }
+                        CRC => cursor.skip_digests(count)?,
+                        END => break,
+                        _ => return Err(invalid("pack info")),
+                    }
+                }
+            }
+            UNPACK_INFO => {
+                if cursor.u8()? != FOLDER {
+                    return Err(invalid("unpack info"));
+                }
+                let count = cursor.count()?;
+                if cursor.u8()? != 0 {
+                    return Err(invalid("external folders"));
+                }
+                let mut out_streams = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let (folder, outputs) = parse_folder(cursor)?;
+                    streams.folders.push(folder);
+                    out_streams.push(outputs);
+                }
+                if cursor.u8()? != CODERS_UNPACK_SIZE {
+                    return Err(invalid("unpack info"));
+                }
+                for (folder, (outputs, unbound)) in streams.folders.iter_mut().zip(out_streams) {
+                    for output in 0..outputs {
+                        let size = cursor.number()?;
+                        if output == unbound {
+                            folder.unpack_size = size;
+                        }
+                    }
+                }
+                loop {
+                    match cursor.u8()? {
+                        CRC => {
+                            let defined = cursor.defined(count)?;
+                            for (folder, defined) in streams.folders.iter_mut().zip(defined) {
+                                if defined {
+                                    cursor.skip(4)?;
+                                    folder.has_crc = true;
+                                }
+                            }
+                        }
+                        END => break,
+                        _ => return Err(invalid("unpack info")),
+                    }
+                }
+            }
+            SUBSTREAMS_INFO => substreams = Some(parse_substreams(cursor, &streams.folders)?),
+            END => break,
+            _ => return Err(invalid("streams info")),
+        }
+    }
+    // without substreams info each folder holds a single file
+    streams.substreams = substreams.unwrap_or_else(|| {
+        let sizes = streams
+            .folders
+            .iter()
+            .map(|folder| vec![folder.unpack_size]);
+        sizes.collect()
+    });
+    Ok(streams)
+}
+
+/// Parse a folder, also returning its number of output streams and which
+/// one of them is the final output, not bound to the input of another coder.
+fn parse_folder(cursor: &mut Cursor) -> io::Result<(Folder, (usize, usize))> {
+    let coder_count = cursor.count()?;
+    let (mut inputs, mut outputs) = (0, 0);
+    let mut coders = Vec::with_capacity(coder_count);
+    for _ in 0..coder_count {
+        let flags = cursor.u8()?;
+        let method = cursor.bytes((flags & 0x0f) as usize)?.to_vec();
+        if flags & 0x10 != 0 {
+            inputs += cursor.count()?;
+            outputs += cursor.count()?;
+        } else {
+            inputs += 1;
+            outputs += 1;
+        }
+        let properties = match flags & 0x20 {
+            0 => Vec::new(),
+            _ => {
+                let size = cursor.count()?;
+                cursor.bytes(size)?.to_vec()
+            }
+        };
+        if flags & 0x80 != 0 {
+            return Err(invalid("coder alternatives"));
+        }
+        coders.push(Coder { method, properties });
+    }
+
+    let bind_pairs = outputs.checked_sub(1).ok_or_else(|| invalid("folder"))?;
+    let mut bound_outputs = Vec::with_capacity(bind_pairs);
+    for _ in 0..bind_pairs {
+        let _input = cursor.number()?;
+        bound_outputs.push(cursor.count()?);
+    }
+    let packed_streams = inputs
+        .checked_sub(bind_pairs)
+        .ok_or_else(|| invalid("folder"))?;
+    if packed_streams > 1 {
+        for _ in 0..packed_streams {
+            cursor.number()?;
+        }
+    }
+    let unbound = (0..outputs)
+        .find(|output| !bound_outputs.contains(output))
+        .ok_or_else(|| invalid("folder"))?;
+
+    let folder = Folder {
+        coders,
+        packed_streams,
+        unpack_size: 0,
+        has_crc: false,
+    };
+    Ok((folder, (outputs, unbound)))
+}
+
+fn parse_substreams(cursor: &mut Cursor, folders: &[Folder]) -> io::Result<Vec<Vec<u64>>> {
+    let mut counts = vec![1; folders.len()];
+    let mut sizes = None;
+    loop {
+        match cursor.u8()? {
+            NUM_UNPACK_STREAM => {
+                for count in counts.iter_mut() {
+                    *count = cursor.count()?;
+                }
+            }
+            SIZE => {
+                let mut all = Vec::with_capacity(folders.len());
+                for (folder, &count) in folders.iter().zip(&counts) {
+                    // the last size is what's left of the folder
+                    let mut folder_sizes = Vec::with_capacity(count);
+                    for _ in 1..count {
+                        folder_sizes.push(cursor.number()?);
+                    }
+                    if count > 0 {
+                        let used: u64 = folder_sizes.iter().sum();
+                        let last = folder.unpack_size.checked_sub(used);
+                        folder_sizes.push(last.ok_or_else(|| invalid("substream sizes"))?);
+                    }
+                    all.push(folder_sizes);
+                }
+                sizes = Some(all);
+            }
+            CRC => {
+                // folders of one file whose checksum is already known are left out
+                let unknown = folders
+                    .iter()
+                    .zip(&counts)
+                    .filter(|&(folder, &count)| count != 1 || !folder.has_crc)
+                    .map(|(_, &count)| count)
+                    .sum();
+                cursor.skip_digests(unknown)?;
+            }
+            END => break,
+            _ => return Err(invalid("substreams info")),
+        }
+    }
+    match sizes {
+        Some(sizes) => Ok(sizes),
+        None if counts.iter().all(|&count| count <= 1) => Ok(folders
+            .iter()
+            .zip(counts)
+            .map(|(folder, count)| vec![folder.unpack_size; count])
+            .collect()),
+        None => Err(invalid("substream sizes")),
+    }
+}
+
+fn parse_files(cursor: &mut Cursor) -> io::Result<Vec<File>> {
+    let count = cursor.count()?;
+    let mut files = vec![File::default(); count];
+    let mut empty_streams = vec![false; count];
+    let mut empty_files = Vec::new();
+    let mut anti = Vec::new();
+
+    loop {
+        let property = cursor.u8()?;
+        if property == END {
+            break;
+        }
+        let size = cursor.count()?;
+        let mut data = Cursor::new(cursor.bytes(size)?);
+        let empty_count = empty_streams.iter().filter(|&&empty| empty).count();
+        match property {
+            EMPTY_STREAM => empty_streams = data.bits(count)?,
+            EMPTY_FILE => empty_files = data.bits(empty_count)?,
+            ANTI => anti = data.bits(empty_count)?,
+            NAME => {
+                if data.u8()? != 0 {
+                    return Err(invalid("external names"));
+                }
+                let units: Vec<u16> = data
+                    .bytes(data.remaining())?
+                    .chunks_exact(2)
+                    .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+                    .collect();
+                let names = units.split(|&unit| unit == 0);
+                for (file, name) in files.iter_mut().zip(names) {
+                    file.name = String::from_utf16_lossy(name);
+                }
+            }
+            MTIME => {
+                let defined = data.defined(count)?;
+                if data.u8()? != 0 {
+                    return Err(invalid("external times"));
+                }
+                for (file, defined) in files.iter_mut().zip(defined) {
+                    if defined {
+                        file.mtime = Some(data.u64()?);
+                    }
+                }
+            }
+            WIN_ATTRIBUTES => {
+                let defined = data.defined(count)?;
+                if data.u8()? != 0 {
+                    return Err(invalid("external attributes"));
+                }
+                for (file, defined) in files.iter_mut().zip(defined) {
+                    if defined {
+                        file.attributes = Some(data.u32()?);
+                    }
+                }
+            }
+            _ => {} // creation and access times, start positions, padding
+        }
+    }
+
+    // the empty file and anti flags only count the files without a stream
+    let mut empty_index = 0;
+    for (file, empty_stream) in files.iter_mut().zip(empty_streams) {
+        file.has_stream = !empty_stream;
+        if empty_stream {
+            let is_empty_file = empty_files.get(empty_index).copied().unwrap_or(false);
+            file.is_dir = !is_empty_file;
+            file.is_anti = anti.get(empty_index).copied().unwrap_or(false);
+            empty_index += 1;
+        }
+        if let Some(attributes) = file.attributes {
+            file.is_dir = match unix_mode(attributes) {
+                Some(mode) => mode & libc::S_IFMT == libc::S_IFDIR,
+                None => attributes & ATTRIBUTE_DIRECTORY != 0,
+            } && !file.has_stream;
+        }
+    }
+    Ok(files)
+}
+
+fn unix_mode(attributes: u32) -> Option<u32> {
+    (attributes & ATTRIBUTE_UNIX_EXTENSION != 0).then_some(attributes >> 16)
+}
+
+/// The decoded contents of a folder.
+fn folder_source(
+    source: &Arc<dyn ByteSource>,
+    streams: &Streams,
+    index: usize,
+) -> io::Result<Arc<dyn ByteSource>> {
+    let folder = &streams.folders[index];
+    let [coder] = &folder.coders[..] else {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            "7z folders with several coders, like filters or encryption",
+        ));
+    };
+
+    let first_stream: usize = streams.folders[..index]
+        .iter()
+        .map(|folder| folder.packed_streams)
+        .sum();
+    let offset = streams.pack_sizes[..first_stream.min(streams.pack_sizes.len())]
+        .iter()
+        .fold(SIGNATURE_HEADER_SIZE + streams.pack_position, |a, b| a + b);
+    let size = *streams
+        .pack_sizes
+        .get(first_stream)
+        .ok_or_else(|| invalid("pack info"))?;
+    let packed = Arc::new(SliceSource::new(Arc::clone(source), offset, size));
+
+    let properties = &coder.properties;
+    let codec = match coder.method[..] {
+        [0x00] => return Ok(packed),
+        [0x03, 0x01, 0x01] if properties.len() >= 5 => Codec::Lzma {
+            properties: properties[0],
+            dict_size: u32::from_le_bytes(properties[1..5].try_into().unwrap()),
+            size: folder.unpack_size,
+        },
+        [0x21] if !properties.is_empty() => {
+            let bits = properties[0] as u32;
+            let dict_size = match bits {
+                40.. => u32::MAX,
+                _ => (2 | (bits & 1)) << (bits / 2 + 11),
+            };
+            Codec::Lzma2 { dict_size }
+        }
+        [0x04, 0x01, 0x08] => Codec::Deflate,
+        [0x04, 0x02, 0x02] => Codec::Bzip2,
+        [0x04, 0xf7, 0x11, 0x01] => Codec::Zstd,
+        _ => {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("7z coder {:02x?}", coder.method),
+            ))
+        }
+    };
+    let decoded = DecompressedSource::with_size(packed, codec, folder.unpack_size);
+    Ok(Arc::new(CachedSource::new(Arc::new(decoded))))
+}
+
+fn into_entries(source: &Arc<dyn ByteSource>, streams: &Streams, files: Vec<File>) -> Vec<Entry> {
+    // the files with data take the substreams of the folders in order
+    let mut substreams = streams
+        .substreams
+        .iter()
+        .enumerate()
+        .flat_map(|(index, sizes)| {
+            sizes.iter().scan(0, move |offset, &size| {
+                let start = *offset;
+                *offset += size;
+                Some((index, start, size))
+            })
+        });
+    let mut folders: Vec<Option<Option<Arc<dyn ByteSource>>>> = vec![None; streams.folders.len()];
+
+    let mut entries = Vec::new();
+    for file in files {
+        let path = file.name.replace('\\', "/");
+        let content = if file.has_stream {
+            let Some((index, offset, size)) = substreams.next() else {
+                warn!("Skipping 7z file without data: {}", path);
+                continue;
+            };
+            let folder = folders[index].get_or_insert_with(|| {
+                folder_source(source, streams, index)
+                    .inspect_err(|e| warn!("Can't read 7z folder {}: {}", index, e))
+                    .ok()
+            });
+            let Some(folder) = folder else {
+                warn!("Skipping 7z file in an unreadable folder: {}", path);
+                continue;
+            };
+            Some(Arc::new(SliceSource::new(Arc::clone(folder), offset, size)) as Arc<dyn ByteSource>)
+        } else {
+            None
+        };
+        if file.is_anti {
+            continue; // marks a deletion in an update archive
+        }
+
+        let mode = file.attributes.and_then(unix_mode);
+        let is_link = mode.is_some_and(|mode| mode & libc::S_IFMT == libc::S_IFLNK);
+        let mut entry = match content {
+            _ if file.is_dir => Entry::directory(path),
+            Some(content) if is_link => {
+                // p7zip stores symbolic links as files holding the target
+                let mut target = vec![0; content.size().min(4096) as usize];
+                match content.read_full_at(0, &mut target) {
+                    Ok(n) => target.truncate(n),
+                    Err(e) => {
+                        warn!("Can't read 7z symlink {}: {}", path, e);
+                        continue;
+                    }
+                }
+                Entry::symlink(path, String::from_utf8_lossy(&target).into_owned())
+            }
+            Some(content) => Entry::file(path, content),
+            None => Entry::file(path, Arc::new(MemorySource::new(Vec::new()))),
+        };
+
+        match (mode, file.attributes) {
+            (Some(mode), _) if mode & 0o7777 != 0 => entry.mode = mode & 0o7777,
+            (_, Some(attributes)) if attributes & ATTRIBUTE_READ_ONLY != 0 => entry.mode &= !0o222,
+            _ => {}
+        }
+        if let Some(filetime) = file.mtime {
+            // in 100 nanosecond ticks
+            let seconds = (filetime / 10_000_000).saturating_sub(FILETIME_UNIX_EPOCH);
+            entry.mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(seconds);
+        }
+        entries.push(entry);
+    }
+    entries
+}
+
+struct Cursor<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() - self.position
+    }
+
+    fn bytes(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.remaining() < n {
+            return Err(invalid("truncated header"));
+        }
+        let bytes = &self.data[self.position..self.position + n];
+        self.position += n;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, n: usize) -> io::Result<()> {
+        self.bytes(n).map(|_| ())
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    /// A 7z number, whose first byte's leading one bits count the bytes that follow.
+    fn number(&mut self) -> io::Result<u64> {
+        let first = self.u8()?;
+        let mut value = 0;
+        for i in 0..8 {
+            let mask = 0x80 >> i;
+            if first & mask == 0 {
+                let high = (first & (mask.wrapping_sub(1))) as u64;
+                return Ok(value | (high << (8 * i)));
+            }
+            value |= (self.u8()? as u64) << (8 * i);
+        }
+        Ok(value)
+    }
+
+    /// A number of things, which can't be more than there are bytes left.
+    fn count(&mut self) -> io::Result<usize> {
+        let count = self.number()?;
+        match usize::try_from(count) {
+            Ok(count) if count <= self.data.len() * 8 => Ok(count),
+            _ => Err(invalid("count")),
+        }
+    }
+
+    /// A bit for each of `n` things, most significant bit first.
+    fn bits(&mut self, n: usize) -> io::Result<Vec<bool>> {
+        let bytes = self.bytes(n.div_ceil(8))?;
+        Ok((0..n)
+            .map(|i| bytes[i / 8] & (0x80 >> (i % 8)) != 0)
+            .collect())
+    }
+
+    /// Which of `n` things have a value, either all of them or as bits.
+    fn defined(&mut self, n: usize) -> io::Result<Vec<bool>> {
+        match self.u8()? {
+            0 => self.bits(n),
+            _ => Ok(vec![true; n]),
+        }
+    }
+
+    fn skip_digests(&mut self, n: usize) -> io::Result<()> {
+        let defined = self.defined(n)?;
+        self.skip(defined.iter().filter(|&&defined| defined).count() * 4)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::sync::Mutex;
+
+    /// The content of `docs/notes.txt`, long enough to span cache blocks.
+    pub(crate) fn notes() -> Vec<u8> {
+        (0..200_000u32)
+            .flat_map(|i| (i % 1000).to_le_bytes())
+            .collect()
+    }
+
+    fn number(value: u64) -> Vec<u8> {
+        match value {
+            0..0x80 => vec![value as u8],
+            _ => [&[0xff][..], &value.to_le_bytes()].concat(),
+        }
+    }
+
+    fn lzma(data: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let options = lzma_rust2::LzmaOptions::with_preset(1);
+        let mut encoder =
+            lzma_rust2::LzmaWriter::new_no_header(Vec::new(), &options, false).unwrap();
+        let mut properties = vec![encoder.props()];
+        properties.extend(options.dict_size.to_le_bytes());
+        encoder.write_all(data).unwrap();
+        (encoder.finish().unwrap(), properties)
+    }
+
+    fn lzma2(data: &[u8]) -> (Vec<u8>, u8) {
+        let options = lzma_rust2::Lzma2Options::with_preset(1);
+        let dict_size = options.lzma_options.dict_size;
+        let bits = (0..40)
+            .find(|bits| (2 | (bits & 1)) << (bits / 2 + 11) >= dict_size)
+            .unwrap();
+        let mut encoder = lzma_rust2::Lzma2Writer::new(Vec::new(), options);
+        encoder.write_all(data).unwrap();
+        (encoder.finish().unwrap(), bits as u8)
+    }
+
+    /// Streams info for packed streams at `position`, each its own folder.
+    fn streams_info(position: u64, folders: &[(&[u8], &[u8], u64, u64)]) -> Vec<u8> {
+        let mut info = vec![PACK_INFO];
+        info.extend(number(position));
+        info.extend(number(folders.len() as u64));
+        info.push(SIZE);
+        for &(_, _, packed_size, _) in folders {
+            info.extend(number(packed_size));
+        }
+        info.extend([END, UNPACK_INFO, FOLDER]);
+        info.extend(number(folders.len() as u64));
+        info.push(0);
+        for &(method, properties, _, _) in folders {
+            let flags = method.len() as u8 | if properties.is_empty() { 0 } else { 0x20 };
+            info.extend([1, flags]);
+            info.extend(method);
+            if !properties.is_empty() {
+                info.extend(number(properties.len() as u64));
+                info.extend(properties);
+            }
+        }
+        info.push(CODERS_UNPACK_SIZE);
+        for &(_, _, _, unpack_size) in folders {
+            info.extend(number(unpack_size));
+        }
+        info.push(END);
+        info
+    }
+
+    /// Build an archive with a solid LZMA2 block holding `docs/readme.txt`
+    /// and `docs/notes.txt`, `raw.bin` and a `link` stored as is, an LZMA
+    /// compressed `lzma.txt`, an empty `blank.txt` and an empty `docs/empty`.
+    pub(crate) fn seven_zip(encode_header: bool) -> Vec<u8> {
+        let readme = b"Hello from 7z!";
+        let solid = [&readme[..], &notes()].concat();
+        let (solid_packed, lzma2_bits) = lzma2(&solid);
+        let raw = b"raw data";
+        let stored = [&raw[..], b"docs/readme.txt"].concat();
+        let lzma_text = b"squeezed with lzma".repeat(10);
+        let (lzma_packed, lzma_properties) = lzma(&lzma_text);
+
+        let mut packed = [&solid_packed[..], &stored, &lzma_packed].concat();
+        let mut header = vec![HEADER, MAIN_STREAMS_INFO];
+        header.extend(streams_info(
+            0,
+            &[
+                (
+                    &[0x21],
+                    &[lzma2_bits],
+                    solid_packed.len() as u64,
+                    solid.len() as u64,
+                ),
+                (&[0x00], &[], stored.len() as u64, stored.len() as u64),
+                (
+                    &[0x03, 0x01, 0x01],
+                    &lzma_properties,
+                    lzma_packed.len() as u64,
+                    lzma_text.len() as u64,
+                ),
+            ],
+        ));
+        header.extend([SUBSTREAMS_INFO, NUM_UNPACK_STREAM, 2, 2, 1, SIZE]);
+        header.extend(number(readme.len() as u64));
+        header.extend(number(raw.len() as u64));
+        header.extend([END, END]);
+
+        const DIRECTORY: u32 = ATTRIBUTE_DIRECTORY | ATTRIBUTE_UNIX_EXTENSION | (0o40750 << 16);
+        const FILE: u32 = ATTRIBUTE_UNIX_EXTENSION | (0o100640 << 16);
+        const LINK: u32 = ATTRIBUTE_UNIX_EXTENSION | (0o120777 << 16);
+        let files: [(&str, u32); 8] = [
+            ("docs", DIRECTORY),
+            ("docs/readme.txt", FILE),
+            ("docs/notes.txt", FILE),
+            ("raw.bin", ATTRIBUTE_READ_ONLY),
+            ("link", LINK),
+            ("lzma.txt", FILE),
+            ("blank.txt", FILE),
+            ("docs/empty", DIRECTORY),
+        ];
+        let property = |header: &mut Vec<u8>, id: u8, data: &[u8]| {
+            header.push(id);
+            header.extend(number(data.len() as u64));
+            header.extend(data);
+        };
+        header.push(FILES_INFO);
+        header.extend(number(files.len() as u64));
+        property(&mut header, EMPTY_STREAM, &[0b1000_0011]);
+        property(&mut header, EMPTY_FILE, &[0b0100_0000]);
+        let mut names = vec![0];
+        for (name, _) in files {
+            names.extend(name.encode_utf16().chain([0]).flat_map(u16::to_le_bytes));
+        }
+        property(&mut header, NAME, &names);
+        let mut times = vec![1, 0];
+        let filetime = (1_700_000_000 + FILETIME_UNIX_EPOCH) * 10_000_000;
+        times.extend(files.iter().flat_map(|_| filetime.to_le_bytes()));
+        property(&mut header, MTIME, &times);
+        let mut attributes = vec![1, 0];
+        attributes.extend(files.iter().flat_map(|(_, a)| a.to_le_bytes()));
+        property(&mut header, WIN_ATTRIBUTES, &attributes);
+        header.extend([END, END]);
+
+        if encode_header {
+            let (header_packed, properties) = lzma(&header);
+            let position = packed.len() as u64;
+            let method: &[u8] = &[0x03, 0x01, 0x01];
+            let info = streams_info(
+                position,
+                &[(
+                    method,
+                    &properties,
+                    header_packed.len() as u64,
+                    header.len() as u64,
+                )],
+            );
+            packed.extend(header_packed);
+            header = [&[ENCODED_HEADER][..], &info, &[END]].concat();
+        }
+
+        let mut start_header = Vec::new();
+        start_header.extend((packed.len() as u64).to_le_bytes());
+        start_header.extend((header.len() as u64).to_le_bytes());
+        start_header.extend(crc32(&header).to_le_bytes());
+        let mut archive = SIGNATURE.to_vec();
+        archive.extend([0, 4]);
+        archive.extend(crc32(&start_header).to_le_bytes());
+        archive.extend(start_header);
+        archive.extend(packed);
+        archive.extend(header);
+        archive
+    }
+
+    fn read(entry: &Entry) -> io::Result<Vec<u8>> {
+        let content = entry.content.as_ref().unwrap();
+        let mut buf = vec![0; content.size() as usize];
+        content.read_full_at(0, &mut buf)?;
+        Ok(buf)
+    }
+
+    #[test]
+    fn test_entries() -> io::Result<()> {
+        for encode_header in [false, true] {
+            let archive: Arc<dyn ByteSource> =
+                Arc::new(MemorySource::new(seven_zip(encode_header)));
+            assert!(SevenZip.sniff(&archive));
+            let entries = SevenZip.entries(&archive, &Decoding::default())?;
+            let paths: Vec<_> = entries.iter().map(|e| e.path.as_str()).collect();
+            assert_eq!(
+                paths,
+                [
+                    "docs",
+                    "docs/readme.txt",
+                    "docs/notes.txt",
+                    "raw.bin",
+                    "link",
+                    "lzma.txt",
+                    "blank.txt",
+                    "docs/empty"
+                ]
+            );
+
+            assert!(entries[0].is_dir && entries[7].is_dir);
+            assert_eq!(entries[0].mode, 0o750);
+            assert_eq!(read(&entries[1])?, b"Hello from 7z!");
+            assert_eq!(read(&entries[2])?, notes());
+            assert_eq!(read(&entries[3])?, b"raw data");
+            assert_eq!(entries[3].mode, 0o444);
+            assert_eq!(entries[4].link_target.as_deref(), Some("docs/readme.txt"));
+            assert_eq!(read(&entries[5])?, b"squeezed with lzma".repeat(10));
+            assert_eq!(read(&entries[6])?, b"");
+            assert_eq!(entries[6].mode, 0o640);
+            assert_eq!(
+                entries[1].mtime,
+                SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+            );
+        }
+        Ok(())
+    }
+
+    /// An archive noting where each read of it starts.
+    struct Counting {
+        inner: MemorySource,
+        reads: Mutex<Vec<u64>>,
+    }
+
+    impl ByteSource for Counting {
+        fn size(&self) -> u64 {
+            self.inner.size()
+        }
+
+        fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+            self.reads.lock().unwrap().push(offset);
+            self.inner.read_at(offset, buf)
+        }
+    }
+
+    #[test]
+    fn test_solid_block_decoded_once() -> io::Result<()> {
+        let counting = Arc::new(Counting {
+            inner: MemorySource::new(seven_zip(false)),
+            reads: Mutex::new(Vec::new()),
+        });
+        let archive: Arc<dyn ByteSource> = counting.clone();
+        let entries = SevenZip.entries(&archive, &Decoding::default())?;
+        counting.reads.lock().unwrap().clear();
+
+        // the solid block is packed first, right after the signature header
+        for entry in [&entries[2], &entries[1], &entries[2]] {
+            read(entry)?;
+        }
+        let reads = counting.reads.lock().unwrap();
+        assert_eq!(reads.iter().filter(|&&offset| offset == 32).count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_numbers() -> io::Result<()> {
+        let data = [
+            0x7f, 0x81, 0x02, 0xc0, 0x34, 0x12, 0xff, 1, 2, 3, 4, 5, 6, 7, 8,
+        ];
+        let mut cursor = Cursor::new(&data);
+        assert_eq!(cursor.number()?, 0x7f);
+        assert_eq!(cursor.number()?, 0x102);
+        assert_eq!(cursor.number()?, 0x1234);
+        assert_eq!(cursor.number()?, 0x0807060504030201);
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupt_header() {
+        let mut archive = seven_zip(false);
+        let last = archive.len() - 3;
+        archive[last] ^= 0xff;
+        let archive: Arc<dyn ByteSource> = Arc::new(MemorySource::new(archive));
+        assert!(SevenZip.entries(&archive, &Decoding::default()).is_err());
+    }
+}
@@ -0,0 +1,923 @@
+use super::{has_extension, ArchiveFormat, Entry, Special};
+use crate::tartarfs::compression::Decoding;
+use crate::tartarfs::source::ByteSource;
+use flate2::read::ZlibDecoder;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Error, ErrorKind, Read};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tracing::debug;
+use xz2::read::XzDecoder;
+use xz2::stream::Stream;
+
+const MAGIC: &[u8; 4] = b"hsqs";
+const SUPERBLOCK_SIZE: usize = 96;
+const METADATA_SIZE: usize = 8192;
+const METADATA_UNCOMPRESSED: u16 = 0x8000;
+const DATA_UNCOMPRESSED: u32 = 1 << 24;
+const NO_TABLE: u64 = u64::MAX;
+const NO_FRAGMENT: u32 = u32::MAX;
+const NO_XATTRS: u32 = u32::MAX;
+/// Out of line xattr values are stored once and referred to from each key.
+const XATTR_OUT_OF_LINE: u16 = 0x100;
+
+/// The basic inode types; the extended ones are these plus 7.
+const DIRECTORY: u16 = 1;
+const FILE: u16 = 2;
+const SYMLINK: u16 = 3;
+const BLOCK_DEVICE: u16 = 4;
+const CHAR_DEVICE: u16 = 5;
+const FIFO: u16 = 6;
+const SOCKET: u16 = 7;
+
+/// A SquashFS 4.0 image, as made by `mksquashfs`.
+///
+/// Data blocks are decompressed on read, one block at a time; the tail ends
+/// of small files are packed together in shared fragment blocks.
+pub struct SquashFs;
+
+impl ArchiveFormat for SquashFs {
+    fn name(&self) -> &str {
+        "squashfs"
+    }
+
+    fn sniff(&self, source: &Arc<dyn ByteSource>) -> bool {
+        let mut magic = [0; 4];
+        matches!(source.read_full_at(0, &mut magic), Ok(4)) && magic == *MAGIC
+    }
+
+    fn matches_name(&self, name: &str) -> bool {
+        has_extension(name, &[".squashfs", ".sqfs", ".sqsh", ".sfs"])
+    }
+
+    fn entries(
+        &self,
+        source: &Arc<dyn ByteSource>,
+        _decoding: &Decoding,
+    ) -> io::Result<Vec<Entry>> {
+        let (image, root) = Image::open(source)?;
+        let root = image.read_inode(root)?;
+        let mut entries = Vec::new();
+        let mut visited = HashSet::new();
+        image.read_directory(&root, "", &mut entries, &mut visited)?;
+        Ok(entries)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Compressor {
+    Gzip,
+    Lzma,
+    Xz,
+    Lz4,
+    Zstd,
+}
+
+impl Compressor {
+    fn from_id(id: u16) -> io::Result<Self> {
+        match id {
+            1 => Ok(Compressor::Gzip),
+            2 => Ok(Compressor::Lzma),
+            4 => Ok(Compressor::Xz),
+            5 => Ok(Compressor::Lz4),
+            6 => Ok(Compressor::Zstd),
+            3 => Err(Error::new(ErrorKind::Unsupported, "lzo squashfs images")),
+            _ => Err(invalid("compressor")),
+        }
+    }
+
+    /// Decode one block, which holds at most `max_size` bytes.
+    fn decompress(self, data: &[u8], max_size: usize) -> io::Result<Vec<u8>> {
+        let mut output = Vec::with_capacity(max_size);
+        let limit = max_size as u64;
+        match self {
+            Compressor::Gzip => {
+                ZlibDecoder::new(data)
+                    .take(limit)
+                    .read_to_end(&mut output)?;
+            }
+            Compressor::Lzma => {
+                let stream = Stream::new_lzma_decoder(u64::MAX).map_err(Error::other)?;
+                XzDecoder::new_stream(data, stream)
+                    .take(limit)
+                    .read_to_end(&mut output)?;
+            }
+            Compressor::Xz => {
+                XzDecoder::new(data).take(limit).read_to_end(&mut output)?;
+            }
+            Compressor::Lz4 => {
+                output.resize(max_size, 0);
+                let size = lz4_flex::block::decompress_into(data, &mut output)
+                    .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+                output.truncate(size);
+            }
+            Compressor::Zstd => output = zstd::bulk::decompress(data, max_size)?,
+        }
+        Ok(output)
+    }
+}
+
+fn invalid(what: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("bad squashfs {what}"))
+}
+
+fn le16(data: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([data[at], data[at + 1]])
+}
+
+fn le32(data: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(data[at..at + 4].try_into().unwrap())
+}
+
+fn le64(data: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(data[at..at + 8].try_into().unwrap())
+}
+
+/// What file contents need to be read: the data blocks and fragments.
+struct Data {
+    source: Arc<dyn ByteSource>,
+    compressor: Compressor,
+    block_size: u64,
+    /// Where each fragment block is and its size, with the uncompressed bit.
+    fragments: Vec<(u64, u32)>,
+    /// The last fragment block decoded; the files sharing one tend to be read together.
+    fragment_cache: Mutex<Option<(u32, Arc<Vec<u8>>)>>,
+}
+
+impl Data {
+    /// Read a data or fragment block given its size word, with at most `max_size` bytes.
+    fn block(&self, position: u64, word: u32, max_size: usize) -> io::Result<Vec<u8>> {
+        let size = (word & !DATA_UNCOMPRESSED) as usize;
+        if size == 0 {
+            return Ok(vec![0; max_size]); // a hole in a sparse file
+        }
+        let mut stored = vec![0; size];
+        if self.source.read_full_at(position, &mut stored)? < size {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "truncated squashfs block",
+            ));
+        }
+        match word & DATA_UNCOMPRESSED {
+            0 => self.compressor.decompress(&stored, max_size),
+            _ => Ok(stored),
+        }
+    }
+
+    fn fragment(&self, index: u32) -> io::Result<Arc<Vec<u8>>> {
+        let mut cache = self
+            .fragment_cache
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if let Some((cached, data)) = cache.as_ref()
+            && *cached == index
+        {
+            return Ok(Arc::clone(data));
+        }
+        let &(position, word) = self
+            .fragments
+            .get(index as usize)
+            .ok_or_else(|| invalid("fragment index"))?;
+        let data = Arc::new(self.block(position, word, self.block_size as usize)?);
+        *cache = Some((index, Arc::clone(&data)));
+        Ok(data)
+    }
+}
+
+/// The contents of a regular file, decoded a block at a time.
+struct FileData {
+    data: Arc<Data>,
+    size: u64,
+    /// Where each block starts in the image, with its size word.
+    blocks: Vec<(u64, u32)>,
+    /// The fragment holding the tail end and the offset in it.
+    fragment: Option<(u32, usize)>,
+    /// The last block decoded, since reads tend to be sequential.
+    cache: Mutex<Option<(usize, Arc<Vec<u8>>)>>,
+}
+
+impl FileData {
+    fn block(&self, index: usize) -> io::Result<Arc<Vec<u8>>> {
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((cached, data)) = cache.as_ref()
+            && *cached == index
+        {
+            return Ok(Arc::clone(data));
+        }
+
+        let block_size = self.data.block_size;
+        let length = (self.size - index as u64 * block_size).min(block_size) as usize;
+        let data = match (self.blocks.get(index), self.fragment) {
+            (Some(&(position, word)), _) => self.data.block(position, word, length)?,
+            (None, Some((fragment, offset))) => {
+                let fragment = self.data.fragment(fragment)?;
+                fragment
+                    .get(offset..offset + length)
+                    .ok_or_else(|| invalid("fragment offset"))?
+                    .to_vec()
+            }
+            (None, None) => return Err(invalid("block list")),
+        };
+        if data.len() < length {
+            return Err(invalid("block size"));
+        }
+        let data = Arc::new(data);
+        *cache = Some((index, Arc::clone(&data)));
+        Ok(data)
+    }
+}
+
+impl ByteSource for FileData {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        if offset >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let block_size = self.data.block_size;
+        let block = self.block((offset / block_size) as usize)?;
+        let start = (offset % block_size) as usize;
+        let n = buf.len().min(block.len() - start);
+        buf[..n].copy_from_slice(&block[start..start + n]);
+        Ok(n)
+    }
+}
+
+/// Where to read next in a metadata table: the position of a block in the
+/// image, and an offset into its decoded data.
+#[derive(Clone, Copy)]
+struct Cursor {
+    block: u64,
+    offset: usize,
+}
+
+struct Image {
+    data: Arc<Data>,
+    inode_table: u64,
+    directory_table: u64,
+    ids: Vec<u32>,
+    /// Where the xattr keys and values start, and the `(reference, count)` of each set.
+    xattrs: Option<(u64, Vec<(u64, u32)>)>,
+    /// Decoded metadata blocks by position.
+    metadata: Mutex<HashMap<u64, Arc<MetadataBlock>>>,
+}
+
+struct MetadataBlock {
+    data: Vec<u8>,
+    /// Where the following block starts.
+    next: u64,
+}
+
+struct Inode {
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    mtime: u32,
+    xattrs: u32,
+    body: Body,
+}
+
+enum Body {
+    Directory {
+        block: u32,
+        offset: usize,
+        size: u32,
+    },
+    File(Arc<FileData>),
+    Symlink(String),
+    Special(Special),
+}
+
+impl Image {
+    /// Read the superblock and lookup tables, returning the root inode reference.
+    fn open(source: &Arc<dyn ByteSource>) -> io::Result<(Self, u64)> {
+        let mut superblock = [0; SUPERBLOCK_SIZE];
+        source.read_full_at(0, &mut superblock)?;
+        if &superblock[..4] != MAGIC {
+            return Err(invalid("magic"));
+        }
+        if le16(&superblock, 28) != 4 {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "squashfs images before version 4",
+            ));
+        }
+        let block_size = le32(&superblock, 12) as u64;
+        if !(4096..=1024 * 1024).contains(&block_size) || !block_size.is_power_of_two() {
+            return Err(invalid("block size"));
+        }
+
+        let mut image = Self {
+            data: Arc::new(Data {
+                source: Arc::clone(source),
+                compressor: Compressor::from_id(le16(&superblock, 20))?,
+                block_size,
+                fragments: Vec::new(),
+                fragment_cache: Mutex::new(None),
+            }),
+            inode_table: le64(&superblock, 64),
+            directory_table: le64(&superblock, 72),
+            ids: Vec::new(),
+            xattrs: None,
+            metadata: Mutex::new(HashMap::new()),
+        };
+
+        let id_count = le16(&superblock, 26) as usize;
+        let ids = image.read_table(le64(&superblock, 48), id_count * 4)?;
+        image.ids = ids.chunks_exact(4).map(|id| le32(id, 0)).collect();
+
+        let fragment_count = le32(&superblock, 16) as usize;
+        let fragment_table = le64(&superblock, 80);
+        if fragment_count > 0 && fragment_table != NO_TABLE {
+            let fragments = image.read_table(fragment_table, fragment_count * 16)?;
+            let fragments = fragments.chunks_exact(16);
+            let fragments = fragments.map(|entry| (le64(entry, 0), le32(entry, 8)));
+            Arc::get_mut(&mut image.data).unwrap().fragments = fragments.collect();
+        }
+
+        let xattr_table = le64(&superblock, 56);
+        if xattr_table != NO_TABLE {
+            let mut header = [0; 16];
+            source.read_full_at(xattr_table, &mut header)?;
+            let count = le32(&header, 8) as usize;
+            let sets = image.read_table(xattr_table + 16, count * 16)?;
+            let sets = sets
+                .chunks_exact(16)
+                .map(|set| (le64(set, 0), le32(set, 8)));
+            image.xattrs = Some((le64(&header, 0), sets.collect()));
+        }
+
+        Ok((image, le64(&superblock, 32)))
+    }
+
+    /// Decode the metadata block at `position`, or get it from the cache.
+    fn metadata_block(&self, position: u64) -> io::Result<Arc<MetadataBlock>> {
+        let cached = self.metadata.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(block) = cached.get(&position) {
+            return Ok(Arc::clone(block));
+        }
+        drop(cached);
+        let mut header = [0; 2];
+        if self.data.source.read_full_at(position, &mut header)? < 2 {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "truncated squashfs metadata",
+            ));
+        }
+        let header = u16::from_le_bytes(header);
+        let size = (header & !METADATA_UNCOMPRESSED) as usize;
+        let mut stored = vec![0; size];
+        self.data.source.read_full_at(position + 2, &mut stored)?;
+        let data = match header & METADATA_UNCOMPRESSED {
+            0 => self.data.compressor.decompress(&stored, METADATA_SIZE)?,
+            _ => stored,
+        };
+        let next = position + 2 + size as u64;
+        let block = Arc::new(MetadataBlock { data, next });
+        let mut metadata = self.metadata.lock().unwrap_or_else(|e| e.into_inner());
+        metadata.insert(position, Arc::clone(&block));
+        Ok(block)
+    }
+
+    /// Read `len` bytes of metadata, which may continue in the following blocks.
+    fn read_metadata(&self, cursor: &mut Cursor, len: usize) -> io::Result<Vec<u8>> {
+        // grown as the metadata turns out to be there, as `len` may be bogus
+        let mut output = Vec::with_capacity(len.min(METADATA_SIZE));
+        while output.len() < len {
+            let block = self.metadata_block(cursor.block)?;
+            let data = &block.data;
+            if cursor.offset >= data.len() {
+                if data.is_empty() {
+                    return Err(invalid("metadata block"));
+                }
+                *cursor = Cursor {
+                    block: block.next,
+                    offset: cursor.offset - data.len(),
+                };
+                continue;
+            }
+            let n = (len - output.len()).min(data.len() - cursor.offset);
+            output.extend_from_slice(&data[cursor.offset..cursor.offset + n]);
+            cursor.offset += n;
+        }
+        Ok(output)
+    }
+
+    /// Read a lookup table, which is `len` bytes of metadata found through
+    /// a list of block positions at `index`.
+    fn read_table(&self, index: u64, len: usize) -> io::Result<Vec<u8>> {
+        let mut positions = vec![0; len.div_ceil(METADATA_SIZE) * 8];
+        self.data.source.read_full_at(index, &mut positions)?;
+        let mut table = Vec::with_capacity(len);
+        for position in positions.chunks_exact(8) {
+            let mut cursor = Cursor {
+                block: le64(position, 0),
+                offset: 0,
+            };
+            let n = (len - table.len()).min(METADATA_SIZE);
+            table.extend(self.read_metadata(&mut cursor, n)?);
+        }
+        Ok(table)
+    }
+
+    fn read_inode(&self, reference: u64) -> io::Result<Inode> {
+        let mut cursor = Cursor {
+            block: self.inode_table + (reference >> 16),
+            offset: (reference & 0xffff) as usize,
+        };
+        let mut read = |len| self.read_metadata(&mut cursor, len);
+
+        let header = read(16)?;
+        let kind = le16(&header, 0);
+        let id = |index: u16| self.ids.get(index as usize).copied();
+        let (Some(uid), Some(gid)) = (id(le16(&header, 4)), id(le16(&header, 6))) else {
+            return Err(invalid("id index"));
+        };
+        let mut xattrs = NO_XATTRS;
+        let extended = kind > SOCKET;
+        let basic = if extended { kind - SOCKET } else { kind };
+        let body = match basic {
+            DIRECTORY if extended => {
+                let fields = read(24)?;
+                xattrs = le32(&fields, 20);
+                Body::Directory {
+                    block: le32(&fields, 8),
+                    offset: le16(&fields, 18) as usize,
+                    size: le32(&fields, 4),
+                }
+            }
+            DIRECTORY => {
+                let fields = read(16)?;
+                Body::Directory {
+                    block: le32(&fields, 0),
+                    offset: le16(&fields, 10) as usize,
+                    size: le16(&fields, 8) as u32,
+                }
+            }
+            FILE => {
+                let (blocks_start, size, fragment, fragment_offset) = if extended {
+                    let fields = read(40)?;
+                    xattrs = le32(&fields, 36);
+                    let (start, size) = (le64(&fields, 0), le64(&fields, 8));
+                    (start, size, le32(&fields, 28), le32(&fields, 32))
+                } else {
+                    let fields = read(16)?;
+                    let (start, size) = (le32(&fields, 0) as u64, le32(&fields, 12) as u64);
+                    (start, size, le32(&fields, 4), le32(&fields, 8))
+                };
+
+                let block_size = self.data.block_size;
+                let count = match fragment {
+                    NO_FRAGMENT => size.div_ceil(block_size),
+                    _ => size / block_size,
+                };
+                if count > (1 << 32) {
+                    return Err(invalid("file size"));
+                }
+                let words = read(count as usize * 4)?;
+                let mut position = blocks_start;
+                let mut blocks = Vec::with_capacity(words.len() / 4);
+                for word in words.chunks_exact(4).map(|word| le32(word, 0)) {
+                    blocks.push((position, word));
+                    position += (word & !DATA_UNCOMPRESSED) as u64;
+                }
+
+                Body::File(Arc::new(FileData {
+                    data: Arc::clone(&self.data),
+                    size,
+                    blocks,
+                    fragment: (fragment != NO_FRAGMENT)
+                        .then_some((fragment, fragment_offset as usize)),
+                    cache: Mutex::new(None),
+                }))
+            }
+            SYMLINK => {
+                let fields = read(8)?;
+                let target = read(le32(&fields, 4).min(4096) as usize)?;
+                if extended {
+                    xattrs = le32(&read(4)?, 0);
+                }
+                Body::Symlink(String::from_utf8_lossy(&target).into_owned())
+            }
+            BLOCK_DEVICE | CHAR_DEVICE => {
+                let fields = read(if extended { 12 } else { 8 })?;
+                if extended {
+                    xattrs = le32(&fields, 8);
+                }
+                // the kernel's encoding, with the low minor bits in the low byte
+                let rdev = le32(&fields, 4);
+                let (major, minor) = (
+                    (rdev >> 8) & 0xfff,
+                    (rdev & 0xff) | ((rdev >> 12) & 0xfff00),
+                );
+                Body::Special(match basic {
+                    BLOCK_DEVICE => Special::BlockDevice { major, minor },
+                    _ => Special::CharDevice { major, minor },
+                })
+            }
+            FIFO | SOCKET => {
+                let fields = read(if extended { 8 } else { 4 })?;
+                if extended {
+                    xattrs = le32(&fields, 4);
+                }
+                Body::Special(match basic {
+                    FIFO => Special::Fifo,
+                    _ => Special::Socket,
+                })
+            }
+            _ => return Err(invalid("inode type")),
+        };
+        Ok(Inode {
+            mode: le16(&header, 2) as u32,
+            uid,
+            gid,
+            mtime: le32(&header, 8),
+            xattrs,
+            body,
+        })
+    }
+
+    /// The `(name, inode reference)` of each entry in a directory.
+    fn read_listing(&self, block: u32, offset: usize, size: u32) -> io::Result<Vec<(String, u64)>> {
+        let mut cursor = Cursor {
+            block: self.directory_table + block as u64,
+            offset,
+        };
+        // the size counts the `.` and `..` entries, which aren't stored
+        let listing = self.read_metadata(&mut cursor, size.saturating_sub(3) as usize)?;
+
+        let mut names = Vec::new();
+        let mut position = 0;
+        // a header with the inode block for up to 256 entries
+        while let Some(header) = listing.get(position..position + 12) {
+            position += 12;
+            let (count, start) = (le32(header, 0) + 1, le32(header, 4) as u64);
+            for _ in 0..count {
+                let Some(entry) = listing.get(position..position + 8) else {
+                    return Err(invalid("directory entry"));
+                };
+                let name_size = le16(entry, 6) as usize + 1;
+                let Some(name) = listing.get(position + 8..position + 8 + name_size) else {
+                    return Err(invalid("directory entry"));
+                };
+                let reference = (start << 16) | le16(entry, 0) as u64;
+                names.push((String::from_utf8_lossy(name).into_owned(), reference));
+                position += 8 + name_size;
+            }
+        }
+        Ok(names)
+    }
+
+    fn read_directory(
+        &self,
+        directory: &Inode,
+        prefix: &str,
+        entries: &mut Vec<Entry>,
+        visited: &mut HashSet<(u32, usize)>,
+    ) -> io::Result<()> {
+        let Body::Directory {
+            block,
+            offset,
+            size,
+        } = directory.body
+        else {
+            return Err(invalid("directory inode"));
+        };
+        if !visited.insert((block, offset)) {
+            return Ok(()); // a loop in a broken image
+        }
+
+        for (name, reference) in self.read_listing(block, offset, size)? {
+            if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+                debug!("Skipping squashfs entry named {:?}", name);
+                continue;
+            }
+            let path = match prefix {
+                "" => name,
+                prefix => format!("{prefix}/{name}"),
+            };
+            let inode = self.read_inode(reference)?;
+
+            let mut entry = match &inode.body {
+                Body::Directory { .. } => Entry::directory(path.clone()),
+                Body::File(data) => Entry::file(path.clone(), Arc::clone(data) as _),
+                Body::Symlink(target) => Entry::symlink(path.clone(), target.clone()),
+                Body::Special(special) => Entry::special(path.clone(), *special),
+            };
+            entry.mode = inode.mode & 0o7777;
+            entry.uid = inode.uid;
+            entry.gid = inode.gid;
+            entry.mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(inode.mtime as u64);
+            entry.xattrs = self.read_xattrs(inode.xattrs)?;
+            entries.push(entry);
+
+            if matches!(inode.body, Body::Directory { .. }) {
+                self.read_directory(&inode, &path, entries, visited)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The extended attributes of set `index`, with full names like `user.comment`.
+    fn read_xattrs(&self, index: u32) -> io::Result<Vec<(String, Vec<u8>)>> {
+        let Some((start, sets)) = &self.xattrs else {
+            return Ok(Vec::new());
+        };
+        if index == NO_XATTRS {
+            return Ok(Vec::new());
+        }
+        let &(reference, count) = sets
+            .get(index as usize)
+            .ok_or_else(|| invalid("xattr index"))?;
+        let at = |reference: u64| Cursor {
+            block: start + (reference >> 16),
+            offset: (reference & 0xffff) as usize,
+        };
+
+        let mut cursor = at(reference);
+        let mut xattrs = Vec::new();
+        for _ in 0..count {
+            let key = self.read_metadata(&mut cursor, 4)?;
+            let (kind, name_size) = (le16(&key, 0), le16(&key, 2) as usize);
+            let name = self.read_metadata(&mut cursor, name_size)?;
+            let size = le32(&self.read_metadata(&mut cursor, 4)?, 0) as usize;
+            let mut value = self.read_metadata(&mut cursor, size.min(1 << 16))?;
+            if kind & XATTR_OUT_OF_LINE != 0 {
+                // the value is a reference to where the value is
+                if value.len() < 8 {
+                    return Err(invalid("xattr value reference"));
+                }
+                let mut value_cursor = at(le64(&value, 0));
+                let size = le32(&self.read_metadata(&mut value_cursor, 4)?, 0) as usize;
+                value = self.read_metadata(&mut value_cursor, size.min(1 << 16))?;
+            }
+
+            let prefix = match kind & 0xff {
+                0 => "user.",
+                1 => "trusted.",
+                2 => "security.",
+                _ => {
+                    debug!("Skipping squashfs xattr of unknown type {}", kind);
+                    continue;
+                }
+            };
+            let name = format!("{prefix}{}", String::from_utf8_lossy(&name));
+            xattrs.push((name, value));
+        }
+        Ok(xattrs)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::tartarfs::source::MemorySource;
+    use flate2::write::ZlibEncoder;
+    use std::io::Write;
+
+    pub(crate) const GZIP: u16 = 1;
+    const XZ: u16 = 4;
+    const LZ4: u16 = 5;
+    const ZSTD: u16 = 6;
+    const BLOCK_SIZE: usize = 4096;
+
+    fn compress(compressor: u16, data: &[u8]) -> Vec<u8> {
+        match compressor {
+            GZIP => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            XZ => {
+                let mut encoder = xz2::write::XzEncoder::new(Vec::new(), 6);
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            LZ4 => lz4_flex::block::compress(data),
+            ZSTD => zstd::bulk::compress(data, 3).unwrap(),
+            _ => unreachable!(),
+        }
+    }
+
+    fn metadata(compressor: Option<u16>, data: &[u8]) -> Vec<u8> {
+        let (header, mut block) = match compressor {
+            Some(compressor) => {
+                let block = compress(compressor, data);
+                (block.len() as u16, block)
+            }
+            None => (data.len() as u16 | METADATA_UNCOMPRESSED, data.to_vec()),
+        };
+        block.splice(0..0, header.to_le_bytes());
+        block
+    }
+
+    fn fields(buf: &mut Vec<u8>, values: &[(u64, usize)]) {
+        for &(value, size) in values {
+            buf.extend_from_slice(&value.to_le_bytes()[..size]);
+        }
+    }
+
+    /// The contents of `big.bin` in the test image, a block and a bit.
+    pub(crate) fn big() -> Vec<u8> {
+        (0..BLOCK_SIZE + 100).map(|i| (i % 251) as u8).collect()
+    }
+
+    /// Build an image holding `big.bin`, `small.txt`, a `link` to it,
+    /// a `null` character device and `sub/x` with a `user.comment` xattr.
+    pub(crate) fn squashfs(compressor: u16) -> Vec<u8> {
+        image(compressor, 1)
+    }
+
+    /// Like [`squashfs`], but with `sub/x` claiming to be `x_size` long.
+    fn image(compressor: u16, x_size: u64) -> Vec<u8> {
+        let mut image = vec![0; SUPERBLOCK_SIZE];
+        let big = big();
+        let block = compress(compressor, &big[..BLOCK_SIZE]);
+        let big_start = image.len() as u64;
+        image.extend(&block);
+        // the tail of big.bin, then small.txt and sub/x share a fragment
+        let fragment = [&big[BLOCK_SIZE..], b"hello", b"X"].concat();
+        let fragment = compress(compressor, &fragment);
+        let fragment_start = image.len() as u64;
+        image.extend(&fragment);
+
+        let mtime = 1_700_000_000;
+        let header = |inodes: &mut Vec<u8>, kind: u64, mode: u64, number: u64| {
+            fields(inodes, &[(kind, 2), (mode, 2), (0, 2), (1, 2)]);
+            fields(inodes, &[(mtime, 4), (number, 4)]);
+        };
+        let mut inodes = Vec::new();
+        let big_inode = inodes.len() as u64;
+        header(&mut inodes, 2, 0o644, 2);
+        fields(
+            &mut inodes,
+            &[(big_start, 4), (0, 4), (0, 4), (big.len() as u64, 4)],
+        );
+        fields(&mut inodes, &[(block.len() as u64, 4)]);
+        let small_inode = inodes.len() as u64;
+        header(&mut inodes, 2, 0o644, 3);
+        fields(&mut inodes, &[(0, 4), (0, 4), (100, 4), (5, 4)]);
+        let link_inode = inodes.len() as u64;
+        header(&mut inodes, 3, 0o777, 4);
+        fields(&mut inodes, &[(1, 4), (9, 4)]);
+        inodes.extend(b"small.txt");
+        let null_inode = inodes.len() as u64;
+        header(&mut inodes, 5, 0o666, 5);
+        fields(&mut inodes, &[(1, 4), (0x103, 4)]);
+        let x_inode = inodes.len() as u64;
+        header(&mut inodes, 9, 0o600, 7);
+        fields(&mut inodes, &[(0, 8), (x_size, 8), (0, 8)]);
+        fields(&mut inodes, &[(1, 4), (0, 4), (105, 4), (0, 4)]);
+
+        let listing = |directories: &mut Vec<u8>, first: u64, names: &[(&str, u64, u64)]| {
+            fields(
+                directories,
+                &[(names.len() as u64 - 1, 4), (0, 4), (first, 4)],
+            );
+            for (i, &(name, inode, kind)) in names.iter().enumerate() {
+                fields(directories, &[(inode, 2), (i as u64, 2), (kind, 2)]);
+                fields(directories, &[(name.len() as u64 - 1, 2)]);
+                directories.extend(name.bytes());
+            }
+        };
+        let mut directories = Vec::new();
+        listing(&mut directories, 7, &[("x", x_inode, 2)]);
+        let sub_size = directories.len() as u64 + 3;
+        let sub_inode = inodes.len() as u64;
+        header(&mut inodes, 1, 0o755, 6);
+        fields(
+            &mut inodes,
+            &[(0, 4), (2, 4), (sub_size, 2), (0, 2), (1, 4)],
+        );
+        let root_listing = directories.len() as u64;
+        let names = [
+            ("big.bin", big_inode, 2),
+            ("link", link_inode, 3),
+            ("null", null_inode, 5),
+            ("small.txt", small_inode, 2),
+            ("sub", sub_inode, 1),
+        ];
+        listing(&mut directories, 2, &names);
+        let root_size = directories.len() as u64 - root_listing + 3;
+        let root_inode = inodes.len() as u64;
+        header(&mut inodes, 1, 0o755, 1);
+        fields(&mut inodes, &[(0, 4), (3, 4), (root_size, 2)]);
+        fields(&mut inodes, &[(root_listing, 2), (8, 4)]);
+
+        let inode_table = image.len() as u64;
+        image.extend(metadata(Some(compressor), &inodes));
+        let directory_table = image.len() as u64;
+        image.extend(metadata(None, &directories));
+
+        let table = |image: &mut Vec<u8>, data: &[u8]| {
+            let block = image.len() as u64;
+            image.extend(metadata(None, data));
+            let index = image.len() as u64;
+            image.extend(block.to_le_bytes());
+            index
+        };
+        let mut fragments = Vec::new();
+        fields(
+            &mut fragments,
+            &[(fragment_start, 8), (fragment.len() as u64, 4), (0, 4)],
+        );
+        let fragment_table = table(&mut image, &fragments);
+        let mut ids = Vec::new();
+        fields(&mut ids, &[(1000, 4), (100, 4)]);
+        let id_table = table(&mut image, &ids);
+
+        let xattr_start = image.len() as u64;
+        let mut pairs = Vec::new();
+        fields(&mut pairs, &[(0, 2), (7, 2)]);
+        pairs.extend(b"comment");
+        fields(&mut pairs, &[(2, 4)]);
+        pairs.extend(b"hi");
+        image.extend(metadata(None, &pairs));
+        let mut sets = Vec::new();
+        fields(&mut sets, &[(0, 8), (1, 4), (pairs.len() as u64, 4)]);
+        let sets_block = image.len() as u64;
+        image.extend(metadata(None, &sets));
+        let xattr_table = image.len() as u64;
+        fields(
+            &mut image,
+            &[(xattr_start, 8), (1, 4), (0, 4), (sets_block, 8)],
+        );
+
+        let mut superblock = MAGIC.to_vec();
+        fields(
+            &mut superblock,
+            &[(7, 4), (mtime, 4), (BLOCK_SIZE as u64, 4)],
+        );
+        fields(&mut superblock, &[(1, 4), (compressor as u64, 2), (12, 2)]);
+        fields(&mut superblock, &[(0, 2), (2, 2), (4, 2), (0, 2)]);
+        fields(&mut superblock, &[(root_inode, 8), (image.len() as u64, 8)]);
+        fields(&mut superblock, &[(id_table, 8), (xattr_table, 8)]);
+        fields(&mut superblock, &[(inode_table, 8), (directory_table, 8)]);
+        fields(&mut superblock, &[(fragment_table, 8), (NO_TABLE, 8)]);
+        image[..SUPERBLOCK_SIZE].copy_from_slice(&superblock);
+        image.resize(image.len().next_multiple_of(BLOCK_SIZE), 0);
+        image
+    }
+
+    fn read(entry: &Entry) -> io::Result<Vec<u8>> {
+        let content = entry.content.as_ref().unwrap();
+        let mut buf = vec![0; content.size() as usize];
+        content.read_full_at(0, &mut buf)?;
+        Ok(buf)
+    }
+
+    #[test]
+    fn test_compressors() -> io::Result<()> {
+        for compressor in [GZIP, XZ, LZ4, ZSTD] {
+            let source: Arc<dyn ByteSource> = Arc::new(MemorySource::new(squashfs(compressor)));
+            assert!(SquashFs.sniff(&source));
+            let entries = SquashFs.entries(&source, &Decoding::default())?;
+            let paths: Vec<_> = entries.iter().map(|e| e.path.as_str()).collect();
+            assert_eq!(
+                paths,
+                ["big.bin", "link", "null", "small.txt", "sub", "sub/x"]
+            );
+
+            assert_eq!(read(&entries[0])?, big());
+            assert_eq!(read(&entries[3])?, b"hello");
+            assert_eq!(read(&entries[5])?, b"X");
+            assert_eq!(entries[1].link_target.as_deref(), Some("small.txt"));
+            assert_eq!(
+                entries[2].special,
+                Some(Special::CharDevice { major: 1, minor: 3 })
+            );
+            assert!(entries[4].is_dir);
+            assert_eq!(entries[5].mode, 0o600);
+            assert_eq!((entries[5].uid, entries[5].gid), (1000, 100));
+            assert_eq!(
+                entries[5].xattrs,
+                [("user.comment".to_string(), b"hi".to_vec())]
+            );
+            assert!(entries[0].xattrs.is_empty());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_across_blocks() -> io::Result<()> {
+        let source: Arc<dyn ByteSource> = Arc::new(MemorySource::new(squashfs(GZIP)));
+        let entries = SquashFs.entries(&source, &Decoding::default())?;
+        let content = entries[0].content.as_ref().unwrap();
+        let mut buf = [0; 20];
+        assert_eq!(content.read_full_at(BLOCK_SIZE as u64 - 10, &mut buf)?, 20);
+        assert_eq!(buf[..], big()[BLOCK_SIZE - 10..BLOCK_SIZE + 10]);
+        assert_eq!(content.read_full_at(BLOCK_SIZE as u64 + 90, &mut buf)?, 10);
+        Ok(())
+    }
+
+    #[test]
+    fn test_huge_block_list() {
+        // 2^32 blocks, whose list would take 16 GiB
+        let image = image(GZIP, (BLOCK_SIZE as u64) << 32);
+        let source: Arc<dyn ByteSource> = Arc::new(MemorySource::new(image));
+        // there isn't that much metadata, so reading it fails before long
+        assert!(SquashFs.entries(&source, &Decoding::default()).is_err());
+    }
+}
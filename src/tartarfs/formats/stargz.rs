@@ -0,0 +1,563 @@
+use super::{parse_timestamp, ArchiveFormat, Entry, Special};
+use crate::tartarfs::compression::{Codec, Decoding, DecompressedSource};
+use crate::tartarfs::digest::{Algorithm, Digest, VerifiedSource};
+use crate::tartarfs::source::{
+    ByteSource, ConcatSource, MemorySource, SliceSource, SourceReader, ZeroSource,
+};
+use flate2::bufread::GzDecoder;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufReader, Error, ErrorKind, Read};
+use std::sync::Arc;
+use tracing::debug;
+
+/// The gzip member at the end of an eStargz layer, with the TOC offset in
+/// an extra field; legacy stargz has no subfield header, making it 47 bytes.
+const GZIP_FOOTER_SIZE: u64 = 51;
+const LEGACY_GZIP_FOOTER_SIZE: u64 = 47;
+/// The TOC entry in the tarball that the TOC offset points at.
+const TOC_NAME: &str = "stargz.index.json";
+/// The end of a zstd:chunked layer, the contents of a skippable frame.
+const ZSTD_FOOTER_SIZE: u64 = 64;
+const ZSTD_FOOTER_MAGIC: &[u8; 8] = b"GNUlInUx";
+/// The zstd:chunked manifest type of a TOC in the eStargz layout.
+const MANIFEST_TYPE_TOC: u64 = 1;
+/// A TOC larger than this is surely corrupt.
+const MAX_TOC_SIZE: u64 = 256 * 1024 * 1024;
+/// Files the eStargz writer adds to mark the end of prioritized files.
+const LANDMARKS: [&str; 2] = [".prefetch.landmark", ".no.prefetch.landmark"];
+
+/// A container layer in the eStargz or zstd:chunked format: a compressed
+/// tarball with each file in compressed chunks of its own and a table of
+/// contents listing them, found through the footer.
+///
+/// Only the TOC is read when indexing, and reading a file only decompresses
+/// its own chunks, each checked against the chunk digest in the TOC.
+/// Without the footer, the layer is still an ordinary compressed tarball.
+pub struct Stargz;
+
+impl ArchiveFormat for Stargz {
+    fn name(&self) -> &str {
+        "stargz"
+    }
+
+    fn sniff(&self, source: &Arc<dyn ByteSource>) -> bool {
+        matches!(Layout::detect(source.as_ref()), Ok(Some(_)))
+    }
+
+    fn entries(
+        &self,
+        source: &Arc<dyn ByteSource>,
+        _decoding: &Decoding,
+    ) -> io::Result<Vec<Entry>> {
+        let Some(layout) = Layout::detect(source.as_ref())? else {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "no eStargz or zstd:chunked footer",
+            ));
+        };
+        let toc: Toc = serde_json::from_slice(&layout.read_toc(source)?)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        layout.entries(source, toc)
+    }
+}
+
+/// Where the chunks and the TOC are, going by the footer.
+enum Layout {
+    Gzip {
+        toc_offset: u64,
+    },
+    Zstd {
+        offset: u64,
+        compressed: u64,
+        uncompressed: u64,
+    },
+}
+
+impl Layout {
+    fn detect(source: &dyn ByteSource) -> io::Result<Option<Self>> {
+        let mut magic = [0; 4];
+        source.read_full_at(0, &mut magic)?;
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            return Ok(gzip_toc_offset(source)?.map(|toc_offset| Layout::Gzip { toc_offset }));
+        }
+        if magic != [0x28, 0xb5, 0x2f, 0xfd] || source.size() < ZSTD_FOOTER_SIZE {
+            return Ok(None);
+        }
+
+        let mut footer = [0; ZSTD_FOOTER_SIZE as usize];
+        source.read_full_at(source.size() - ZSTD_FOOTER_SIZE, &mut footer)?;
+        let field = |i: usize| u64::from_le_bytes(footer[i * 8..i * 8 + 8].try_into().unwrap());
+        if &footer[56..] != ZSTD_FOOTER_MAGIC || field(3) != MANIFEST_TYPE_TOC {
+            return Ok(None);
+        }
+        Ok(Some(Layout::Zstd {
+            offset: field(0),
+            compressed: field(1),
+            uncompressed: field(2),
+        }))
+    }
+
+    fn read_toc(&self, source: &Arc<dyn ByteSource>) -> io::Result<Vec<u8>> {
+        match *self {
+            Layout::Gzip { toc_offset } => {
+                let toc_member = SliceSource::new(
+                    Arc::clone(source),
+                    toc_offset,
+                    source.size().saturating_sub(toc_offset),
+                );
+                let reader = BufReader::new(SourceReader::new(Arc::new(toc_member)));
+                let mut archive = tar::Archive::new(GzDecoder::new(reader));
+                for entry in archive.entries()? {
+                    let entry = entry?;
+                    if entry.path()?.to_str() == Some(TOC_NAME) {
+                        let mut toc = Vec::new();
+                        entry.take(MAX_TOC_SIZE).read_to_end(&mut toc)?;
+                        return Ok(toc);
+                    }
+                }
+                Err(Error::new(ErrorKind::InvalidData, "no eStargz TOC"))
+            }
+            Layout::Zstd {
+                offset,
+                compressed,
+                uncompressed,
+            } => {
+                if compressed > MAX_TOC_SIZE || uncompressed > MAX_TOC_SIZE {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "zstd:chunked TOC too large",
+                    ));
+                }
+                let mut manifest = vec![0; compressed as usize];
+                if source.read_full_at(offset, &mut manifest)? < manifest.len() {
+                    return Err(Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "truncated zstd:chunked TOC",
+                    ));
+                }
+                zstd::bulk::decompress(&manifest, uncompressed as usize)
+            }
+        }
+    }
+
+    /// Where the chunks end, for chunks whose compressed size isn't recorded.
+    fn chunks_end(&self) -> u64 {
+        match *self {
+            Layout::Gzip { toc_offset } => toc_offset,
+            Layout::Zstd { offset, .. } => offset,
+        }
+    }
+
+    fn entries(&self, source: &Arc<dyn ByteSource>, toc: Toc) -> io::Result<Vec<Entry>> {
+        // regular files and their chunks, which follow them in the TOC
+        let mut files: Vec<File> = Vec::new();
+        let mut file_indexes: HashMap<String, usize> = HashMap::new();
+        let mut hardlinks = Vec::new();
+        for item in toc.entries {
+            if LANDMARKS.contains(&item.name.as_str()) {
+                continue;
+            }
+            if item.kind == "chunk" {
+                let Some(&index) = file_indexes.get(&item.name) else {
+                    debug!("Skipping chunk of unknown file {}", item.name);
+                    continue;
+                };
+                let chunk = self.chunk(source, &item, files[index].size)?;
+                files[index].chunks.push(chunk);
+                continue;
+            }
+
+            let mut entry = match item.kind.as_str() {
+                "dir" => Entry::directory(item.name.clone()),
+                "reg" => Entry::file(item.name.clone(), Arc::new(MemorySource::new(Vec::new()))),
+                "symlink" => Entry::symlink(item.name.clone(), item.link_name.clone()),
+                "hardlink" => {
+                    hardlinks.push((item.name.clone(), item.link_name.clone()));
+                    Entry::file(item.name.clone(), Arc::new(MemorySource::new(Vec::new())))
+                }
+                "char" | "block" | "fifo" => {
+                    let (major, minor) = (item.dev_major, item.dev_minor);
+                    let special = match item.kind.as_str() {
+                        "char" => Special::CharDevice { major, minor },
+                        "block" => Special::BlockDevice { major, minor },
+                        _ => Special::Fifo,
+                    };
+                    Entry::special(item.name.clone(), special)
+                }
+                kind => {
+                    debug!("Skipping {} of type {}", item.name, kind);
+                    continue;
+                }
+            };
+            entry.mode = item.mode;
+            entry.uid = item.uid;
+            entry.gid = item.gid;
+            if let Some(mtime) = parse_timestamp(&item.modtime) {
+                entry.mtime = mtime;
+            }
+            entry.digest = sha256(&item.digest);
+            entry.xattrs = item
+                .xattrs
+                .iter()
+                .filter_map(|(name, value)| Some((name.clone(), base64_decode(value)?)))
+                .collect();
+
+            let mut chunks = Vec::new();
+            if item.kind == "reg" && item.size > 0 {
+                chunks.push(self.chunk(source, &item, item.size)?);
+            }
+            file_indexes.insert(item.name, files.len());
+            files.push(File {
+                entry,
+                size: item.size,
+                chunks,
+            });
+        }
+
+        let mut entries: Vec<Entry> = files
+            .into_iter()
+            .map(
+                |File {
+                     mut entry,
+                     mut chunks,
+                     ..
+                 }| {
+                    if !chunks.is_empty() {
+                        entry.content = Some(match chunks.len() {
+                            1 => chunks.remove(0),
+                            _ => Arc::new(ConcatSource::new(chunks)),
+                        });
+                    }
+                    entry
+                },
+            )
+            .collect();
+        let contents: HashMap<&str, _> = entries
+            .iter()
+            .filter_map(|entry| Some((entry.path.as_str(), entry.content.clone()?)))
+            .collect();
+        let hardlinks: Vec<_> = hardlinks
+            .into_iter()
+            .filter_map(|(path, target)| Some((path, contents.get(target.as_str())?.clone())))
+            .collect();
+        for (path, content) in hardlinks {
+            if let Some(entry) = entries.iter_mut().find(|entry| entry.path == path) {
+                entry.content = Some(content);
+            }
+        }
+        Ok(entries)
+    }
+
+    /// One chunk of a file, decompressed on read and checked against its digest.
+    fn chunk(
+        &self,
+        source: &Arc<dyn ByteSource>,
+        item: &TocEntry,
+        file_size: u64,
+    ) -> io::Result<Arc<dyn ByteSource>> {
+        // the rest of the file if the chunk size is missing
+        let rest = file_size.saturating_sub(item.chunk_offset);
+        let size = match item.chunk_size {
+            0 => rest,
+            size => size.min(rest),
+        };
+        if item.chunk_type == "zeros" {
+            return Ok(Arc::new(ZeroSource::new(size)));
+        }
+        let end = match item.end_offset {
+            0 => self.chunks_end(),
+            end => end,
+        };
+        if item.offset > end {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("chunk of {} past the end", item.name),
+            ));
+        }
+        let codec = match self {
+            Layout::Gzip { .. } => Codec::Gzip,
+            Layout::Zstd { .. } => Codec::Zstd,
+        };
+        let compressed = SliceSource::new(Arc::clone(source), item.offset, end - item.offset);
+        let decompressed = DecompressedSource::new(Arc::new(compressed), codec);
+        let chunk = Arc::new(SliceSource::new(
+            Arc::new(decompressed),
+            item.inner_offset,
+            size,
+        ));
+        Ok(match sha256(&item.chunk_digest) {
+            Some(digest) => Arc::new(VerifiedSource::new(chunk, digest)),
+            None => chunk,
+        })
+    }
+}
+
+/// A file from the TOC, collecting its chunks.
+struct File {
+    entry: Entry,
+    size: u64,
+    chunks: Vec<Arc<dyn ByteSource>>,
+}
+
+/// Find the TOC offset in the footer of an eStargz layer.
+fn gzip_toc_offset(source: &dyn ByteSource) -> io::Result<Option<u64>> {
+    for footer_size in [GZIP_FOOTER_SIZE, LEGACY_GZIP_FOOTER_SIZE] {
+        let Some(start) = source.size().checked_sub(footer_size) else {
+            continue;
+        };
+        let mut footer = vec![0; footer_size as usize];
+        source.read_full_at(start, &mut footer)?;
+        // a gzip header with the FEXTRA flag, then the extra field length
+        if footer[..3] != [0x1f, 0x8b, 8] || footer[3] & 4 == 0 {
+            continue;
+        }
+        let extra_size = u16::from_le_bytes([footer[10], footer[11]]) as usize;
+        let extra = match footer_size {
+            GZIP_FOOTER_SIZE if extra_size == 26 && footer[12..14] == *b"SG" => &footer[16..38],
+            LEGACY_GZIP_FOOTER_SIZE if extra_size == 22 => &footer[12..34],
+            _ => continue,
+        };
+        if &extra[16..] != b"STARGZ" {
+            continue;
+        }
+        let offset = std::str::from_utf8(&extra[..16])
+            .ok()
+            .and_then(|hex| u64::from_str_radix(hex, 16).ok());
+        if let Some(offset) = offset
+            && offset < start
+        {
+            return Ok(Some(offset));
+        }
+    }
+    Ok(None)
+}
+
+#[derive(Deserialize)]
+struct Toc {
+    entries: Vec<TocEntry>,
+}
+
+/// An entry of the TOC, shared by eStargz and zstd:chunked.
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct TocEntry {
+    name: String,
+    #[serde(rename = "type")]
+    kind: String,
+    size: u64,
+    modtime: String,
+    link_name: String,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    dev_major: u32,
+    dev_minor: u32,
+    /// Values in base64.
+    xattrs: BTreeMap<String, String>,
+    digest: String,
+    /// Where the compressed chunk starts.
+    offset: u64,
+    /// Where it ends, in zstd:chunked only.
+    end_offset: u64,
+    /// Where the chunk starts once decompressed, for small files sharing a member.
+    inner_offset: u64,
+    chunk_offset: u64,
+    chunk_size: u64,
+    chunk_digest: String,
+    /// `zeros` for holes in sparse files, in zstd:chunked only.
+    chunk_type: String,
+}
+
+fn sha256(digest: &str) -> Option<Digest> {
+    Digest::from_hex(Algorithm::Sha256, digest.strip_prefix("sha256:")?)
+}
+
+/// Decode standard base64 with padding.
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(text.len() / 4 * 3);
+    let mut bits = 0u32;
+    let mut count = 0;
+    for byte in text.trim_end_matches('=').bytes() {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        bits = (bits << 6) | value as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            decoded.push((bits >> count) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use sha2::{Digest as _, Sha256};
+    use std::io::Write;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn compress(codec: Codec, data: &[u8]) -> Vec<u8> {
+        match codec {
+            Codec::Zstd => zstd::encode_all(data, 3).unwrap(),
+            _ => gzip(data),
+        }
+    }
+
+    fn tar_header(path: &str, size: usize) -> Vec<u8> {
+        let mut header = tar::Header::new_ustar();
+        header.set_path(path).unwrap();
+        header.set_size(size as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        header.as_bytes().to_vec()
+    }
+
+    fn sha256_hex(data: &[u8]) -> String {
+        let hex: String = Sha256::digest(data)
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        format!("sha256:{hex}")
+    }
+
+    /// A layer with `docs/readme.txt`, `big.txt` in chunks of 10 bytes and a
+    /// symlink, each chunk compressed on its own; `corrupt` spoils a digest.
+    pub(crate) fn layer(codec: Codec, corrupt: bool) -> Vec<u8> {
+        let mut blob = Vec::new();
+        let mut toc = vec![
+            r#"{"name": "docs/", "type": "dir", "mode": 493}"#.to_string(),
+            r#"{"name": "link", "type": "symlink", "linkName": "docs/readme.txt"}"#.to_string(),
+        ];
+        for (path, text, chunk_size) in [
+            ("docs/readme.txt", &b"Hello from eStargz!"[..], 0),
+            ("big.txt", &b"0123456789abcdefghijklmnopqrstuvwxyz"[..], 10),
+        ] {
+            blob.extend(compress(codec, &tar_header(path, text.len())));
+            let chunks: Vec<&[u8]> = match chunk_size {
+                0 => vec![text],
+                size => text.chunks(size).collect(),
+            };
+            for (i, chunk) in chunks.iter().enumerate() {
+                let offset = blob.len();
+                let mut data = chunk.to_vec();
+                if i == chunks.len() - 1 {
+                    data.resize(data.len() + (512 - text.len() % 512) % 512, 0);
+                }
+                blob.extend(compress(codec, &data));
+                let digest = match corrupt {
+                    true => sha256_hex(b"something else"),
+                    false => sha256_hex(chunk),
+                };
+                let kind = if i == 0 { "reg" } else { "chunk" };
+                toc.push(format!(
+                    r#"{{"name": "{path}", "type": "{kind}", "size": {}, "mode": 420,
+                    "modtime": "2024-03-01T12:30:00Z", "offset": {offset}, "endOffset": {},
+                    "chunkOffset": {}, "chunkSize": {}, "chunkDigest": "{digest}",
+                    "digest": "{}", "xattrs": {{"user.comment": "aGk="}}}}"#,
+                    text.len(),
+                    blob.len(),
+                    i * chunk_size,
+                    if chunk_size == 0 { 0 } else { chunk.len() },
+                    sha256_hex(text),
+                ));
+            }
+        }
+        let toc = format!(r#"{{"version": 1, "entries": [{}]}}"#, toc.join(", "));
+
+        if codec == Codec::Zstd {
+            let skippable = |data: &[u8]| {
+                let mut frame = vec![0x50, 0x2a, 0x4d, 0x18];
+                frame.extend((data.len() as u32).to_le_bytes());
+                frame.extend(data);
+                frame
+            };
+            let manifest = zstd::encode_all(toc.as_bytes(), 3).unwrap();
+            let offset = blob.len() + 8;
+            blob.extend(skippable(&manifest));
+            let mut footer = Vec::new();
+            for field in [offset, manifest.len(), toc.len(), 1, 0, 0, 0] {
+                footer.extend((field as u64).to_le_bytes());
+            }
+            footer.extend(ZSTD_FOOTER_MAGIC);
+            blob.extend(skippable(&footer));
+            return blob;
+        }
+
+        let toc_offset = blob.len();
+        let mut toc_tar = tar_header(TOC_NAME, toc.len());
+        toc_tar.extend(toc.as_bytes());
+        toc_tar.resize(toc_tar.len().div_ceil(512) * 512 + 1024, 0);
+        blob.extend(gzip(&toc_tar));
+        // an empty gzip member with the TOC offset in its extra field
+        blob.extend([
+            0x1f, 0x8b, 8, 4, 0, 0, 0, 0, 0, 0xff, 26, 0, b'S', b'G', 22, 0,
+        ]);
+        blob.extend(format!("{toc_offset:016x}STARGZ").bytes());
+        blob.extend([1, 0, 0, 0xff, 0xff, 0, 0, 0, 0, 0, 0, 0, 0]);
+        blob
+    }
+
+    fn read(entry: &Entry) -> io::Result<Vec<u8>> {
+        let content = entry.content.as_ref().unwrap();
+        let mut buf = vec![0; content.size() as usize];
+        content.read_full_at(0, &mut buf)?;
+        Ok(buf)
+    }
+
+    #[test]
+    fn test_entries() -> io::Result<()> {
+        for codec in [Codec::Gzip, Codec::Zstd] {
+            let source: Arc<dyn ByteSource> = Arc::new(MemorySource::new(layer(codec, false)));
+            assert!(Stargz.sniff(&source), "{codec:?}");
+
+            let entries = Stargz.entries(&source, &Decoding::default())?;
+            let paths: Vec<_> = entries.iter().map(|e| e.path.as_str()).collect();
+            assert_eq!(paths, ["docs/", "link", "docs/readme.txt", "big.txt"]);
+            assert_eq!(entries[1].link_target.as_deref(), Some("docs/readme.txt"));
+            assert_eq!(read(&entries[2])?, b"Hello from eStargz!");
+            assert_eq!(read(&entries[3])?, b"0123456789abcdefghijklmnopqrstuvwxyz");
+            assert_eq!(
+                entries[3].xattrs,
+                [("user.comment".to_string(), b"hi".to_vec())]
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupt_chunk() -> io::Result<()> {
+        let source: Arc<dyn ByteSource> = Arc::new(MemorySource::new(layer(Codec::Gzip, true)));
+        let entries = Stargz.entries(&source, &Decoding::default())?;
+        let error = read(&entries[3]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        Ok(())
+    }
+
+    #[test]
+    fn test_plain_tar_gz() {
+        let source: Arc<dyn ByteSource> =
+            Arc::new(MemorySource::new(gzip(&tar_header("a.txt", 0))));
+        assert!(!Stargz.sniff(&source));
+    }
+
+    #[test]
+    fn test_base64_decode() {
+        assert_eq!(base64_decode("aGk=").unwrap(), b"hi");
+        assert_eq!(base64_decode("aGVsbG8/").unwrap(), b"hello?");
+        assert_eq!(base64_decode("a*"), None);
+    }
+}
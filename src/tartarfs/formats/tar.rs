@@ -0,0 +1,406 @@
+use super::{has_extension, ArchiveFormat, Entry, Special};
+use crate::tartarfs::compression::{
+    decompress, parallel_members, Codec, Decoding, DecompressedSource, Member,
+};
+use crate::tartarfs::source::{ByteSource, ConcatSource, SliceSource, SourceReader};
+use std::collections::BTreeMap;
+use std::io::{self, BufRead};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tar::Archive;
+use tracing::warn;
+
+const BLOCK_SIZE: usize = 512;
+
+/// The most of a member's contents kept in one piece while decoding members
+/// in parallel: its start or end, or a header with a long name after it.
+const MAX_KEPT: usize = 64 * 1024 + BLOCK_SIZE;
+
+/// An uncompressed tar archive.
+pub struct Tar;
+
+impl ArchiveFormat for Tar {
+    fn name(&self) -> &str {
+        "tar"
+    }
+
+    /// Check the header checksum of the first member, which unlike the
+    /// `ustar` magic is also there in pre-POSIX archives.
+    fn sniff(&self, source: &Arc<dyn ByteSource>) -> bool {
+        let mut header = [0; BLOCK_SIZE];
+        matches!(source.read_full_at(0, &mut header), Ok(BLOCK_SIZE)) && is_header(&header)
+    }
+
+    fn matches_name(&self, name: &str) -> bool {
+        has_extension(name, &[".tar"])
+    }
+
+    fn entries(
+        &self,
+        source: &Arc<dyn ByteSource>,
+        _decoding: &Decoding,
+    ) -> io::Result<Vec<Entry>> {
+        read_entries(source, false)
+    }
+}
+
+/// Check the header checksum of a tar block.
+fn is_header(header: &[u8]) -> bool {
+    let recorded = std::str::from_utf8(&header[148..156])
+        .ok()
+        .map(|field| field.trim_matches(|c: char| c == ' ' || c == '\0'))
+        .and_then(|field| u32::from_str_radix(field, 8).ok());
+    let Some(recorded) = recorded else {
+        return false;
+    };
+
+    // the checksum is computed with its own field filled with spaces
+    let (unsigned, signed) = header
+        .iter()
+        .enumerate()
+        .fold((0u32, 0i32), |(u, s), (i, &b)| {
+            let b = if (148..156).contains(&i) { b' ' } else { b };
+            (u + b as u32, s + b as i8 as i32)
+        });
+    recorded == unsigned || recorded as i32 == signed
+}
+
+/// A tar archive compressed as a whole, like `.tar.gz` or `.tar.zst`.
+pub struct CompressedTar;
+
+impl ArchiveFormat for CompressedTar {
+    fn name(&self) -> &str {
+        "compressed tar"
+    }
+
+    /// Check the compression magic, then the tar header behind it, decoding
+    /// just the start of the stream rather than setting up random access.
+    fn sniff(&self, source: &Arc<dyn ByteSource>) -> bool {
+        let Some(codec) = Codec::detect(source.as_ref()) else {
+            return false;
+        };
+        let decompressed: Arc<dyn ByteSource> =
+            Arc::new(DecompressedSource::new(Arc::clone(source), codec));
+        Tar.sniff(&decompressed)
+    }
+
+    fn matches_name(&self, name: &str) -> bool {
+        const EXTENSIONS: [&str; 13] = [
+            ".tar.gz",
+            ".tgz",
+            ".tar.zst",
+            ".tzst",
+            ".tar.xz",
+            ".txz",
+            ".tar.bz2",
+            ".tbz2",
+            ".tbz",
+            ".tar.lz4",
+            ".tar.lz",
+            ".tar.lzma",
+            ".tlz",
+        ];
+        has_extension(name, &EXTENSIONS)
+    }
+
+    fn entries(&self, source: &Arc<dyn ByteSource>, decoding: &Decoding) -> io::Result<Vec<Entry>> {
+        let Some(codec) = Codec::detect(source.as_ref()) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unknown compression",
+            ));
+        };
+        if decoding.jobs > 1
+            && let Some(members) = parallel_members(source, codec, decoding.jobs, scan_headers)
+        {
+            return read_members(members);
+        }
+        // compressed tarballs get concatenated, plain ones hardly ever
+        read_entries(
+            &decompress(Arc::clone(source), codec, &decoding.spool),
+            true,
+        )
+    }
+}
+
+/// List the members of a tar stream decoded in parallel, reading headers
+/// from what was kept of each member while decoding it rather than decoding
+/// members again.
+fn read_members(members: Vec<Member<Pieces>>) -> io::Result<Vec<Entry>> {
+    let kept = join_members(members);
+    let decompressed = Arc::clone(&kept.inner);
+    read_entries_from(
+        &(Arc::new(kept) as Arc<dyn ByteSource>),
+        &decompressed,
+        true,
+    )
+}
+
+/// Put the decoded members back together, along with what was kept of them.
+///
+/// Only the end of the last member with any contents can hold the
+/// end-of-archive marker and padding; of the others just enough is kept
+/// for a header split with the next member, which keeps small members like
+/// BGZF blocks from being kept whole.
+fn join_members(members: Vec<Member<Pieces>>) -> Kept {
+    let last = members.iter().rposition(|member| member.content.size() > 0);
+    let mut kept = BTreeMap::new();
+    let mut parts = Vec::new();
+    let mut start = 0;
+    for (index, member) in members.into_iter().enumerate() {
+        let mut pieces = member.read;
+        if Some(index) != last
+            && let Some((offset, tail)) = pieces.last_mut()
+        {
+            let cut = tail.len().saturating_sub(BLOCK_SIZE);
+            tail.drain(..cut);
+            *offset += cut as u64;
+        }
+        kept.extend(
+            pieces
+                .into_iter()
+                .map(|(offset, data)| (start + offset, data)),
+        );
+        start += member.content.size();
+        parts.push(member.content);
+    }
+    Kept {
+        kept,
+        inner: Arc::new(ConcatSource::new(parts)),
+    }
+}
+
+/// Pieces of a member's contents, each with where it starts in the member.
+type Pieces = Vec<(u64, Vec<u8>)>;
+
+/// Pieces of a decompressed stream kept in memory, falling back to decoding
+/// the stream for the rest, e.g. for headers of pre-POSIX archives.
+struct Kept {
+    kept: BTreeMap<u64, Vec<u8>>,
+    inner: Arc<dyn ByteSource>,
+}
+
+impl ByteSource for Kept {
+    fn size(&self) -> u64 {
+        self.inner.size()
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let nearby = self
+            .kept
+            .range(offset.saturating_sub(MAX_KEPT as u64)..=offset);
+        for (&start, data) in nearby.rev() {
+            if let Some(data) = data.get((offset - start) as usize..)
+                && !data.is_empty()
+            {
+                let n = data.len().min(buf.len());
+                buf[..n].copy_from_slice(&data[..n]);
+                return Ok(n);
+            }
+        }
+        self.inner.read_at(offset, buf)
+    }
+}
+
+/// Keep what tar headers will be read from in a member's contents: each
+/// block that looks like a header, the long name or pax records that follow
+/// it, and the start and end of the member, for headers split between two
+/// members and the end-of-archive marker.
+fn scan_headers(reader: &mut dyn BufRead) -> io::Result<Pieces> {
+    // the `ustar` magic, where any header but a pre-POSIX one has it
+    const MAGIC_OFFSET: usize = 257;
+    let mut kept = Vec::new();
+    // the contents not yet done with, from `window_start` on
+    let mut window = Vec::new();
+    let mut window_start = 0;
+    // where in the window the next header could start
+    let mut next = 0;
+    let mut chunk = vec![0; 1 << 20];
+    loop {
+        let n = match reader.read(&mut chunk) {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            result => result?,
+        };
+        let done = n == 0;
+        window.extend_from_slice(&chunk[..n]);
+        if window_start == 0 && kept.is_empty() && (window.len() >= BLOCK_SIZE || done) {
+            kept.push((0, window[..BLOCK_SIZE.min(window.len())].to_vec()));
+        }
+
+        loop {
+            let searched = window.get(next + MAGIC_OFFSET..).unwrap_or_default();
+            let Some(found) = searched.windows(5).position(|magic| magic == b"ustar") else {
+                next = next.max((window.len() + 1).saturating_sub(MAGIC_OFFSET + 5));
+                break;
+            };
+            let start = next + found;
+            let Some(header) = window.get(start..start + BLOCK_SIZE) else {
+                break; // the rest of it is still to come
+            };
+            next = start + 1;
+            if !is_header(header) {
+                continue;
+            }
+            let mut end = start + BLOCK_SIZE;
+            if matches!(header[156], b'L' | b'K' | b'x' | b'g') {
+                let size = tar::Header::from_byte_slice(header)
+                    .entry_size()
+                    .unwrap_or(0);
+                end += (size as usize)
+                    .next_multiple_of(BLOCK_SIZE)
+                    .min(MAX_KEPT - BLOCK_SIZE);
+                if end > window.len() && !done {
+                    next = start;
+                    break;
+                }
+            }
+            let data = window[start..end.min(window.len())].to_vec();
+            kept.push((window_start + start as u64, data));
+        }
+
+        if done {
+            let tail = window.len().saturating_sub(MAX_KEPT);
+            kept.push((window_start + tail as u64, window.split_off(tail)));
+            return Ok(kept);
+        }
+        let consumed = next.min(window.len().saturating_sub(MAX_KEPT));
+        window.drain(..consumed);
+        window_start += consumed as u64;
+        next -= consumed;
+    }
+}
+
+/// List the members of a tar stream, each pointing at its slice of the stream;
+/// `concatenated` goes on past end-of-archive markers, for `cat a.tar.gz b.tar.gz`.
+fn read_entries(source: &Arc<dyn ByteSource>, concatenated: bool) -> io::Result<Vec<Entry>> {
+    read_entries_from(source, source, concatenated)
+}
+
+/// Like [`read_entries`], but reading the headers from `headers`, which has
+/// the same contents as `source`.
+fn read_entries_from(
+    headers: &Arc<dyn ByteSource>,
+    source: &Arc<dyn ByteSource>,
+    concatenated: bool,
+) -> io::Result<Vec<Entry>> {
+    let mut archive = Archive::new(SourceReader::new(Arc::clone(headers)));
+    archive.set_ignore_zeros(concatenated);
+    let mut entries = Vec::new();
+    // seek past member data instead of reading it, which matters for remote archives
+    for entry in archive.entries_with_seek()? {
+        let entry = match entry {
+            Ok(entry) => entry,
+            // a bad header shouldn't hide the members before it, but there's
+            // no telling where the next one starts
+            Err(e) if !entries.is_empty() => {
+                warn!("Stopped reading tar headers: {}", e);
+                break;
+            }
+            Err(e) => return Err(e),
+        };
+        match read_entry(&entry, source) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => warn!("Skipping tar member: {}", e),
+        }
+    }
+    Ok(entries)
+}
+
+/// Turn one tar member into an entry pointing at its slice of `source`.
+fn read_entry<R: io::Read>(
+    entry: &tar::Entry<'_, R>,
+    source: &Arc<dyn ByteSource>,
+) -> io::Result<Entry> {
+    let header = entry.header();
+    let is_dir = header.entry_type().is_dir();
+    let link_target = match header.entry_type().is_symlink() {
+        true => entry
+            .link_name()?
+            .map(|target| target.to_string_lossy().into_owned()),
+        false => None,
+    };
+    let mtime = header
+        .mtime()
+        .ok()
+        .and_then(|t| SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(t)))
+        .unwrap_or_else(SystemTime::now);
+
+    let entry_type = header.entry_type();
+    let major = header.device_major().ok().flatten().unwrap_or(0);
+    let minor = header.device_minor().ok().flatten().unwrap_or(0);
+    let special = if entry_type.is_character_special() {
+        Some(Special::CharDevice { major, minor })
+    } else if entry_type.is_block_special() {
+        Some(Special::BlockDevice { major, minor })
+    } else if entry_type.is_fifo() {
+        Some(Special::Fifo)
+    } else {
+        None
+    };
+
+    let content: Option<Arc<dyn ByteSource>> =
+        if is_dir || link_target.is_some() || special.is_some() {
+            None
+        } else {
+            let size = header.size().unwrap_or(0);
+            let offset = entry.raw_file_position();
+            Some(Arc::new(SliceSource::new(Arc::clone(source), offset, size)))
+        };
+
+    Ok(Entry {
+        path: entry.path()?.to_string_lossy().into_owned(),
+        is_dir,
+        mode: header.mode().unwrap_or(if is_dir { 0o755 } else { 0o644 }),
+        uid: header
+            .uid()
+            .ok()
+            .and_then(|id| id.try_into().ok())
+            .unwrap_or(1000),
+        gid: header
+            .gid()
+            .ok()
+            .and_then(|id| id.try_into().ok())
+            .unwrap_or(1000),
+        mtime,
+        content,
+        digest: None,
+        link_target,
+        special,
+        xattrs: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tartarfs::compression::tests::bgzf;
+    use crate::tartarfs::source::MemorySource;
+
+    #[test]
+    fn test_join_members() -> io::Result<()> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for i in 0..40 {
+            let data = vec![i as u8; 50_000];
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            builder.append_data(&mut header, format!("file-{i}.bin"), &data[..])?;
+        }
+        let data = builder.into_inner()?;
+        let compressed: Arc<dyn ByteSource> = Arc::new(MemorySource::new(bgzf(&data)?));
+
+        let members = parallel_members(&compressed, Codec::Gzip, 4, scan_headers).unwrap();
+        assert!(members.len() > 30);
+        let kept = join_members(members);
+        let total: usize = kept.kept.values().map(Vec::len).sum();
+        assert!(total < data.len() / 8, "kept {total} of {}", data.len());
+
+        let entries =
+            read_members(parallel_members(&compressed, Codec::Gzip, 4, scan_headers).unwrap())?;
+        assert_eq!(entries.len(), 40);
+        let mut buf = vec![0; 50_000];
+        let content = entries[39].content.as_ref().unwrap();
+        content.read_full_at(0, &mut buf)?;
+        assert_eq!(buf, vec![39; 50_000]);
+        Ok(())
+    }
+}
@@ -0,0 +1,526 @@
+use super::{has_extension, parse_timestamp, ArchiveFormat, Entry};
+use crate::tartarfs::compression::{gzip_members, Decoding};
+use crate::tartarfs::index::normalize_path;
+use crate::tartarfs::source::{ByteSource, ConcatSource, SliceSource, SourceReader};
+use flate2::bufread::GzDecoder;
+use std::collections::HashSet;
+use std::io::{self, BufRead, BufReader, Error, ErrorKind, Read};
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+const GZIP_MAGIC: &[u8; 2] = &[0x1f, 0x8b];
+const VERSION_PREFIX: &[u8; 5] = b"WARC/";
+/// Longer header lines than this mean it isn't a WARC file after all.
+const MAX_LINE: u64 = 64 * 1024;
+/// The extended attributes of each record header are named like `user.warc.warc-date`.
+pub const XATTR_PREFIX: &str = "user.warc.";
+
+/// A WARC web archive, plain or as `.warc.gz` with a gzip member per record.
+///
+/// Each `response` or `resource` record shows up at a path made from its
+/// target URI, like `example.com/docs/index.html`, with the record headers
+/// as extended attributes. Reading a record only decodes its own gzip member.
+#[derive(Default)]
+pub struct Warc {
+    /// Serve only the body of HTTP responses, with chunked transfer encoding
+    /// undone, instead of the whole HTTP message as captured.
+    pub payload_only: bool,
+}
+
+impl ArchiveFormat for Warc {
+    fn name(&self) -> &str {
+        "warc"
+    }
+
+    fn settings(&self) -> String {
+        match self.payload_only {
+            true => "payload_only".into(),
+            false => String::new(),
+        }
+    }
+
+    fn sniff(&self, source: &Arc<dyn ByteSource>) -> bool {
+        let mut magic = [0; 5];
+        if !matches!(source.read_full_at(0, &mut magic), Ok(5)) {
+            return false;
+        }
+        if magic.starts_with(GZIP_MAGIC) {
+            // only decode as much of the first member as needed
+            let reader = BufReader::new(SourceReader::new(Arc::clone(source)));
+            let mut decoder = GzDecoder::new(reader).take(5);
+            magic = [0; 5];
+            if decoder.read_exact(&mut magic).is_err() {
+                return false;
+            }
+        }
+        magic == *VERSION_PREFIX
+    }
+
+    fn matches_name(&self, name: &str) -> bool {
+        has_extension(name, &[".warc", ".warc.gz"])
+    }
+
+    fn entries(
+        &self,
+        source: &Arc<dyn ByteSource>,
+        _decoding: &Decoding,
+    ) -> io::Result<Vec<Entry>> {
+        let mut magic = [0; 2];
+        source.read_full_at(0, &mut magic)?;
+        let mut entries = if magic == *GZIP_MAGIC {
+            self.read_members(source)?
+        } else {
+            let mut scanner = Scanner::new(BufReader::new(SourceReader::new(Arc::clone(source))));
+            let records = scanner.records(self.payload_only)?;
+            records
+                .into_iter()
+                .filter_map(|record| record.into_entry(source))
+                .collect()
+        };
+        move_clashing_files(&mut entries);
+        Ok(entries)
+    }
+}
+
+impl Warc {
+    /// Read the records of each gzip member in turn, so that reading a
+    /// record later only decodes its own member.
+    fn read_members(&self, source: &Arc<dyn ByteSource>) -> io::Result<Vec<Entry>> {
+        let members = gzip_members(source, |reader| {
+            Scanner::new(reader).records(self.payload_only)
+        })?;
+        let mut entries = Vec::new();
+        for member in members {
+            match member.read {
+                Ok(records) => entries.extend(
+                    records
+                        .into_iter()
+                        .filter_map(|record| record.into_entry(&member.content)),
+                ),
+                Err(e) => warn!("Skipping broken WARC member at {}: {}", member.offset, e),
+            }
+        }
+        Ok(entries)
+    }
+}
+
+struct Record {
+    headers: Vec<(String, String)>,
+    /// Where the content is in the decoded stream, in pieces when chunked.
+    pieces: Vec<(u64, u64)>,
+}
+
+impl Record {
+    fn header(&self, name: &str) -> Option<&str> {
+        let mut headers = self.headers.iter();
+        let (_, value) = headers.find(|(key, _)| key.eq_ignore_ascii_case(name))?;
+        Some(value)
+    }
+
+    fn into_entry(self, stream: &Arc<dyn ByteSource>) -> Option<Entry> {
+        let kind = self.header("WARC-Type")?;
+        if !kind.eq_ignore_ascii_case("response") && !kind.eq_ignore_ascii_case("resource") {
+            return None;
+        }
+        let uri = self.header("WARC-Target-URI")?;
+        let Some(path) = uri_path(uri) else {
+            debug!("Skipping WARC record for {}", uri);
+            return None;
+        };
+
+        let mut pieces: Vec<Arc<dyn ByteSource>> = self
+            .pieces
+            .iter()
+            .map(|&(offset, size)| {
+                Arc::new(SliceSource::new(Arc::clone(stream), offset, size)) as Arc<dyn ByteSource>
+            })
+            .collect();
+        let content = match pieces.len() {
+            1 => pieces.remove(0),
+            _ => Arc::new(ConcatSource::new(pieces)),
+        };
+
+        let mut entry = Entry::file(path, content);
+        if let Some(mtime) = self.header("WARC-Date").and_then(parse_timestamp) {
+            entry.mtime = mtime;
+        }
+        // repeated headers like `WARC-Concurrent-To` get one attribute
+        let mut xattrs: Vec<(String, Vec<u8>)> = Vec::new();
+        for (name, value) in &self.headers {
+            let name = format!("{XATTR_PREFIX}{}", name.to_ascii_lowercase());
+            match xattrs.iter_mut().find(|(key, _)| *key == name) {
+                Some((_, values)) => values.extend(format!(", {value}").bytes()),
+                None => xattrs.push((name, value.as_bytes().to_vec())),
+            }
+        }
+        entry.xattrs = xattrs;
+        Some(entry)
+    }
+}
+
+/// Reads records from a decoded WARC stream, keeping track of the position.
+struct Scanner<R> {
+    reader: R,
+    position: u64,
+}
+
+impl<R: BufRead> Scanner<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            position: 0,
+        }
+    }
+
+    /// The next line without its line ending, or `None` at the end.
+    fn line(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut line = Vec::new();
+        let n = (&mut self.reader)
+            .take(MAX_LINE)
+            .read_until(b'\n', &mut line)?;
+        self.position += n as u64;
+        if n == 0 {
+            return Ok(None);
+        }
+        if line.last() != Some(&b'\n') && n as u64 == MAX_LINE {
+            return Err(Error::new(ErrorKind::InvalidData, "overlong WARC line"));
+        }
+        while matches!(line.last(), Some(b'\n' | b'\r')) {
+            line.pop();
+        }
+        Ok(Some(line))
+    }
+
+    fn skip_to(&mut self, position: u64) -> io::Result<()> {
+        let wanted = position.saturating_sub(self.position);
+        let skipped = io::copy(&mut (&mut self.reader).take(wanted), &mut io::sink())?;
+        self.position += skipped;
+        match skipped == wanted {
+            true => Ok(()),
+            false => Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "truncated WARC record",
+            )),
+        }
+    }
+
+    fn records(&mut self, payload_only: bool) -> io::Result<Vec<Record>> {
+        let mut records = Vec::new();
+        while let Some(line) = self.line()? {
+            if line.is_empty() {
+                continue; // the blank lines after each record
+            }
+            if !line.starts_with(VERSION_PREFIX) {
+                return Err(Error::new(ErrorKind::InvalidData, "bad WARC record header"));
+            }
+
+            let mut headers: Vec<(String, String)> = Vec::new();
+            while let Some(line) = self.line()? {
+                if line.is_empty() {
+                    break;
+                }
+                let line = String::from_utf8_lossy(&line);
+                if line.starts_with([' ', '\t'])
+                    && let Some((_, value)) = headers.last_mut()
+                {
+                    value.push(' ');
+                    value.push_str(line.trim());
+                } else if let Some((name, value)) = line.split_once(':') {
+                    headers.push((name.trim().to_string(), value.trim().to_string()));
+                }
+            }
+            let mut record = Record {
+                headers,
+                pieces: Vec::new(),
+            };
+            let length: u64 = record
+                .header("Content-Length")
+                .and_then(|length| length.parse().ok())
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "WARC record without length"))?;
+
+            let start = self.position;
+            let end = start + length;
+            let is_http_response = record.header("WARC-Type") == Some("response")
+                && record
+                    .header("Content-Type")
+                    .is_some_and(|kind| kind.starts_with("application/http"));
+            record.pieces = match is_http_response && payload_only {
+                true => self.http_body(end)?,
+                false => None,
+            }
+            .unwrap_or_else(|| vec![(start, length)]);
+            self.skip_to(end)?;
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    /// Find the body of an HTTP message ending at `end`, in pieces if it's
+    /// chunked; `None` if it doesn't look like HTTP.
+    fn http_body(&mut self, end: u64) -> io::Result<Option<Vec<(u64, u64)>>> {
+        let mut chunked = false;
+        loop {
+            if self.position >= end {
+                return Ok(None);
+            }
+            let Some(line) = self.line()? else {
+                return Ok(None);
+            };
+            if line.is_empty() {
+                break;
+            }
+            let line = String::from_utf8_lossy(&line);
+            if let Some((name, value)) = line.split_once(':')
+                && name.trim().eq_ignore_ascii_case("Transfer-Encoding")
+            {
+                chunked = value.to_ascii_lowercase().contains("chunked");
+            }
+        }
+        if self.position > end {
+            return Ok(None);
+        }
+        if !chunked {
+            return Ok(Some(vec![(self.position, end - self.position)]));
+        }
+
+        let mut pieces = Vec::new();
+        while self.position < end {
+            let Some(line) = self.line()? else {
+                break;
+            };
+            let line = String::from_utf8_lossy(&line);
+            // the size may be followed by chunk extensions
+            let size = line.split(';').next().unwrap_or_default().trim();
+            let Ok(size) = u64::from_str_radix(size, 16) else {
+                break;
+            };
+            if size == 0 {
+                break;
+            }
+            let size = size.min(end.saturating_sub(self.position));
+            pieces.push((self.position, size));
+            self.skip_to(self.position + size)?;
+            self.line()?; // the line ending after the chunk
+        }
+        Ok(Some(pieces))
+    }
+}
+
+/// Turn `https://Example.com:443/docs/?page=2` into `example.com/docs/index.html?page=2`.
+fn uri_path(uri: &str) -> Option<String> {
+    // WARC 1.0 put angle brackets around it
+    let uri = uri.trim().trim_start_matches('<').trim_end_matches('>');
+    let (scheme, rest) = uri.split_once("://")?;
+    let rest = rest.split('#').next().unwrap_or_default();
+    let split = rest.find(['/', '?']).unwrap_or(rest.len());
+    let (authority, path_and_query) = rest.split_at(split);
+    let (path, query) = match path_and_query.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (path_and_query, None),
+    };
+
+    let host = authority
+        .rsplit('@')
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let default_port = match scheme.to_ascii_lowercase().as_str() {
+        "http" => Some(":80"),
+        "https" => Some(":443"),
+        _ => None,
+    };
+    let host = match default_port {
+        Some(port) => host.strip_suffix(port).unwrap_or(&host).to_string(),
+        None => host,
+    };
+    if host.is_empty() || host.starts_with('.') {
+        return None;
+    }
+
+    let mut path: Vec<String> = path.split('/').map(percent_decode).collect();
+    if path.last().is_none_or(|name| name.is_empty()) {
+        path.pop();
+        path.push("index.html".to_string());
+    }
+    let mut path = format!("{host}/{}", path.join("/"));
+    if let Some(query) = query {
+        path.push('?');
+        // a slash in the query would make the page a directory
+        path.push_str(&query.replace('/', "%2F"));
+    }
+    Some(normalize_path(&path))
+}
+
+/// Decode `%20` and the like, except for encoded slashes and NULs,
+/// which would change the path.
+fn percent_decode(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) if byte != b'/' && byte != 0 => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// A page like `example.com/docs` can't be a file if `example.com/docs/a.html`
+/// was captured too, so it moves to `example.com/docs/index.html`.
+fn move_clashing_files(entries: &mut [Entry]) {
+    let directories: HashSet<String> = entries
+        .iter()
+        .flat_map(|entry| {
+            let parents = entry.path.match_indices('/').map(|(i, _)| &entry.path[..i]);
+            parents.map(str::to_string).collect::<Vec<_>>()
+        })
+        .collect();
+    for entry in entries.iter_mut() {
+        if directories.contains(&entry.path) {
+            debug!("Moving WARC page {} below its directory", entry.path);
+            entry.path = format!("{}/index.html", entry.path);
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::tartarfs::source::MemorySource;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+    use std::time::{Duration, SystemTime};
+
+    fn record(kind: &str, uri: &str, content_type: &str, block: &[u8]) -> Vec<u8> {
+        let mut record = format!(
+            "WARC/1.1\r\nWARC-Type: {kind}\r\nWARC-Target-URI: {uri}\r\n\
+             WARC-Date: 2024-03-01T12:30:00Z\r\nWARC-Record-ID: <urn:uuid:{}>\r\n\
+             Content-Type: {content_type}\r\nContent-Length: {}\r\n\r\n",
+            uri.len(),
+            block.len()
+        )
+        .into_bytes();
+        record.extend(block);
+        record.extend(b"\r\n\r\n");
+        record
+    }
+
+    /// The records of a small crawl: a `warcinfo`, a request, a plain and a
+    /// chunked response, and a response for a page with pages below it.
+    pub(crate) fn records() -> Vec<Vec<u8>> {
+        let http = "application/http; msgtype=response";
+        vec![
+            record(
+                "warcinfo",
+                "",
+                "application/warc-fields",
+                b"software: test\r\n",
+            ),
+            record(
+                "request",
+                "https://example.com/",
+                "application/http; msgtype=request",
+                b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n",
+            ),
+            record(
+                "response",
+                "https://example.com/",
+                http,
+                b"HTTP/1.1 200 OK\r\nContent-Type: text/html\r\n\r\n<h1>Home</h1>",
+            ),
+            record(
+                "response",
+                "http://Example.com:80/docs/a%20b.txt?v=2",
+                http,
+                b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                  5\r\nHello\r\n7;ext=1\r\n, world\r\n0\r\n\r\n",
+            ),
+            record(
+                "response",
+                "https://example.com/docs",
+                http,
+                b"HTTP/1.1 200 OK\r\n\r\ndocs",
+            ),
+        ]
+    }
+
+    /// A `.warc.gz` with each record in its own gzip member.
+    pub(crate) fn warc_gz() -> Vec<u8> {
+        let mut archive = Vec::new();
+        for record in records() {
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(&record).unwrap();
+            archive.extend(encoder.finish().unwrap());
+        }
+        archive
+    }
+
+    fn read(entry: &Entry) -> io::Result<String> {
+        let content = entry.content.as_ref().unwrap();
+        let mut buf = vec![0; content.size() as usize];
+        content.read_full_at(0, &mut buf)?;
+        Ok(String::from_utf8(buf).unwrap())
+    }
+
+    #[test]
+    fn test_records() -> io::Result<()> {
+        let plain = records().concat();
+        for archive in [plain, warc_gz()] {
+            let source: Arc<dyn ByteSource> = Arc::new(MemorySource::new(archive));
+            assert!(Warc::default().sniff(&source));
+
+            let entries = Warc::default().entries(&source, &Decoding::default())?;
+            let paths: Vec<_> = entries.iter().map(|e| e.path.as_str()).collect();
+            assert_eq!(
+                paths,
+                [
+                    "example.com/index.html",
+                    "example.com/docs/a b.txt?v=2",
+                    "example.com/docs/index.html"
+                ]
+            );
+            assert!(read(&entries[0])?.starts_with("HTTP/1.1 200 OK\r\n"));
+            assert!(entries[0]
+                .xattrs
+                .contains(&("user.warc.warc-type".to_string(), b"response".to_vec())));
+            assert_eq!(
+                entries[0].mtime,
+                SystemTime::UNIX_EPOCH + Duration::from_secs(1_709_296_200)
+            );
+
+            let payload = Warc { payload_only: true }.entries(&source, &Decoding::default())?;
+            assert_eq!(read(&payload[0])?, "<h1>Home</h1>");
+            assert_eq!(read(&payload[1])?, "Hello, world");
+            assert_eq!(read(&payload[2])?, "docs");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_uri_path() {
+        assert_eq!(
+            uri_path("http://a.org").as_deref(),
+            Some("a.org/index.html")
+        );
+        assert_eq!(
+            uri_path("<https://user@A.org:8443/x/../y/>").as_deref(),
+            Some("a.org:8443/y/index.html")
+        );
+        assert_eq!(
+            uri_path("https://a.org/a%2Fb#top").as_deref(),
+            Some("a.org/a%2Fb")
+        );
+        assert_eq!(uri_path("dns:example.com"), None);
+    }
+}
@@ -0,0 +1,571 @@
+use super::{has_extension, unix_time, ArchiveFormat, Entry};
+use crate::tartarfs::compression::{Codec, Decoding, DecompressedSource};
+use crate::tartarfs::source::{ByteSource, Recipe};
+use std::io::{self, Error, ErrorKind};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, SystemTime};
+use tracing::warn;
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x06054b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x06064b50;
+const ZIP64_LOCATOR_SIGNATURE: u32 = 0x07064b50;
+
+const END_OF_CENTRAL_DIRECTORY_SIZE: u64 = 22;
+const ZIP64_LOCATOR_SIZE: u64 = 20;
+const LOCAL_HEADER_SIZE: usize = 30;
+const CENTRAL_HEADER_SIZE: usize = 46;
+
+const HOST_UNIX: u16 = 3;
+const FLAG_ENCRYPTED: u16 = 1;
+
+/// A zip archive, including `.jar`, `.whl`, Android `.apk` and the like.
+pub struct Zip;
+
+impl ArchiveFormat for Zip {
+    fn name(&self) -> &str {
+        "zip"
+    }
+
+    fn sniff(&self, source: &Arc<dyn ByteSource>) -> bool {
+        let mut header = [0; 4];
+        matches!(source.read_full_at(0, &mut header), Ok(4))
+            && (header == *b"PK\x03\x04" || header == *b"PK\x05\x06")
+    }
+
+    fn matches_name(&self, name: &str) -> bool {
+        const EXTENSIONS: [&str; 7] = [".zip", ".jar", ".war", ".ear", ".aar", ".whl", ".apk"];
+        has_extension(name, &EXTENSIONS)
+    }
+
+    fn entries(
+        &self,
+        source: &Arc<dyn ByteSource>,
+        _decoding: &Decoding,
+    ) -> io::Result<Vec<Entry>> {
+        read_entries(source)
+    }
+}
+
+/// List the members of a zip archive from its central directory,
+/// without touching the member data or local headers.
+fn read_entries(source: &Arc<dyn ByteSource>) -> io::Result<Vec<Entry>> {
+    let directory = find_central_directory(source.as_ref())?;
+    let end = directory.offset.checked_add(directory.size);
+    if end.is_none_or(|end| end > source.size()) {
+        return Err(invalid("central directory is past the end of the file"));
+    }
+
+    let mut buffer = vec![0; directory.size as usize];
+    source.read_full_at(directory.offset, &mut buffer)?;
+    let mut cursor = Cursor::new(&buffer);
+
+    let mut entries = Vec::with_capacity(directory.count.min(1 << 20) as usize);
+    while cursor.remaining() >= CENTRAL_HEADER_SIZE {
+        if cursor.u32()? != CENTRAL_HEADER_SIGNATURE {
+            break;
+        }
+        let header = CentralHeader::parse(&mut cursor)?;
+        if let Some(entry) = header.into_entry(source, directory.base) {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+struct CentralDirectory {
+    offset: u64,
+    size: u64,
+    count: u64,
+    /// Bytes prepended to the archive, e.g. by a self-extracting stub.
+    base: u64,
+}
+
+fn find_central_directory(source: &dyn ByteSource) -> io::Result<CentralDirectory> {
+    // the end record sits at the very end, followed only by a comment of up to 64 KiB
+    let file_size = source.size();
+    let tail_size = file_size.min(END_OF_CENTRAL_DIRECTORY_SIZE + u16::MAX as u64);
+    let tail_offset = file_size - tail_size;
+    let mut tail = vec![0; tail_size as usize];
+    source.read_full_at(tail_offset, &mut tail)?;
+
+    let record_start = (0..tail
+        .len()
+        .saturating_sub(END_OF_CENTRAL_DIRECTORY_SIZE as usize - 1))
+        .rev()
+        .find(|&i| tail[i..i + 4] == END_OF_CENTRAL_DIRECTORY_SIGNATURE.to_le_bytes())
+        .ok_or_else(|| invalid("no end of central directory record"))?;
+    let record_offset = tail_offset + record_start as u64;
+
+    let mut cursor = Cursor::new(&tail[record_start + 4..]);
+    cursor.skip(6)?; // disk numbers and the per-disk entry count
+    let count = cursor.u16()?;
+    let size = cursor.u32()?;
+    let offset = cursor.u32()?;
+
+    let is_zip64 = count == u16::MAX || size == u32::MAX || offset == u32::MAX;
+    if is_zip64 && record_offset >= ZIP64_LOCATOR_SIZE {
+        let mut locator = [0; ZIP64_LOCATOR_SIZE as usize];
+        source.read_full_at(record_offset - ZIP64_LOCATOR_SIZE, &mut locator)?;
+        let mut cursor = Cursor::new(&locator);
+        if cursor.u32()? == ZIP64_LOCATOR_SIGNATURE {
+            cursor.skip(4)?;
+            let zip64_record_offset = cursor.u64()?;
+
+            let mut record = [0; 56];
+            source.read_full_at(zip64_record_offset, &mut record)?;
+            let mut cursor = Cursor::new(&record);
+            if cursor.u32()? != ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE {
+                return Err(invalid("bad zip64 end of central directory record"));
+            }
+            cursor.skip(28)?; // record size, versions, disk numbers, per-disk count
+            let count = cursor.u64()?;
+            let size = cursor.u64()?;
+            let offset = cursor.u64()?;
+            return Ok(CentralDirectory {
+                offset,
+                size,
+                count,
+                base: 0,
+            });
+        }
+    }
+
+    // the directory ends where the end record starts, so anything beyond
+    // the recorded offset was prepended to the archive
+    let base = record_offset.saturating_sub(size as u64 + offset as u64);
+    Ok(CentralDirectory {
+        offset: base + offset as u64,
+        size: size as u64,
+        count: count as u64,
+        base,
+    })
+}
+
+struct CentralHeader {
+    version_made_by: u16,
+    flags: u16,
+    method: u16,
+    dos_time: u16,
+    dos_date: u16,
+    compressed_size: u64,
+    size: u64,
+    external_attributes: u32,
+    local_header_offset: u64,
+    name: Vec<u8>,
+    unix_mtime: Option<u32>,
+    uid: Option<u32>,
+    gid: Option<u32>,
+}
+
+impl CentralHeader {
+    /// Parse a central directory header, the signature already consumed.
+    fn parse(cursor: &mut Cursor) -> io::Result<Self> {
+        let version_made_by = cursor.u16()?;
+        cursor.skip(2)?; // version needed to extract
+        let flags = cursor.u16()?;
+        let method = cursor.u16()?;
+        let dos_time = cursor.u16()?;
+        let dos_date = cursor.u16()?;
+        cursor.skip(4)?; // crc-32
+        let compressed_size = cursor.u32()?;
+        let size = cursor.u32()?;
+        let name_length = cursor.u16()? as usize;
+        let extra_length = cursor.u16()? as usize;
+        let comment_length = cursor.u16()? as usize;
+        cursor.skip(4)?; // disk number start, internal attributes
+        let external_attributes = cursor.u32()?;
+        let local_header_offset = cursor.u32()?;
+        let name = cursor.bytes(name_length)?.to_vec();
+        let extra = cursor.bytes(extra_length)?;
+        cursor.skip(comment_length)?;
+
+        let mut header = CentralHeader {
+            version_made_by,
+            flags,
+            method,
+            dos_time,
+            dos_date,
+            compressed_size: compressed_size as u64,
+            size: size as u64,
+            external_attributes,
+            local_header_offset: local_header_offset as u64,
+            name,
+            unix_mtime: None,
+            uid: None,
+            gid: None,
+        };
+        header.parse_extra_fields(extra, compressed_size, size, local_header_offset);
+        Ok(header)
+    }
+
+    fn parse_extra_fields(
+        &mut self,
+        extra: &[u8],
+        compressed_size: u32,
+        size: u32,
+        local_header_offset: u32,
+    ) {
+        let mut cursor = Cursor::new(extra);
+        while let (Ok(id), Ok(length)) = (cursor.u16(), cursor.u16()) {
+            let Ok(data) = cursor.bytes(length as usize) else {
+                break;
+            };
+            let mut field = Cursor::new(data);
+            match id {
+                // zip64: only the values that overflowed their 32-bit field are present
+                0x0001 => {
+                    if size == u32::MAX
+                        && let Ok(value) = field.u64()
+                    {
+                        self.size = value;
+                    }
+                    if compressed_size == u32::MAX
+                        && let Ok(value) = field.u64()
+                    {
+                        self.compressed_size = value;
+                    }
+                    if local_header_offset == u32::MAX
+                        && let Ok(value) = field.u64()
+                    {
+                        self.local_header_offset = value;
+                    }
+                }
+                // extended timestamp; the central copy only ever has the mtime
+                0x5455 => {
+                    if let Ok(flags) = field.u8()
+                        && flags & 1 != 0
+                    {
+                        self.unix_mtime = field.u32().ok();
+                    }
+                }
+                // Info-ZIP Unix uid/gid
+                0x7875 => {
+                    let _version = field.u8();
+                    self.uid = field.u8().and_then(|n| field.uint(n as usize)).ok();
+                    self.gid = field.u8().and_then(|n| field.uint(n as usize)).ok();
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn into_entry(self, source: &Arc<dyn ByteSource>, base: u64) -> Option<Entry> {
+        // names without the UTF-8 flag are CP437 on paper, but in practice
+        // they're ASCII or UTF-8 written by tools that didn't set the flag
+        let path = String::from_utf8_lossy(&self.name).into_owned();
+
+        let unix_mode = match self.version_made_by >> 8 {
+            HOST_UNIX => self.external_attributes >> 16,
+            _ => 0,
+        };
+        let is_dir = if unix_mode != 0 {
+            unix_mode & libc::S_IFMT == libc::S_IFDIR
+        } else {
+            path.ends_with('/') || self.external_attributes & 0x10 != 0
+        };
+        let mode = match unix_mode & 0o7777 {
+            0 => {
+                let mode = if is_dir { 0o755 } else { 0o644 };
+                // the MS-DOS read-only attribute
+                if self.external_attributes & 0x01 != 0 {
+                    mode & !0o222
+                } else {
+                    mode
+                }
+            }
+            permissions => permissions,
+        };
+
+        let mtime = match self.unix_mtime {
+            Some(seconds) => SystemTime::UNIX_EPOCH + Duration::from_secs(seconds as u64),
+            None => dos_date_time(self.dos_date, self.dos_time),
+        };
+
+        let content = if is_dir {
+            None
+        } else if self.flags & FLAG_ENCRYPTED != 0 {
+            warn!("Skipping encrypted zip entry: {}", path);
+            return None;
+        } else {
+            let member: Arc<dyn ByteSource> = Arc::new(MemberSource {
+                archive: Arc::clone(source),
+                local_header_offset: base + self.local_header_offset,
+                size: self.compressed_size,
+                data_offset: OnceLock::new(),
+            });
+            let codec = match self.method {
+                0 => None,
+                8 => Some(Codec::Deflate),
+                12 => Some(Codec::Bzip2),
+                93 => Some(Codec::Zstd),
+                95 => Some(Codec::Xz),
+                method => {
+                    warn!(
+                        "Skipping zip entry with compression method {}: {}",
+                        method, path
+                    );
+                    return None;
+                }
+            };
+            Some(match codec {
+                Some(codec) => Arc::new(DecompressedSource::with_size(member, codec, self.size)),
+                None => member,
+            })
+        };
+
+        // the data of a symlink is its target
+        let (content, link_target) = match content {
+            Some(data) if unix_mode & libc::S_IFMT == libc::S_IFLNK => {
+                match read_link_target(data.as_ref()) {
+                    Ok(target) => (None, Some(target)),
+                    Err(e) => {
+                        warn!("Skipping zip symlink {}: {}", path, e);
+                        return None;
+                    }
+                }
+            }
+            content => (content, None),
+        };
+
+        Some(Entry {
+            path,
+            is_dir,
+            mode,
+            uid: self.uid.unwrap_or(1000),
+            gid: self.gid.unwrap_or(1000),
+            mtime,
+            content,
+            digest: None,
+            link_target,
+            special: None,
+            xattrs: Vec::new(),
+        })
+    }
+}
+
+fn read_link_target(data: &dyn ByteSource) -> io::Result<String> {
+    let mut target = vec![0; data.size().min(libc::PATH_MAX as u64) as usize];
+    let n = data.read_full_at(0, &mut target)?;
+    target.truncate(n);
+    Ok(String::from_utf8_lossy(&target).into_owned())
+}
+
+/// The stored bytes of one member, found via its local header on first read.
+struct MemberSource {
+    archive: Arc<dyn ByteSource>,
+    local_header_offset: u64,
+    size: u64,
+    data_offset: OnceLock<u64>,
+}
+
+impl MemberSource {
+    fn data_offset(&self) -> io::Result<u64> {
+        if let Some(&offset) = self.data_offset.get() {
+            return Ok(offset);
+        }
+
+        // the local header repeats the name and has its own extra fields
+        let mut header = [0; LOCAL_HEADER_SIZE];
+        self.archive
+            .read_full_at(self.local_header_offset, &mut header)?;
+        let mut cursor = Cursor::new(&header);
+        if cursor.u32()? != LOCAL_HEADER_SIGNATURE {
+            return Err(invalid("bad local header signature"));
+        }
+        cursor.skip(22)?;
+        let name_length = cursor.u16()? as u64;
+        let extra_length = cursor.u16()? as u64;
+
+        let offset =
+            self.local_header_offset + LOCAL_HEADER_SIZE as u64 + name_length + extra_length;
+        Ok(*self.data_offset.get_or_init(|| offset))
+    }
+}
+
+impl ByteSource for MemberSource {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        if offset >= self.size {
+            return Ok(0);
+        }
+        let len = buf.len().min((self.size - offset) as usize);
+        self.archive
+            .read_at(self.data_offset()? + offset, &mut buf[..len])
+    }
+
+    fn recipe(&self) -> Option<Recipe> {
+        Some(Recipe::Slice {
+            parent: Arc::clone(&self.archive),
+            offset: self.data_offset().ok()?,
+            size: self.size,
+        })
+    }
+}
+
+/// Convert an MS-DOS date and time, which has no time zone, as if it were UTC.
+fn dos_date_time(date: u16, time: u16) -> SystemTime {
+    let year = 1980 + (date >> 9) as i64;
+    let month = ((date >> 5) & 0x0f).clamp(1, 12) as i64;
+    let day = (date & 0x1f).max(1) as i64;
+    let hours = (time >> 11) as i64;
+    let minutes = ((time >> 5) & 0x3f) as i64;
+    let seconds = ((time & 0x1f) * 2) as i64;
+
+    let total = unix_time(year, month, day, hours, minutes, seconds);
+    SystemTime::UNIX_EPOCH + Duration::from_secs(total.max(0) as u64)
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("invalid zip archive: {message}"),
+    )
+}
+
+/// Little-endian reads over a byte slice.
+struct Cursor<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() - self.position
+    }
+
+    fn bytes(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.remaining() < n {
+            return Err(invalid("truncated record"));
+        }
+        let bytes = &self.data[self.position..self.position + n];
+        self.position += n;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, n: usize) -> io::Result<()> {
+        self.bytes(n).map(|_| ())
+    }
+
+    fn uint(&mut self, n: usize) -> io::Result<u32> {
+        let bytes = self.bytes(n)?;
+        Ok(bytes
+            .iter()
+            .rev()
+            .fold(0, |value, &b| (value << 8) | b as u32))
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tartarfs::source::MemorySource;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+    use zip::{CompressionMethod, ZipWriter};
+
+    #[test]
+    fn test_read_entries() -> io::Result<()> {
+        let text: String = (0..10_000).map(|i| format!("{i}\n")).collect();
+
+        let mut writer = ZipWriter::new(io::Cursor::new(Vec::new()));
+        let stored = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Stored)
+            .unix_permissions(0o600);
+        let deflated = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .large_file(true); // forces zip64 extra fields
+        writer.add_directory(
+            "docs/",
+            SimpleFileOptions::default().unix_permissions(0o750),
+        )?;
+        writer.start_file("docs/stored.txt", stored)?;
+        writer.write_all(b"stored")?;
+        writer.start_file("docs/deflated.txt", deflated)?;
+        writer.write_all(text.as_bytes())?;
+        writer.add_symlink("link", "docs/stored.txt", SimpleFileOptions::default())?;
+        let mut archive = b"#!/bin/sh\nexit 0\n".to_vec(); // prepended stub
+        archive.extend(writer.finish()?.into_inner());
+
+        let source: Arc<dyn ByteSource> = Arc::new(MemorySource::new(archive));
+        let entries = read_entries(&source)?;
+        let paths: Vec<_> = entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            ["docs/", "docs/stored.txt", "docs/deflated.txt", "link"]
+        );
+
+        assert!(entries[0].is_dir);
+        assert_eq!(entries[0].mode, 0o750);
+        assert_eq!(entries[1].mode, 0o600);
+
+        let read = |entry: &Entry| -> io::Result<Vec<u8>> {
+            let content = entry.content.as_ref().unwrap();
+            let mut buf = vec![0; content.size() as usize];
+            content.read_full_at(0, &mut buf)?;
+            Ok(buf)
+        };
+        assert_eq!(read(&entries[1])?, b"stored");
+        assert_eq!(read(&entries[2])?, text.as_bytes());
+        assert_eq!(entries[3].link_target.as_deref(), Some("docs/stored.txt"));
+        assert!(entries[3].content.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_central_directory_overflow() {
+        // a zip64 end record whose directory offset and size add up past u64::MAX
+        let mut archive = ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE
+            .to_le_bytes()
+            .to_vec();
+        archive.extend([0; 28]);
+        for value in [1, u64::MAX, u64::MAX - 1] {
+            archive.extend(value.to_le_bytes());
+        }
+        archive.extend(ZIP64_LOCATOR_SIGNATURE.to_le_bytes());
+        archive.extend([0; 4]);
+        archive.extend(0u64.to_le_bytes());
+        archive.extend(1u32.to_le_bytes());
+        archive.extend(END_OF_CENTRAL_DIRECTORY_SIGNATURE.to_le_bytes());
+        archive.extend([0; 6]);
+        archive.extend([0xff; 10]);
+        archive.extend([0; 2]);
+
+        let source: Arc<dyn ByteSource> = Arc::new(MemorySource::new(archive));
+        assert!(matches!(read_entries(&source), Err(e) if e.kind() == ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn test_dos_date_time() {
+        // 2024-02-29 13:37:42
+        let date = ((2024 - 1980) << 9) | (2 << 5) | 29;
+        let time = (13 << 11) | (37 << 5) | (42 / 2);
+        let expected = SystemTime::UNIX_EPOCH + Duration::from_secs(1_709_213_862);
+        assert_eq!(dos_date_time(date, time), expected);
+    }
+}
@@ -0,0 +1,214 @@
+use super::compression::{self, Codec, Decompress};
+use super::digest::VerifiedSource;
+use super::formats::{ArchiveFormat, Entry};
+use super::item::ArchiveItem;
+use super::source::ByteSource;
+use super::TartarFS;
+use std::collections::HashSet;
+use std::path::{Component, Path};
+use std::sync::Arc;
+use tracing::{debug, error, warn};
+
+impl TartarFS {
+    /// Index an archive, placing its members under `prefix`.
+    pub(super) fn index_archive(
+        &mut self,
+        source: Arc<dyn ByteSource>,
+        format: Arc<dyn ArchiveFormat>,
+        prefix: &str,
+        depth: u32,
+    ) {
+        match format.entries(&source, &self.decoding()) {
+            Ok(entries) => self.index_entries(entries, prefix, depth),
+            Err(e) => error!("Failed to read archive entries: {}", e),
+        }
+    }
+
+    /// Index an archive laid out as a directory, like an OCI image layout.
+    pub(super) fn index_directory(&mut self, path: &Path, format: Arc<dyn ArchiveFormat>) {
+        match format.dir_entries(path, &self.decoding()) {
+            Ok(entries) => self.index_entries(entries, "", 0),
+            Err(e) => error!("Failed to read archive entries: {}", e),
+        }
+    }
+
+    fn index_entries(&mut self, entries: Vec<Entry>, prefix: &str, depth: u32) {
+        // what the archive itself names, whichever order its members come in
+        let member_paths: HashSet<String> = entries
+            .iter()
+            .map(|entry| self.member_path(prefix, &entry.path))
+            .collect();
+        for mut entry in entries {
+            if self.options.verify_digests
+                && let (Some(content), Some(digest)) = (&entry.content, &entry.digest)
+            {
+                let verified = VerifiedSource::new(Arc::clone(content), digest.clone());
+                entry.content = Some(Arc::new(verified));
+            }
+
+            let entry_path_text = self.member_path(prefix, &entry.path);
+            if entry_path_text == prefix {
+                continue; // the archive root itself, e.g. `./`
+            }
+
+            let Some(content) = &entry.content else {
+                let included = match entry.is_dir {
+                    true => self.options.filter.is_dir_match(&entry_path_text),
+                    false => self.options.filter.is_match(&entry_path_text),
+                };
+                if included {
+                    debug!("Found archive item: {}", entry_path_text);
+                    self.insert_item(item_from_entry(entry_path_text, &entry));
+                }
+                continue;
+            };
+
+            if depth < self.options.nested_depth
+                && let Some(inner_entries) = self.open_nested(&entry_path_text, content)
+            {
+                debug!("Found nested archive: {}", entry_path_text);
+                if self.options.filter.is_match(&entry_path_text) {
+                    let mut item = item_from_entry(entry_path_text.clone(), &entry);
+                    item.is_dir = true;
+                    item.content = None;
+                    // let whoever can read the archive also traverse it
+                    item.mode |= (item.mode & 0o444) >> 2;
+                    self.insert_item(item);
+                }
+                self.index_entries(inner_entries, &entry_path_text, depth + 1);
+                continue;
+            }
+
+            if let Some(decompress) = self.options.decompress
+                && let Some((codec, stem)) = Codec::from_name(&entry_path_text)
+                && codec.sniff(content.as_ref())
+            {
+                // a file of that name in the archive itself wins
+                let decompressed_path = stem.to_string();
+                let mut inserted = false;
+                if !member_paths.contains(&decompressed_path)
+                    && !self.path_to_inode.contains_key(&decompressed_path)
+                    && self.options.filter.is_match(&decompressed_path)
+                {
+                    debug!("Found compressed archive item: {}", entry_path_text);
+                    match compression::decompress_sized(
+                        Arc::clone(content),
+                        codec,
+                        &self.options.spool,
+                    ) {
+                        Ok(decompressed) => {
+                            let mut item = item_from_entry(decompressed_path, &entry);
+                            item.content = Some(decompressed);
+                            self.insert_item(item);
+                            inserted = true;
+                        }
+                        Err(e) => warn!("Can't decompress {}: {}", entry_path_text, e),
+                    }
+                }
+                // keep the compressed one if it can't be shown decompressed
+                if inserted && decompress == Decompress::Replace {
+                    continue;
+                }
+            }
+
+            if !self.options.filter.is_match(&entry_path_text) {
+                debug!("Excluded archive item: {}", entry_path_text);
+                continue;
+            }
+            debug!("Found archive item: {}", entry_path_text);
+            self.insert_item(item_from_entry(entry_path_text, &entry));
+        }
+    }
+
+    /// If `name` looks like an archive and `content` parses as one, list its members.
+    fn open_nested(&self, name: &str, content: &Arc<dyn ByteSource>) -> Option<Vec<Entry>> {
+        let format = self.options.formats.detect_nested(name, content)?;
+        match format.entries(content, &self.decoding()) {
+            Ok(entries) => Some(entries),
+            Err(e) => {
+                debug!("Not treating {} as an archive: {}", name, e);
+                None
+            }
+        }
+    }
+
+    /// Where a member of an archive placed under `prefix` is indexed;
+    /// `prefix` itself for the archive root.
+    fn member_path(&self, prefix: &str, raw: &str) -> String {
+        match (prefix, self.index_path(raw)) {
+            (prefix, path) if path.is_empty() => prefix.to_string(),
+            ("", path) => path,
+            (prefix, path) => format!("{prefix}/{path}"),
+        }
+    }
+
+    /// Turn a raw archive member path into the form it's indexed under.
+    pub(super) fn index_path(&self, raw: &str) -> String {
+        let path = normalize_path(raw);
+        match self.options.normalization {
+            Some(form) => form.apply(&path),
+            None => path,
+        }
+    }
+
+    /// Add an item to the index, creating any missing parent directories.
+    /// An item with the same path as an earlier one replaces it in place.
+    pub(super) fn insert_item(&mut self, item: ArchiveItem) {
+        let entry_path_text = item.name.clone();
+
+        if let Some(&inode) = self.path_to_inode.get(&entry_path_text) {
+            self.inode_to_item.insert(inode, item);
+            return;
+        }
+
+        let inode = self.next_inode;
+        self.next_inode += 1;
+        self.inode_to_item.insert(inode, item);
+        self.path_to_inode.insert(entry_path_text.clone(), inode);
+
+        let entry_path = Path::new(&entry_path_text);
+        for ancestor in entry_path.ancestors().skip(1) {
+            let ancestor_text = ancestor.to_string_lossy().to_string();
+            if !self.path_to_inode.contains_key(&ancestor_text) && !ancestor_text.is_empty() {
+                let parent_ino = self.next_inode;
+                self.next_inode += 1;
+
+                let parent_item = ArchiveItem::directory(ancestor_text.clone());
+                self.inode_to_item.insert(parent_ino, parent_item);
+                self.path_to_inode.insert(ancestor_text, parent_ino);
+            }
+        }
+    }
+}
+
+fn item_from_entry(name: String, entry: &Entry) -> ArchiveItem {
+    ArchiveItem {
+        name,
+        is_dir: entry.is_dir,
+        content: entry.content.clone(),
+        link_target: entry.link_target.clone(),
+        special: entry.special,
+        xattrs: entry.xattrs.clone(),
+        mode: (entry.mode & 0o7777) as u16,
+        uid: entry.uid,
+        gid: entry.gid,
+        atime: entry.mtime, // use mtime for all timestamps since that's what we got
+        mtime: entry.mtime,
+        ctime: entry.mtime,
+    }
+}
+
+/// Turn an archive member path like `./docs//guide/` into `docs/guide`.
+pub fn normalize_path(raw: &str) -> String {
+    let mut parts: Vec<String> = Vec::new();
+    for component in Path::new(raw).components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_string_lossy().into_owned()),
+            Component::ParentDir => {
+                parts.pop();
+            }
+            Component::RootDir | Component::CurDir | Component::Prefix(_) => {}
+        }
+    }
+    parts.join("/")
+}
@@ -0,0 +1,649 @@
+use super::compression::{BgzfBlock, BgzfSource, Codec, DecompressedSource, SpooledSource};
+use super::digest::{Digest, VerifiedSource};
+use super::formats::Special;
+use super::item::ArchiveItem;
+use super::source::{
+    self, ByteSource, ConcatSource, MemorySource, Recipe, SliceSource, ZeroSource,
+};
+use super::{Options, TartarFS};
+use crate::inode::Inode;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+
+/// Bumped whenever what gets saved changes, so older indexes are ignored.
+const INDEX_VERSION: u32 = 1;
+
+/// How much of the start and of the end of an archive goes into its key.
+const SAMPLE_SIZE: u64 = 1 << 20;
+
+/// Where the index of an archive is saved after indexing it, so that the
+/// next mount of the same archive loads it instead of reading the archive.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum IndexCache {
+    /// Next to the archive, as `<archive>.tartarfs-index`.
+    Sidecar,
+    /// In this directory, named after the archive's key, which also works
+    /// for archives at a URL or in a read-only directory.
+    Dir(PathBuf),
+}
+
+impl IndexCache {
+    /// Where the index of the archive at `location` with this key goes.
+    fn path(&self, location: &str, key: &str) -> io::Result<PathBuf> {
+        match self {
+            IndexCache::Sidecar if source::is_remote(location) => Err(Error::new(
+                ErrorKind::Unsupported,
+                "archives at a URL have no sidecar index, use an index directory",
+            )),
+            IndexCache::Sidecar => Ok(PathBuf::from(format!("{location}.tartarfs-index"))),
+            IndexCache::Dir(dir) => Ok(dir.join(format!("tartarfs-{key}.index"))),
+        }
+    }
+}
+
+/// An index as saved: every item with the sources its contents are read
+/// from, down to the archive itself.
+///
+/// Of compressed streams that is their decompressed size, the blocks of
+/// BGZF and the members decoded apart with `--jobs`, but no points to resume
+/// decoding from within a stream, which is decoded from its start again.
+#[derive(Serialize, Deserialize)]
+struct SavedIndex {
+    version: u32,
+    key: String,
+    /// Each source after the ones it is made of; the archive comes first.
+    sources: Vec<SavedSource>,
+    items: Vec<SavedItem>,
+    next_inode: u64,
+}
+
+/// A [`Recipe`] with the sources in it referred to by their place in
+/// [`SavedIndex::sources`].
+#[derive(Serialize, Deserialize)]
+enum SavedSource {
+    Archive,
+    Memory(Vec<u8>),
+    Zero {
+        size: u64,
+    },
+    Slice {
+        parent: usize,
+        offset: u64,
+        size: u64,
+    },
+    Concat(Vec<usize>),
+    Decompressed {
+        compressed: usize,
+        codec: Codec,
+        size: Option<u64>,
+    },
+    Spooled {
+        compressed: usize,
+        codec: Codec,
+    },
+    Bgzf {
+        compressed: usize,
+        blocks: Option<Vec<BgzfBlock>>,
+    },
+    Verified {
+        inner: usize,
+        digest: Digest,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedItem {
+    inode: u64,
+    name: String,
+    is_dir: bool,
+    content: Option<usize>,
+    link_target: Option<String>,
+    special: Option<Special>,
+    xattrs: Vec<(String, Vec<u8>)>,
+    mode: u16,
+    uid: u32,
+    gid: u32,
+    atime: SystemTime,
+    mtime: SystemTime,
+    ctime: SystemTime,
+}
+
+impl TartarFS {
+    /// Mount the archive at `location` from its saved index if it has one
+    /// that is up to date, or index it and save the index for next time.
+    pub(super) fn from_index_cache(
+        location: &str,
+        archive: Arc<dyn ByteSource>,
+        cache: &IndexCache,
+        options: Options,
+    ) -> Self {
+        let path = key(archive.as_ref(), &options).and_then(|key| {
+            let path = cache.path(location, &key)?;
+            Ok((key, path))
+        });
+        let (key, path) = match path {
+            Ok(found) => found,
+            Err(e) => {
+                warn!("Not using a saved index: {}", e);
+                return Self::from_source(archive, options);
+            }
+        };
+
+        match Self::load_index(&path, &key, &archive, options.clone()) {
+            Ok(fs) => {
+                info!("Loaded index from {}", path.display());
+                return fs.finish();
+            }
+            Err(e) => debug!("No saved index at {}: {}", path.display(), e),
+        }
+
+        let mut fs = Self::empty(options);
+        fs.index_root(Arc::clone(&archive));
+        match fs.save_index(&path, &key, &archive) {
+            Ok(()) => info!("Saved index to {}", path.display()),
+            Err(e) => warn!("Failed to save index to {}: {}", path.display(), e),
+        }
+        fs.finish()
+    }
+
+    /// Index the archive at `location` ahead of time, unless its saved index
+    /// is up to date already, returning where the index is.
+    pub fn write_index(location: &str, options: Options) -> io::Result<PathBuf> {
+        if Path::new(location).is_dir() {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "archives laid out as directories aren't indexed",
+            ));
+        }
+        let cache = options.index_cache.clone().unwrap_or(IndexCache::Sidecar);
+        let archive = source::open(location)?;
+        let key = key(archive.as_ref(), &options)?;
+        let path = cache.path(location, &key)?;
+        if Self::load_index(&path, &key, &archive, options.clone()).is_ok() {
+            info!("Index at {} is up to date", path.display());
+            return Ok(path);
+        }
+
+        let mut fs = Self::empty(options);
+        fs.index_root(Arc::clone(&archive));
+        fs.save_index(&path, &key, &archive)?;
+        Ok(path)
+    }
+
+    /// Save the index as it is before [`TartarFS::finish`], which gets
+    /// applied again on loading.
+    fn save_index(&self, path: &Path, key: &str, archive: &Arc<dyn ByteSource>) -> io::Result<()> {
+        let mut sources = Sources::new(archive);
+        let mut items = Vec::with_capacity(self.inode_to_item.len());
+        for (&inode, item) in &self.inode_to_item {
+            let content = match &item.content {
+                Some(content) => Some(sources.add(content).ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::Unsupported,
+                        format!("{} is read in a way that can't be saved", item.name),
+                    )
+                })?),
+                None => None,
+            };
+            items.push(SavedItem {
+                inode: inode.0,
+                name: item.name.clone(),
+                is_dir: item.is_dir,
+                content,
+                link_target: item.link_target.clone(),
+                special: item.special,
+                xattrs: item.xattrs.clone(),
+                mode: item.mode,
+                uid: item.uid,
+                gid: item.gid,
+                atime: item.atime,
+                mtime: item.mtime,
+                ctime: item.ctime,
+            });
+        }
+        items.sort_by_key(|item| item.inode);
+        let saved = SavedIndex {
+            version: INDEX_VERSION,
+            key: key.to_string(),
+            sources: sources.saved,
+            items,
+            next_inode: self.next_inode.0,
+        };
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // write it whole before it replaces an older one
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(format!(".{}.partial", std::process::id()));
+        let written = File::create(&partial).and_then(|file| {
+            let mut encoder = zstd::Encoder::new(BufWriter::new(file), 3)?;
+            serde_json::to_writer(&mut encoder, &saved)?;
+            encoder.finish()?.flush()
+        });
+        match written {
+            Ok(()) => fs::rename(&partial, path),
+            Err(e) => {
+                let _ = fs::remove_file(&partial);
+                Err(e)
+            }
+        }
+    }
+
+    /// Load a saved index, as it was before [`TartarFS::finish`].
+    fn load_index(
+        path: &Path,
+        key: &str,
+        archive: &Arc<dyn ByteSource>,
+        options: Options,
+    ) -> io::Result<Self> {
+        let decoder = zstd::Decoder::new(BufReader::new(File::open(path)?))?;
+        let saved: SavedIndex = serde_json::from_reader(decoder)?;
+        if saved.version != INDEX_VERSION || saved.key != key {
+            return Err(Error::new(ErrorKind::InvalidData, "out of date"));
+        }
+
+        let mut sources: Vec<Arc<dyn ByteSource>> = Vec::with_capacity(saved.sources.len());
+        for source in saved.sources {
+            let source = load_source(source, &sources, archive, &options)
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "bad source"))?;
+            sources.push(source);
+        }
+
+        let mut fs = Self::empty(options);
+        fs.path_to_inode.clear();
+        fs.inode_to_item.clear();
+        for item in saved.items {
+            let content = match item.content {
+                Some(id) => {
+                    Some(Arc::clone(sources.get(id).ok_or_else(|| {
+                        Error::new(ErrorKind::InvalidData, "bad source")
+                    })?))
+                }
+                None => None,
+            };
+            let inode = Inode(item.inode);
+            fs.path_to_inode.insert(item.name.clone(), inode);
+            fs.inode_to_item.insert(
+                inode,
+                ArchiveItem {
+                    name: item.name,
+                    is_dir: item.is_dir,
+                    content,
+                    link_target: item.link_target,
+                    special: item.special,
+                    xattrs: item.xattrs,
+                    mode: item.mode,
+                    uid: item.uid,
+                    gid: item.gid,
+                    atime: item.atime,
+                    mtime: item.mtime,
+                    ctime: item.ctime,
+                },
+            );
+        }
+        fs.next_inode = Inode(saved.next_inode);
+        Ok(fs)
+    }
+}
+
+/// Sources being saved, each once however many items are read from it.
+struct Sources {
+    saved: Vec<SavedSource>,
+    ids: HashMap<*const (), usize>,
+}
+
+impl Sources {
+    fn new(archive: &Arc<dyn ByteSource>) -> Self {
+        Self {
+            saved: vec![SavedSource::Archive],
+            ids: HashMap::from([(Arc::as_ptr(archive) as *const (), 0)]),
+        }
+    }
+
+    /// Save `source` and what it is made of, or `None` if any of it has no
+    /// [`Recipe`].
+    fn add(&mut self, source: &Arc<dyn ByteSource>) -> Option<usize> {
+        let pointer = Arc::as_ptr(source) as *const ();
+        if let Some(&id) = self.ids.get(&pointer) {
+            return Some(id);
+        }
+        let saved = match source.recipe()? {
+            Recipe::Memory(data) => SavedSource::Memory(data),
+            Recipe::Zero { size } => SavedSource::Zero { size },
+            Recipe::Slice {
+                parent,
+                offset,
+                size,
+            } => SavedSource::Slice {
+                parent: self.add(&parent)?,
+                offset,
+                size,
+            },
+            Recipe::Concat(parts) => SavedSource::Concat(
+                parts
+                    .iter()
+                    .map(|part| self.add(part))
+                    .collect::<Option<_>>()?,
+            ),
+            Recipe::Decompressed {
+                compressed,
+                codec,
+                size,
+            } => SavedSource::Decompressed {
+                compressed: self.add(&compressed)?,
+                codec,
+                size,
+            },
+            Recipe::Spooled { compressed, codec } => SavedSource::Spooled {
+                compressed: self.add(&compressed)?,
+                codec,
+            },
+            Recipe::Bgzf { compressed, blocks } => SavedSource::Bgzf {
+                compressed: self.add(&compressed)?,
+                blocks,
+            },
+            Recipe::Verified { inner, digest } => SavedSource::Verified {
+                inner: self.add(&inner)?,
+                digest,
+            },
+        };
+        let id = self.saved.len();
+        self.saved.push(saved);
+        self.ids.insert(pointer, id);
+        Some(id)
+    }
+}
+
+/// Make a saved source again from the ones loaded before it.
+fn load_source(
+    saved: SavedSource,
+    loaded: &[Arc<dyn ByteSource>],
+    archive: &Arc<dyn ByteSource>,
+    options: &Options,
+) -> Option<Arc<dyn ByteSource>> {
+    let get = |id: usize| loaded.get(id).cloned();
+    Some(match saved {
+        SavedSource::Archive => Arc::clone(archive),
+        SavedSource::Memory(data) => Arc::new(MemorySource::new(data)),
+        SavedSource::Zero { size } => Arc::new(ZeroSource::new(size)),
+        SavedSource::Slice {
+            parent,
+            offset,
+            size,
+        } => Arc::new(SliceSource::new(get(parent)?, offset, size)),
+        SavedSource::Concat(parts) => Arc::new(ConcatSource::new(
+            parts.into_iter().map(get).collect::<Option<_>>()?,
+        )),
+        SavedSource::Decompressed {
+            compressed,
+            codec,
+            size: Some(size),
+        } => Arc::new(DecompressedSource::with_size(get(compressed)?, codec, size)),
+        SavedSource::Decompressed {
+            compressed,
+            codec,
+            size: None,
+        } => Arc::new(DecompressedSource::new(get(compressed)?, codec)),
+        SavedSource::Spooled { compressed, codec } => Arc::new(SpooledSource::new(
+            get(compressed)?,
+            codec,
+            options.spool.clone(),
+        )),
+        SavedSource::Bgzf {
+            compressed,
+            blocks: Some(blocks),
+        } => Arc::new(BgzfSource::with_blocks(get(compressed)?, blocks)),
+        SavedSource::Bgzf {
+            compressed,
+            blocks: None,
+        } => Arc::new(BgzfSource::new(get(compressed)?)),
+        SavedSource::Verified { inner, digest } => {
+            Arc::new(VerifiedSource::new(get(inner)?, digest))
+        }
+    })
+}
+
+/// What identifies an archive and the options it was indexed with: its
+/// size, modification time and a hash of its start and end, which together
+/// tell a changed archive apart without reading all of it, unless it changed
+/// in the middle only and got its modification time back. Hashing all of
+/// it with [`Options::index_full_hash`] tells those apart too.
+///
+/// Flattening and case folding are left out, as they get applied to loaded
+/// indexes anew, as are the options for how streams get decoded.
+fn key(archive: &dyn ByteSource, options: &Options) -> io::Result<String> {
+    let size = archive.size();
+    let modified = archive
+        .modified()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+
+    let mut hasher = Sha256::new();
+    hasher.update(format!(
+        "{INDEX_VERSION} {size} {} {} {:?} {:?} {} {:?} {} {:?}\n",
+        modified.as_secs(),
+        modified.subsec_nanos(),
+        options.filter,
+        options.normalization,
+        options.nested_depth,
+        options.decompress,
+        options.verify_digests,
+        options.formats,
+    ));
+    let samples = if options.index_full_hash {
+        (0..size)
+            .step_by(SAMPLE_SIZE as usize)
+            .map(|offset| (offset, SAMPLE_SIZE.min(size - offset)))
+            .collect()
+    } else {
+        let tail = size.saturating_sub(SAMPLE_SIZE).max(SAMPLE_SIZE);
+        vec![
+            (0, SAMPLE_SIZE.min(size)),
+            (tail, size.saturating_sub(tail)),
+        ]
+    };
+    for (offset, length) in samples {
+        let mut sample = vec![0; length as usize];
+        let n = archive.read_full_at(offset, &mut sample)?;
+        hasher.update(&sample[..n]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tartarfs::compression::Spool;
+    use crate::tartarfs::source::FileSource;
+    use crate::PathFilter;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use tempfile::TempDir;
+
+    fn tarball(dir: &Path) -> io::Result<PathBuf> {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::fast()));
+        for (name, data) in [("a.txt", &b"alpha"[..]), ("docs/b.txt", b"bravo")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o640);
+            header.set_mtime(1_700_000_000);
+            builder.append_data(&mut header, name, data)?;
+        }
+        let path = dir.join("files.tar.gz");
+        fs::write(&path, builder.into_inner()?.finish()?)?;
+        Ok(path)
+    }
+
+    fn read(fs: &TartarFS, path: &str) -> io::Result<Vec<u8>> {
+        let item = &fs.inode_to_item[&fs.path_to_inode[path]];
+        let content = item.content.as_ref().expect("a file");
+        let mut data = vec![0; content.size() as usize];
+        content.read_full_at(0, &mut data)?;
+        Ok(data)
+    }
+
+    #[test]
+    fn test_round_trip() -> io::Result<()> {
+        let dir = TempDir::new()?;
+        let location = tarball(dir.path())?.to_string_lossy().into_owned();
+        let archive = source::open(&location)?;
+        let options = Options::default();
+        let key = key(archive.as_ref(), &options)?;
+        let path = IndexCache::Sidecar.path(&location, &key)?;
+        assert_eq!(path, PathBuf::from(format!("{location}.tartarfs-index")));
+
+        let mut indexed = TartarFS::empty(options.clone());
+        indexed.index_root(Arc::clone(&archive));
+        indexed.save_index(&path, &key, &archive)?;
+
+        let loaded = TartarFS::load_index(&path, &key, &archive, options)?;
+        let mut paths: Vec<_> = loaded.path_to_inode.keys().cloned().collect();
+        paths.sort();
+        assert_eq!(paths, ["", "a.txt", "docs", "docs/b.txt"]);
+        assert_eq!(loaded.next_inode, indexed.next_inode);
+        for (path, inode) in &indexed.path_to_inode {
+            assert_eq!(loaded.path_to_inode.get(path), Some(inode));
+        }
+        assert_eq!(read(&loaded, "docs/b.txt")?, b"bravo");
+        let item = &loaded.inode_to_item[&loaded.path_to_inode["a.txt"]];
+        assert_eq!(item.mode, 0o640);
+        assert_eq!(
+            item.mtime,
+            UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000)
+        );
+
+        // a key for other options or another archive doesn't load
+        assert!(TartarFS::load_index(&path, "other", &archive, Options::default()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_members() -> io::Result<()> {
+        let dir = TempDir::new()?;
+        let mut builder = tar::Builder::new(Vec::new());
+        for name in ["a.txt", "b.txt"] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(5);
+            builder.append_data(&mut header, name, &b"hello"[..])?;
+        }
+        let data = builder.into_inner()?;
+        // one gzip member per file, and one for the end of the archive
+        let mut gzip = Vec::new();
+        for piece in data.chunks(1024) {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+            encoder.write_all(piece)?;
+            gzip.extend(encoder.finish()?);
+        }
+        let location = dir.path().join("files.tar.gz");
+        fs::write(&location, gzip)?;
+        let location = location.to_string_lossy().into_owned();
+        let archive = source::open(&location)?;
+        let options = Options {
+            jobs: 4,
+            ..Options::default()
+        };
+        let key = key(archive.as_ref(), &options)?;
+        let path = IndexCache::Sidecar.path(&location, &key)?;
+
+        let mut indexed = TartarFS::empty(options.clone());
+        indexed.index_root(Arc::clone(&archive));
+        indexed.save_index(&path, &key, &archive)?;
+
+        // the file is read from its own member, without decoding those before it
+        let loaded = TartarFS::load_index(&path, &key, &archive, options)?;
+        let content = loaded.inode_to_item[&loaded.path_to_inode["b.txt"]]
+            .content
+            .as_ref()
+            .expect("a file");
+        let Some(Recipe::Slice { parent, .. }) = content.recipe() else {
+            panic!("a slice of the decompressed archive");
+        };
+        let Some(Recipe::Concat(members)) = parent.recipe() else {
+            panic!("the decompressed members");
+        };
+        assert_eq!(members.len(), data.len().div_ceil(1024));
+        for member in members {
+            assert!(matches!(
+                member.recipe(),
+                Some(Recipe::Decompressed { size: Some(_), .. })
+            ));
+        }
+        assert_eq!(read(&loaded, "b.txt")?, b"hello");
+        Ok(())
+    }
+
+    #[test]
+    fn test_key() -> io::Result<()> {
+        let dir = TempDir::new()?;
+        let path = tarball(dir.path())?;
+        let archive: Arc<dyn ByteSource> = Arc::new(FileSource::open(&path)?);
+        let options = Options::default();
+        let original = key(archive.as_ref(), &options)?;
+        assert_eq!(key(archive.as_ref(), &options)?, original);
+
+        // options that don't change what gets indexed keep the key
+        let mut other = options.clone();
+        other.case_insensitive = true;
+        other.jobs = 4;
+        other.spool = Spool {
+            keep: true,
+            ..Spool::default()
+        };
+        assert_eq!(key(archive.as_ref(), &other)?, original);
+
+        let mut other = options.clone();
+        other.filter = PathFilter::new(["docs/**"], Vec::<String>::new()).unwrap();
+        assert_ne!(key(archive.as_ref(), &other)?, original);
+        let mut other = options.clone();
+        other.nested_depth = 1;
+        assert_ne!(key(archive.as_ref(), &other)?, original);
+
+        let mut data = fs::read(&path)?;
+        data.push(0);
+        fs::write(&path, data)?;
+        let changed: Arc<dyn ByteSource> = Arc::new(FileSource::open(&path)?);
+        assert_ne!(key(changed.as_ref(), &options)?, original);
+        Ok(())
+    }
+
+    #[test]
+    fn test_full_hash_key() -> io::Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("big.bin");
+        let mut data = vec![0; 3 * SAMPLE_SIZE as usize];
+        fs::write(&path, &data)?;
+        let modified = fs::metadata(&path)?.modified()?;
+        let full = Options {
+            index_full_hash: true,
+            ..Options::default()
+        };
+        let keys = |options: &Options| {
+            let archive = FileSource::open(&path)?;
+            key(&archive, options)
+        };
+        let (sampled, hashed) = (keys(&Options::default())?, keys(&full)?);
+
+        // changed in the middle, with the modification time put back
+        data[SAMPLE_SIZE as usize + 1] = 1;
+        fs::write(&path, &data)?;
+        File::options()
+            .write(true)
+            .open(&path)?
+            .set_modified(modified)?;
+        assert_eq!(keys(&Options::default())?, sampled);
+        assert_ne!(keys(&full)?, hashed);
+        Ok(())
+    }
+}
@@ -1,12 +1,20 @@
+use super::formats::Special;
+use super::source::ByteSource;
 use crate::inode::Inode;
 use fuser::{FileAttr, FileType};
+use std::sync::Arc;
 use std::time::SystemTime;
 
 pub struct ArchiveItem {
     pub name: String,
     pub is_dir: bool,
-    pub size: u64,
-    pub offset: u64,
+    pub content: Option<Arc<dyn ByteSource>>,
+    /// Where a symbolic link points.
+    pub link_target: Option<String>,
+    /// Set for device nodes, FIFOs and sockets.
+    pub special: Option<Special>,
+    /// Extended attributes, like `user.comment`, in archive order.
+    pub xattrs: Vec<(String, Vec<u8>)>,
     pub mode: u16,
     pub uid: u32,
     pub gid: u32,
@@ -16,16 +24,67 @@ pub struct ArchiveItem {
 }
 
 impl ArchiveItem {
-    pub fn get_file_attributes(&self, inode: Inode) -> FileAttr {
-        let kind = if self.is_dir {
+    /// A directory that has no entry of its own in the archive,
+    /// like the root or the parents of `a/b/c.txt` in a sparse tar.
+    pub fn directory(name: String) -> Self {
+        let now = SystemTime::now();
+        ArchiveItem {
+            name,
+            is_dir: true,
+            content: None,
+            link_target: None,
+            special: None,
+            xattrs: Vec::new(),
+            mode: 0o755,
+            uid: 1000,
+            gid: 1000,
+            atime: now,
+            mtime: now,
+            ctime: now,
+        }
+    }
+
+    /// Size of the contents, which for compressed members means decoding them once.
+    pub fn size(&self) -> u64 {
+        match (&self.content, &self.link_target) {
+            (Some(content), _) => content.size(),
+            (None, Some(target)) => target.len() as u64,
+            (None, None) => 0,
+        }
+    }
+
+    pub fn kind(&self) -> FileType {
+        if self.is_dir {
             FileType::Directory
+        } else if self.link_target.is_some() {
+            FileType::Symlink
         } else {
-            FileType::RegularFile
-        };
+            match self.special {
+                Some(Special::CharDevice { .. }) => FileType::CharDevice,
+                Some(Special::BlockDevice { .. }) => FileType::BlockDevice,
+                Some(Special::Fifo) => FileType::NamedPipe,
+                Some(Special::Socket) => FileType::Socket,
+                None => FileType::RegularFile,
+            }
+        }
+    }
+
+    /// The device number in the kernel's encoding, or 0 for other files.
+    pub fn rdev(&self) -> u32 {
+        match self.special {
+            Some(Special::CharDevice { major, minor } | Special::BlockDevice { major, minor }) => {
+                (minor & 0xff) | ((major & 0xfff) << 8) | ((minor & !0xff) << 12)
+            }
+            _ => 0,
+        }
+    }
+
+    pub fn get_file_attributes(&self, inode: Inode) -> FileAttr {
+        let kind = self.kind();
 
         FileAttr {
             ino: inode.0,
-            size: self.size,
+            size: self.size(),
             blocks: 1,
             atime: self.atime,
             mtime: self.mtime,
@@ -36,7 +95,7 @@ impl ArchiveItem {
             nlink: 1,
             uid: self.uid,
             gid: self.gid,
-            rdev: 0,
+            rdev: self.rdev(),
             blksize: 512,
             flags: 0,
         }
@@ -1,133 +1,154 @@
+mod casefold;
+pub mod compression;
+pub mod digest;
+mod filter;
+mod flatten;
+pub mod formats;
+mod index;
+mod index_cache;
 mod item;
+mod normalization;
 mod operations;
+mod options;
+pub mod source;
 
 use crate::inode::Inode;
-use fuser::{Filesystem, ReplyAttr, ReplyData, ReplyDirectory, ReplyEmpty, ReplyEntry, Request};
+use fuser::{
+    Filesystem, ReplyAttr, ReplyData, ReplyDirectory, ReplyEmpty, ReplyEntry, ReplyXattr, Request,
+};
 use item::ArchiveItem;
+use source::ByteSource;
+use std::borrow::Cow;
 use std::collections::HashMap;
-use std::fs::File;
-use std::path::{Path, PathBuf};
-use std::time::{Duration, SystemTime};
-use tar::Archive;
-use tracing::{debug, error, info};
+use std::path::Path;
+use std::sync::Arc;
+use tracing::{error, info};
+
+pub use compression::{Decompress, Spool};
+pub use filter::PathFilter;
+pub use flatten::Collisions;
+pub use index_cache::IndexCache;
+pub use normalization::Normalization;
+pub use options::Options;
 
 pub struct TartarFS {
-    archive_path: PathBuf,
+    options: Options,
     path_to_inode: HashMap<String, Inode>,
+    folded_path_to_inode: HashMap<String, Inode>,
     inode_to_item: HashMap<Inode, ArchiveItem>,
     next_inode: Inode,
 }
 
 impl TartarFS {
     pub fn new(archive_path: String) -> Self {
+        Self::with_options(archive_path, Options::default())
+    }
+
+    /// Mount the archive at a local path or an `http(s)://` URL, or an
+    /// archive laid out as a local directory, like an OCI image layout.
+    pub fn with_options(archive_path: String, options: Options) -> Self {
+        let path = Path::new(&archive_path);
+        if path.is_dir() {
+            let mut fs = Self::empty(options);
+            match fs.options.formats.detect_dir(path) {
+                Some(format) => {
+                    info!("Archive format: {}", format.name());
+                    fs.index_directory(path, format);
+                }
+                None => error!("Not an archive directory: {}", archive_path),
+            }
+            return fs.finish();
+        }
+        match source::open(&archive_path) {
+            Ok(archive) => match options.index_cache.clone() {
+                Some(cache) => Self::from_index_cache(&archive_path, archive, &cache, options),
+                None => Self::from_source(archive, options),
+            },
+            Err(e) => {
+                error!("Failed to open archive {}: {}", archive_path, e);
+                Self::build(None, options)
+            }
+        }
+    }
+
+    /// Mount an archive from any byte source, e.g. a [`source::MemorySource`]
+    /// or one of your own.
+    pub fn from_source(archive: Arc<dyn ByteSource>, options: Options) -> Self {
+        Self::build(Some(archive), options)
+    }
+
+    fn build(archive: Option<Arc<dyn ByteSource>>, options: Options) -> Self {
+        let mut fs = Self::empty(options);
+        if let Some(archive) = archive {
+            fs.index_root(archive);
+        }
+        fs.finish()
+    }
+
+    /// Index the archive that gets mounted, in whichever format it is.
+    fn index_root(&mut self, archive: Arc<dyn ByteSource>) {
+        let format = self.options.formats.detect(&archive);
+        info!("Archive format: {}", format.name());
+        self.index_archive(archive, format, "", 0);
+    }
+
+    /// A filesystem with just the root directory in it.
+    fn empty(options: Options) -> Self {
         let root_inode = Inode(1);
         let mut fs = TartarFS {
-            archive_path: PathBuf::from(&archive_path),
+            options,
             path_to_inode: HashMap::new(),
+            folded_path_to_inode: HashMap::new(),
             inode_to_item: HashMap::new(),
             next_inode: root_inode + 1,
         };
 
-        let now = SystemTime::now();
-        let root_item = ArchiveItem {
-            name: "".into(),
-            is_dir: true,
-            size: 0,
-            offset: 0,
-            mode: 0o755,
-            uid: 1000,
-            gid: 1000,
-            atime: now,
-            mtime: now,
-            ctime: now,
-        };
+        let root_item = ArchiveItem::directory("".into());
         fs.inode_to_item.insert(root_inode, root_item);
         fs.path_to_inode.insert("".into(), root_inode);
+        fs
+    }
 
-        if let Ok(archive_file) = File::open(&archive_path) {
-            let mut archive = Archive::new(archive_file);
-            if let Ok(entries) = archive.entries() {
-                for entry_result in entries {
-                    if let Ok(entry) = entry_result {
-                        if let Ok(path) = entry.path() {
-                            let entry_path_text = path.to_string_lossy().to_string();
-                            debug!("Found archive item: {}", entry_path_text);
-
-                            let inode = fs.next_inode;
-                            fs.next_inode += 1;
-
-                            let header = entry.header();
-                            let size = header.size().unwrap_or(0);
-                            let is_dir = header.entry_type().is_dir();
-                            let offset = entry.raw_file_position();
-                            let mode = header.mode().unwrap_or(if is_dir { 0o755 } else { 0o644 });
-                            let uid = header.uid().unwrap_or(1000);
-                            let gid = header.gid().unwrap_or(1000);
-
-                            let mtime = header
-                                .mtime()
-                                .ok()
-                                .and_then(|t| {
-                                    SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(t))
-                                })
-                                .unwrap_or_else(SystemTime::now);
-
-                            let item = ArchiveItem {
-                                name: entry_path_text.clone(),
-                                is_dir,
-                                size,
-                                offset,
-                                mode: mode.try_into().unwrap(),
-                                uid: uid.try_into().unwrap(),
-                                gid: gid.try_into().unwrap(),
-                                atime: mtime, // use mtime for all timestamps since that's what we got
-                                mtime,
-                                ctime: mtime,
-                            };
-
-                            fs.inode_to_item.insert(inode, item);
-                            fs.path_to_inode.insert(entry_path_text.clone(), inode);
-
-                            let entry_path = Path::new(&entry_path_text);
-                            for ancestor in entry_path.ancestors().skip(1) {
-                                let ancestor_text = ancestor.to_string_lossy().to_string();
-                                if !fs.path_to_inode.contains_key(&ancestor_text)
-                                    && !ancestor_text.is_empty()
-                                {
-                                    let parent_ino = fs.next_inode;
-                                    fs.next_inode += 1;
-
-                                    let now = SystemTime::now();
-                                    let parent_item = ArchiveItem {
-                                        name: ancestor_text.clone(),
-                                        is_dir: true,
-                                        size: 0,
-                                        offset: 0,
-                                        mode: 0o755,
-                                        uid: 1000,
-                                        gid: 1000,
-                                        atime: now,
-                                        mtime: now,
-                                        ctime: now,
-                                    };
-
-                                    fs.inode_to_item.insert(parent_ino, parent_item);
-                                    fs.path_to_inode.insert(ancestor_text, parent_ino);
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        } else {
-            error!("Failed to open archive: {}", archive_path);
+    /// The options for decoding compressed streams found while indexing.
+    fn decoding(&self) -> compression::Decoding {
+        compression::Decoding {
+            spool: self.options.spool.clone(),
+            jobs: self.options.jobs,
+        }
+    }
+
+    /// Apply the options that work on the whole index once it's complete.
+    fn finish(mut self) -> Self {
+        if let Some(collisions) = self.options.flatten {
+            self.flatten(collisions);
+        }
+        if self.options.case_insensitive {
+            self.build_case_folded_index();
         }
 
         info!(
             "Initialized filesystem with {} items",
-            fs.inode_to_item.len()
+            self.inode_to_item.len()
         );
-        fs
+        self
+    }
+
+    /// Resolve a path to its inode, honouring the lookup options.
+    fn find_inode(&self, path: &str) -> Option<Inode> {
+        let path = match self.options.normalization {
+            Some(form) => Cow::Owned(form.apply(path)),
+            None => Cow::Borrowed(path),
+        };
+        if let Some(&inode) = self.path_to_inode.get(path.as_ref()) {
+            return Some(inode);
+        }
+        if self.options.case_insensitive {
+            return self
+                .folded_path_to_inode
+                .get(&casefold::fold_case(&path))
+                .copied();
+        }
+        None
     }
 }
 
@@ -151,6 +172,19 @@ impl Filesystem for TartarFS {
         self.readdir_impl(req, ino, fh, offset, reply);
     }
 
+    fn readlink(&mut self, req: &Request, ino: u64, reply: ReplyData) {
+        self.readlink_impl(req, ino, reply);
+    }
+
+    #[rustfmt::skip]
+    fn getxattr(&mut self, req: &Request, ino: u64, name: &std::ffi::OsStr, size: u32, reply: ReplyXattr) {
+        self.getxattr_impl(req, ino, name, size, reply);
+    }
+
+    fn listxattr(&mut self, req: &Request, ino: u64, size: u32, reply: ReplyXattr) {
+        self.listxattr_impl(req, ino, size, reply);
+    }
+
     fn access(&mut self, req: &Request, ino: u64, mask: i32, reply: ReplyEmpty) {
         self.access_impl(req, ino, mask, reply);
     }
@@ -159,9 +193,15 @@ impl Filesystem for TartarFS {
 #[cfg(test)]
 mod tests {
     use crate::test_utils::TestSetup;
+    use crate::{Collisions, Decompress, Normalization, Options, PathFilter};
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
     use std::fs;
+    use std::io::{Cursor, Write};
     use std::os::unix::fs::{MetadataExt, PermissionsExt};
     use tempfile::TempDir;
+    use zip::write::SimpleFileOptions;
+    use zip::{CompressionMethod, ZipWriter};
 
     #[test]
     fn test_basics() -> std::io::Result<()> {
@@ -211,4 +251,918 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_include_exclude_filters() -> std::io::Result<()> {
+        let source_dir = TempDir::new()?;
+        fs::create_dir_all(source_dir.path().join("docs/guide"))?;
+        fs::create_dir_all(source_dir.path().join("src"))?;
+        fs::write(source_dir.path().join("docs/index.md"), "# Docs")?;
+        fs::write(source_dir.path().join("docs/guide/intro.md"), "Intro")?;
+        fs::write(source_dir.path().join("docs/draft.tmp"), "scratch")?;
+        fs::write(source_dir.path().join("src/main.rs"), "fn main() {}")?;
+        let docs = source_dir.path().join("docs");
+        let docs_mtime = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        fs::File::open(&docs)?.set_modified(docs_mtime)?;
+        fs::set_permissions(&docs, fs::Permissions::from_mode(0o750))?;
+
+        let options = Options {
+            filter: PathFilter::new(["docs/**"], ["**/*.tmp"]).unwrap(),
+            ..Options::default()
+        };
+        let mounted = TestSetup::from_dir_with_options(source_dir.path(), options)?;
+
+        // `docs` itself doesn't match `docs/**`, but keeps its metadata
+        mounted.assert_is_dir("docs", Some(0o750));
+        assert_eq!(
+            fs::metadata(mounted.mount_path.join("docs"))?.modified()?,
+            docs_mtime
+        );
+
+        mounted.assert_is_file("docs/index.md", None, Some("# Docs"));
+        mounted.assert_is_file("docs/guide/intro.md", None, Some("Intro"));
+        mounted.assert_not_exists("docs/draft.tmp");
+        mounted.assert_not_exists("src");
+        mounted.assert_not_exists("src/main.rs");
+
+        let root_names: Vec<_> = fs::read_dir(&mounted.mount_path)?
+            .map(|entry| entry.map(|e| e.file_name()))
+            .collect::<Result<_, _>>()?;
+        assert_eq!(root_names, vec!["docs"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_case_insensitive_lookup() -> std::io::Result<()> {
+        let source_dir = TempDir::new()?;
+        fs::create_dir_all(source_dir.path().join("Textures"))?;
+        fs::write(source_dir.path().join("Textures/Grass.PNG"), "grass")?;
+        fs::write(source_dir.path().join("README.txt"), "upper")?;
+        fs::write(source_dir.path().join("ReadMe.txt"), "mixed")?;
+
+        let options = Options {
+            case_insensitive: true,
+            ..Options::default()
+        };
+        let mounted = TestSetup::from_dir_with_options(source_dir.path(), options)?;
+
+        mounted.assert_is_file("textures/grass.png", None, Some("grass"));
+        mounted.assert_is_file("TEXTURES/GRASS.png", None, Some("grass"));
+
+        // exact spellings still win, collisions go to the byte-wise smallest
+        mounted.assert_is_file("ReadMe.txt", None, Some("mixed"));
+        mounted.assert_is_file("README.txt", None, Some("upper"));
+        mounted.assert_is_file("readme.TXT", None, Some("upper"));
+
+        let mut root_names: Vec<_> = fs::read_dir(&mounted.mount_path)?
+            .map(|entry| entry.map(|e| e.file_name()))
+            .collect::<Result<_, _>>()?;
+        root_names.sort();
+        assert_eq!(root_names, vec!["README.txt", "ReadMe.txt", "Textures"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unicode_normalization() -> std::io::Result<()> {
+        let composed = "caf\u{e9}.txt";
+        let decomposed = "cafe\u{301}.txt";
+
+        let source_dir = TempDir::new()?;
+        fs::write(source_dir.path().join(decomposed), "coffee")?;
+
+        let options = Options {
+            normalization: Some(Normalization::Nfc),
+            ..Options::default()
+        };
+        let mounted = TestSetup::from_dir_with_options(source_dir.path(), options)?;
+
+        mounted.assert_is_file(composed, None, Some("coffee"));
+        mounted.assert_is_file(decomposed, None, Some("coffee"));
+
+        let root_names: Vec<_> = fs::read_dir(&mounted.mount_path)?
+            .map(|entry| entry.map(|e| e.file_name()))
+            .collect::<Result<_, _>>()?;
+        assert_eq!(root_names, vec![composed]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_flatten() -> std::io::Result<()> {
+        let source_dir = TempDir::new()?;
+        fs::create_dir_all(source_dir.path().join("a/b"))?;
+        fs::create_dir_all(source_dir.path().join("x"))?;
+        fs::write(source_dir.path().join("a/b/c.txt"), "abc")?;
+        fs::write(source_dir.path().join("x/c.txt"), "xc")?;
+        fs::write(source_dir.path().join("x/unique.txt"), "unique")?;
+
+        let options = Options {
+            flatten: Some(Collisions::Counter),
+            ..Options::default()
+        };
+        let mounted = TestSetup::from_dir_with_options(source_dir.path(), options)?;
+        mounted.assert_is_file("c.txt", None, Some("abc"));
+        mounted.assert_is_file("c-1.txt", None, Some("xc"));
+        mounted.assert_is_file("unique.txt", None, Some("unique"));
+        mounted.assert_not_exists("a");
+        mounted.assert_not_exists("x");
+        drop(mounted);
+
+        let options = Options {
+            flatten: Some(Collisions::EncodePath),
+            ..Options::default()
+        };
+        let mounted = TestSetup::from_dir_with_options(source_dir.path(), options)?;
+        mounted.assert_is_file("a__b__c.txt", None, Some("abc"));
+        mounted.assert_is_file("x__c.txt", None, Some("xc"));
+        mounted.assert_is_file("unique.txt", None, Some("unique"));
+        mounted.assert_not_exists("c.txt");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_flatten_symlinks() -> std::io::Result<()> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, data) in [
+            ("docs/readme.txt", &b"readme"[..]),
+            ("docs/old/notes.txt", b"notes"),
+            ("x/docs", b"not a directory"),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            builder.append_data(&mut header, path, data)?;
+        }
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        header.set_mode(0o777);
+        builder.append_link(&mut header, "docs/latest", "readme.txt")?;
+
+        let source_dir = TempDir::new()?;
+        let archive_path = source_dir.path().join("docs.tar");
+        fs::write(&archive_path, builder.into_inner()?)?;
+
+        let options = Options {
+            flatten: Some(Collisions::Counter),
+            ..Options::default()
+        };
+        let mounted = TestSetup::from_archive(&archive_path, options)?;
+        mounted.assert_is_file("readme.txt", None, Some("readme"));
+        mounted.assert_is_file("notes.txt", None, Some("notes"));
+        // the symlink keeps its place, and its directory the name
+        mounted.assert_is_symlink("docs/latest", "readme.txt");
+        mounted.assert_is_file("docs-1", None, Some("not a directory"));
+        mounted.assert_not_exists("docs/readme.txt");
+        mounted.assert_not_exists("docs/old");
+        mounted.assert_not_exists("x");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_nested_archives() -> std::io::Result<()> {
+        // innermost.tar inside inner.tar.gz inside the mounted tar
+        let innermost_dir = TempDir::new()?;
+        fs::write(innermost_dir.path().join("deep.txt"), "deep")?;
+        let mut innermost = tar::Builder::new(Vec::new());
+        innermost.append_path_with_name(innermost_dir.path().join("deep.txt"), "deep.txt")?;
+        let innermost = innermost.into_inner()?;
+
+        let big: String = (0..50_000).map(|i| format!("{i}\n")).collect();
+        let inner_dir = TempDir::new()?;
+        fs::create_dir_all(inner_dir.path().join("bin"))?;
+        fs::write(inner_dir.path().join("bin/big.txt"), &big)?;
+        fs::write(inner_dir.path().join("innermost.tar"), innermost)?;
+        let gzip = GzEncoder::new(Vec::new(), Compression::default());
+        let mut inner = tar::Builder::new(gzip);
+        inner.append_dir_all(".", inner_dir.path())?;
+        let inner = inner.into_inner()?.finish()?;
+
+        let source_dir = TempDir::new()?;
+        fs::create_dir_all(source_dir.path().join("artifacts"))?;
+        fs::write(source_dir.path().join("artifacts/inner.tar.gz"), inner)?;
+        fs::write(source_dir.path().join("artifacts/fake.tar"), "not a tar")?;
+
+        let options = Options {
+            nested_depth: 2,
+            ..Options::default()
+        };
+        let mounted = TestSetup::from_dir_with_options(source_dir.path(), options)?;
+        mounted.assert_is_dir("artifacts/inner.tar.gz", None);
+        mounted.assert_is_file("artifacts/inner.tar.gz/bin/big.txt", None, Some(&big));
+        mounted.assert_is_dir("artifacts/inner.tar.gz/innermost.tar", None);
+        mounted.assert_is_file(
+            "artifacts/inner.tar.gz/innermost.tar/deep.txt",
+            None,
+            Some("deep"),
+        );
+        mounted.assert_is_file("artifacts/fake.tar", None, Some("not a tar"));
+        drop(mounted);
+
+        let options = Options {
+            nested_depth: 1,
+            ..Options::default()
+        };
+        let mounted = TestSetup::from_dir_with_options(source_dir.path(), options)?;
+        mounted.assert_is_dir("artifacts/inner.tar.gz", None);
+        mounted.assert_is_file("artifacts/inner.tar.gz/innermost.tar", None, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_decompress_members() -> std::io::Result<()> {
+        let log: String = (0..20_000).map(|i| format!("line {i}\n")).collect();
+
+        let source_dir = TempDir::new()?;
+        fs::create_dir_all(source_dir.path().join("logs"))?;
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(log.as_bytes())?;
+        fs::write(source_dir.path().join("logs/app.log.gz"), gzip.finish()?)?;
+        let zstd = zstd::encode_all(log.as_bytes(), 3)?;
+        fs::write(source_dir.path().join("logs/db.log.zst"), zstd)?;
+        fs::write(source_dir.path().join("logs/fake.gz"), "not gzip")?;
+        let gzip = fs::read(source_dir.path().join("logs/app.log.gz"))?;
+        let truncated = &gzip[..gzip.len() / 2];
+        fs::write(source_dir.path().join("logs/broken.log.gz"), truncated)?;
+
+        let options = Options {
+            decompress: Some(Decompress::Alongside),
+            ..Options::default()
+        };
+        let mounted = TestSetup::from_dir_with_options(source_dir.path(), options)?;
+        mounted.assert_is_file("logs/app.log", None, Some(&log));
+        mounted.assert_is_file("logs/db.log", None, Some(&log));
+        mounted.assert_is_file("logs/app.log.gz", None, None);
+        mounted.assert_is_file("logs/fake.gz", None, Some("not gzip"));
+        mounted.assert_not_exists("logs/fake");
+        mounted.assert_not_exists("logs/broken.log");
+        let metadata = fs::metadata(mounted.mount_path.join("logs/app.log"))?;
+        assert_eq!(metadata.len(), log.len() as u64);
+        drop(mounted);
+
+        let options = Options {
+            decompress: Some(Decompress::Replace),
+            ..Options::default()
+        };
+        let mounted = TestSetup::from_dir_with_options(source_dir.path(), options)?;
+        mounted.assert_is_file("logs/app.log", None, Some(&log));
+        mounted.assert_not_exists("logs/app.log.gz");
+        mounted.assert_not_exists("logs/db.log.zst");
+        // what doesn't decompress stays as it is
+        mounted.assert_is_file("logs/broken.log.gz", None, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_decompress_collision() -> std::io::Result<()> {
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(b"compressed")?;
+        let members = [
+            ("notes.txt", b"plain".to_vec()),
+            ("notes.txt.gz", gzip.finish()?),
+        ];
+
+        // the name is taken, so the compressed member stays as it is,
+        // whether it comes before the plain one or after it
+        for reversed in [false, true] {
+            let mut builder = tar::Builder::new(Vec::new());
+            let mut ordered = members.iter().collect::<Vec<_>>();
+            if reversed {
+                ordered.reverse();
+            }
+            for (path, data) in ordered {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(data.len() as u64);
+                header.set_mode(0o644);
+                builder.append_data(&mut header, path, data.as_slice())?;
+            }
+
+            let source_dir = TempDir::new()?;
+            let archive_path = source_dir.path().join("notes.tar");
+            fs::write(&archive_path, builder.into_inner()?)?;
+
+            let options = Options {
+                decompress: Some(Decompress::Replace),
+                ..Options::default()
+            };
+            let mounted = TestSetup::from_archive(&archive_path, options)?;
+            mounted.assert_is_file("notes.txt", None, Some("plain"));
+            mounted.assert_is_file("notes.txt.gz", None, None);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_zip() -> std::io::Result<()> {
+        let text: String = (0..20_000).map(|i| format!("{i}\n")).collect();
+        let stored = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Stored)
+            .unix_permissions(0o640);
+        let deflated = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .unix_permissions(0o644);
+
+        let mut jar = ZipWriter::new(Cursor::new(Vec::new()));
+        jar.start_file("META-INF/MANIFEST.MF", deflated)?;
+        jar.write_all(b"Manifest-Version: 1.0\n")?;
+        let jar = jar.finish()?.into_inner();
+
+        let mut war = ZipWriter::new(Cursor::new(Vec::new()));
+        war.add_directory("WEB-INF/lib/", SimpleFileOptions::default())?;
+        war.start_file("WEB-INF/lib/app.jar", stored)?;
+        war.write_all(&jar)?;
+        war.start_file("index.html", stored)?;
+        war.write_all(b"<h1>Hello</h1>")?;
+        war.start_file("big.txt", deflated)?;
+        war.write_all(text.as_bytes())?;
+        let war = war.finish()?.into_inner();
+
+        let source_dir = TempDir::new()?;
+        fs::write(source_dir.path().join("app.war"), war)?;
+
+        let mounted =
+            TestSetup::from_archive(source_dir.path().join("app.war"), Options::default())?;
+        mounted.assert_is_file("index.html", Some(0o640), Some("<h1>Hello</h1>"));
+        mounted.assert_is_file("big.txt", Some(0o644), Some(&text));
+        mounted.assert_is_dir("WEB-INF/lib", None);
+        mounted.assert_is_file("WEB-INF/lib/app.jar", None, None);
+        drop(mounted);
+
+        let options = Options {
+            nested_depth: 1,
+            ..Options::default()
+        };
+        let mounted = TestSetup::from_archive(source_dir.path().join("app.war"), options)?;
+        mounted.assert_is_dir("WEB-INF/lib/app.jar", None);
+        mounted.assert_is_file(
+            "WEB-INF/lib/app.jar/META-INF/MANIFEST.MF",
+            None,
+            Some("Manifest-Version: 1.0\n"),
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_http_archive() -> std::io::Result<()> {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(6);
+        header.set_mode(0o644);
+        builder.append_data(&mut header, "docs/remote.txt", "Remote".as_bytes())?;
+        let url = crate::source::http::tests::serve("remote.tar", builder.into_inner()?)?;
+
+        let mounted = TestSetup::from_url(&url, Options::default())?;
+        mounted.assert_is_dir("docs", None);
+        mounted.assert_is_file("docs/remote.txt", Some(0o644), Some("Remote"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cpio() -> std::io::Result<()> {
+        use crate::tartarfs::formats::cpio::tests::newc;
+
+        let source_dir = TempDir::new()?;
+        let archive_path = source_dir.path().join("initramfs.img");
+        let mut image = newc(&[("etc/motd", 0o100644, 1, 1, b"old")]);
+        image.resize(512, 0);
+        let mut encoder = GzEncoder::new(image, Compression::default());
+        encoder.write_all(&newc(&[
+            ("etc", 0o40750, 1, 2, b""),
+            ("etc/motd", 0o100600, 2, 1, b"Welcome!"),
+        ]))?;
+        fs::write(&archive_path, encoder.finish()?)?;
+
+        let mounted = TestSetup::from_archive(&archive_path, Options::default())?;
+        mounted.assert_is_dir("etc", Some(0o750));
+        mounted.assert_is_file("etc/motd", Some(0o600), Some("Welcome!"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_deb() -> std::io::Result<()> {
+        use crate::tartarfs::formats::ar::tests::ar;
+
+        let tarball = |files: &[(&str, u32, &str)]| -> std::io::Result<Vec<u8>> {
+            let mut builder = tar::Builder::new(Vec::new());
+            for &(path, mode, text) in files {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(text.len() as u64);
+                header.set_mode(mode);
+                builder.append_data(&mut header, path, text.as_bytes())?;
+            }
+            builder.into_inner()
+        };
+        let control = tarball(&[
+            ("./control", 0o644, "Package: hello\n"),
+            ("./postinst", 0o755, "#!/bin/sh\n"),
+        ])?;
+        let mut data = GzEncoder::new(Vec::new(), Compression::default());
+        data.write_all(&tarball(&[("./usr/bin/hello", 0o755, "Hello!")])?)?;
+
+        let source_dir = TempDir::new()?;
+        let archive_path = source_dir.path().join("hello_1.0_amd64.deb");
+        let deb = ar(&[
+            ("debian-binary", b"2.0\n"),
+            ("control.tar", &control),
+            ("data.tar.gz", &data.finish()?),
+        ]);
+        fs::write(&archive_path, deb)?;
+
+        let mounted = TestSetup::from_archive(&archive_path, Options::default())?;
+        mounted.assert_not_exists("debian-binary");
+        mounted.assert_is_file("control/control", None, Some("Package: hello\n"));
+        mounted.assert_is_file("control/postinst", Some(0o755), Some("#!/bin/sh\n"));
+        mounted.assert_is_dir("data/usr/bin", None);
+        mounted.assert_is_file("data/usr/bin/hello", Some(0o755), Some("Hello!"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rpm() -> std::io::Result<()> {
+        use crate::tartarfs::formats::rpm::tests::{rpm, TestValue};
+
+        let source_dir = TempDir::new()?;
+        let archive_path = source_dir.path().join("hello-1.0-1.x86_64.rpm");
+        let package = rpm(
+            &[
+                (1000, TestValue::Str(&["hello"])),
+                (1001, TestValue::Str(&["1.0"])),
+                (1116, TestValue::Int(&[0, 0])),
+                (1117, TestValue::Str(&["good", "bad"])),
+                (1118, TestValue::Str(&["/usr/share/hello/"])),
+                (5011, TestValue::Int(&[8])),
+                (
+                    1035,
+                    TestValue::Str(&[
+                        // sha256 of "hello", twice
+                        "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824",
+                        "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824",
+                    ]),
+                ),
+            ],
+            &[
+                ("./usr/share/hello/good", 0o100644, b"hello"),
+                ("./usr/share/hello/bad", 0o100644, b"hullo"),
+            ],
+        );
+        fs::write(&archive_path, package)?;
+
+        let options = Options {
+            verify_digests: true,
+            ..Options::default()
+        };
+        let mounted = TestSetup::from_archive(&archive_path, options)?;
+        mounted.assert_is_dir(".rpm", None);
+        mounted.assert_is_file(".rpm/name", None, Some("hello\n"));
+        mounted.assert_is_file(".rpm/version", None, Some("1.0\n"));
+        mounted.assert_is_file("usr/share/hello/good", None, Some("hello"));
+        let bad = fs::read(mounted.mount_path.join("usr/share/hello/bad"));
+        assert_eq!(bad.unwrap_err().raw_os_error(), Some(libc::EIO));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ar() -> std::io::Result<()> {
+        use crate::tartarfs::formats::ar::tests::ar;
+
+        let source_dir = TempDir::new()?;
+        let archive_path = source_dir.path().join("hello.deb");
+        let deb = ar(&[("debian-binary", b"2.0\n"), ("control.tar", b"")]);
+        fs::write(&archive_path, deb)?;
+
+        // the outer members instead of `control/` and `data/`
+        let mut options = Options::default();
+        assert!(options.formats.prefer("ar"));
+        assert!(!options.formats.prefer("nonsense"));
+        let mounted = TestSetup::from_archive(&archive_path, options)?;
+        mounted.assert_is_file("debian-binary", Some(0o640), Some("2.0\n"));
+        mounted.assert_is_file("control.tar", None, Some(""));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_iso9660() -> std::io::Result<()> {
+        use crate::tartarfs::formats::iso::tests::iso;
+
+        let source_dir = TempDir::new()?;
+        let archive_path = source_dir.path().join("installer.iso");
+        fs::write(&archive_path, iso(true, true))?;
+
+        let mounted = TestSetup::from_archive(&archive_path, Options::default())?;
+        mounted.assert_is_dir("docs", Some(0o750));
+        mounted.assert_is_file("docs/readme.txt", Some(0o640), Some("Hello from ISO!"));
+        mounted.assert_is_symlink("link", "docs/readme.txt");
+        mounted.assert_is_file("link", None, Some("Hello from ISO!"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_squashfs() -> std::io::Result<()> {
+        use crate::tartarfs::formats::squashfs::tests::{big, squashfs, GZIP};
+        use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+        let source_dir = TempDir::new()?;
+        let archive_path = source_dir.path().join("tools.sqfs");
+        fs::write(&archive_path, squashfs(GZIP))?;
+
+        let mounted = TestSetup::from_archive(&archive_path, Options::default())?;
+        assert_eq!(fs::read(mounted.mount_path.join("big.bin"))?, big());
+        mounted.assert_is_file("small.txt", Some(0o644), Some("hello"));
+        mounted.assert_is_symlink("link", "small.txt");
+        mounted.assert_is_file("sub/x", Some(0o600), Some("X"));
+        mounted.assert_has_xattr("sub/x", "user.comment", b"hi");
+
+        let null = fs::symlink_metadata(mounted.mount_path.join("null"))?;
+        assert!(null.file_type().is_char_device());
+        assert_eq!(null.rdev(), libc::makedev(1, 3));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_7z() -> std::io::Result<()> {
+        use crate::tartarfs::formats::sevenz::tests::{notes, seven_zip};
+
+        let source_dir = TempDir::new()?;
+        let archive_path = source_dir.path().join("vendor.7z");
+        fs::write(&archive_path, seven_zip(true))?;
+
+        let mounted = TestSetup::from_archive(&archive_path, Options::default())?;
+        mounted.assert_is_dir("docs", Some(0o750));
+        mounted.assert_is_dir("docs/empty", None);
+        assert_eq!(
+            fs::read(mounted.mount_path.join("docs/notes.txt"))?,
+            notes()
+        );
+        mounted.assert_is_file("docs/readme.txt", Some(0o640), Some("Hello from 7z!"));
+        mounted.assert_is_file("raw.bin", Some(0o444), Some("raw data"));
+        mounted.assert_is_symlink("link", "docs/readme.txt");
+        mounted.assert_is_file("blank.txt", None, Some(""));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_warc() -> std::io::Result<()> {
+        use crate::tartarfs::formats::warc::tests::warc_gz;
+        use crate::tartarfs::formats::Warc;
+
+        let source_dir = TempDir::new()?;
+        let archive_path = source_dir.path().join("crawl.warc.gz");
+        fs::write(&archive_path, warc_gz())?;
+
+        let mounted = TestSetup::from_archive(&archive_path, Options::default())?;
+        let home = "example.com/index.html";
+        let http = fs::read_to_string(mounted.mount_path.join(home))?;
+        assert!(http.starts_with("HTTP/1.1 200 OK"));
+        mounted.assert_has_xattr(home, "user.warc.warc-type", b"response");
+
+        let mut options = Options::default();
+        options
+            .formats
+            .replace(std::sync::Arc::new(Warc { payload_only: true }));
+        let mounted = TestSetup::from_archive(&archive_path, options)?;
+        mounted.assert_is_file(home, None, Some("<h1>Home</h1>"));
+        mounted.assert_is_file("example.com/docs/a b.txt?v=2", None, Some("Hello, world"));
+        mounted.assert_is_file("example.com/docs/index.html", None, Some("docs"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_apk() -> std::io::Result<()> {
+        use crate::tartarfs::formats::apk::tests::apk;
+
+        let source_dir = TempDir::new()?;
+        let archive_path = source_dir.path().join("hello-1.0-r0.apk");
+        fs::write(&archive_path, apk()?)?;
+
+        let mounted = TestSetup::from_archive(&archive_path, Options::default())?;
+        mounted.assert_is_file(".apk/.PKGINFO", None, Some("pkgname = hello\n"));
+        mounted.assert_is_file(".apk/.SIGN.RSA.builder.rsa.pub", None, Some("signature"));
+        mounted.assert_is_file("usr/bin/hello", None, Some("Hello!"));
+        mounted.assert_not_exists(".PKGINFO");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupt_tar_header() -> std::io::Result<()> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, text) in [("a.txt", "first"), ("b.txt", "second")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(text.len() as u64);
+            header.set_mode(0o644);
+            builder.append_data(&mut header, path, text.as_bytes())?;
+        }
+        let mut archive = builder.into_inner()?;
+        // garble the second header, which follows the first header and its data block
+        archive[1024..1124].fill(0xff);
+
+        let source_dir = TempDir::new()?;
+        let archive_path = source_dir.path().join("corrupt.tar");
+        fs::write(&archive_path, archive)?;
+
+        let mounted = TestSetup::from_archive(&archive_path, Options::default())?;
+        mounted.assert_is_file("a.txt", Some(0o644), Some("first"));
+        assert!(!mounted.mount_path.join("b.txt").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_concatenated_tar_gz() -> std::io::Result<()> {
+        let mut archive = Vec::new();
+        for (path, text) in [("a.txt", "first"), ("b/c.txt", "second")] {
+            let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+            let mut header = tar::Header::new_gnu();
+            header.set_size(text.len() as u64);
+            header.set_mode(0o644);
+            builder.append_data(&mut header, path, text.as_bytes())?;
+            archive.extend(builder.into_inner()?.finish()?);
+        }
+
+        let source_dir = TempDir::new()?;
+        let archive_path = source_dir.path().join("both.tar.gz");
+        fs::write(&archive_path, archive)?;
+
+        let mounted = TestSetup::from_archive(&archive_path, Options::default())?;
+        mounted.assert_is_file("a.txt", None, Some("first"));
+        mounted.assert_is_file("b/c.txt", None, Some("second"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bgzf_tar_gz() -> std::io::Result<()> {
+        use crate::tartarfs::compression::tests::bgzf;
+
+        // many blocks of at most 64 KiB each
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut files = Vec::new();
+        for i in 0..100 {
+            let path = format!("dir/{i:02}.txt");
+            let text = format!("file {i} ").repeat(2_000);
+            let mut header = tar::Header::new_gnu();
+            header.set_size(text.len() as u64);
+            header.set_mode(0o644);
+            builder.append_data(&mut header, &path, text.as_bytes())?;
+            files.push((path, text));
+        }
+
+        let source_dir = TempDir::new()?;
+        let archive_path = source_dir.path().join("files.tar.gz");
+        fs::write(&archive_path, bgzf(&builder.into_inner()?)?)?;
+
+        let mounted = TestSetup::from_archive(&archive_path, Options::default())?;
+        for (path, text) in files.iter().rev().step_by(7) {
+            mounted.assert_is_file(path, Some(0o644), Some(text));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_spooled_tar_lz4() -> std::io::Result<()> {
+        use crate::Spool;
+
+        // big enough that reading it goes past what sniffing decodes
+        let text: String = (0..20_000).map(|i| format!("line {i}\n")).collect();
+        let mut builder = tar::Builder::new(lz4_flex::frame::FrameEncoder::new(Vec::new()));
+        let mut header = tar::Header::new_gnu();
+        header.set_size(text.len() as u64);
+        header.set_mode(0o644);
+        builder.append_data(&mut header, "logs/app.log", text.as_bytes())?;
+        let archive = builder
+            .into_inner()?
+            .finish()
+            .map_err(std::io::Error::other)?;
+
+        let source_dir = TempDir::new()?;
+        let archive_path = source_dir.path().join("logs.tar.lz4");
+        fs::write(&archive_path, archive)?;
+        let spool_dir = TempDir::new()?;
+        let spool_files = || fs::read_dir(spool_dir.path()).map(|files| files.count());
+
+        let options = Options {
+            spool: Spool {
+                dir: spool_dir.path().to_path_buf(),
+                ..Spool::default()
+            },
+            ..Options::default()
+        };
+        let mounted = TestSetup::from_archive(&archive_path, options.clone())?;
+        mounted.assert_is_file("logs/app.log", Some(0o644), Some(&text));
+        assert_eq!(spool_files()?, 0);
+        drop(mounted);
+
+        let mut options = options;
+        options.spool.keep = true;
+        for _ in 0..2 {
+            let mounted = TestSetup::from_archive_in_place(&archive_path, options.clone())?;
+            mounted.assert_is_file("logs/app.log", None, Some(&text));
+            drop(mounted);
+            assert_eq!(spool_files()?, 1);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parallel_indexing() -> std::io::Result<()> {
+        use crate::tartarfs::compression::tests::xz_blocks;
+
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut files = Vec::new();
+        for i in 0..200 {
+            let path = match i {
+                // a GNU long name record before the header
+                100 => format!("dir/{}.txt", "long".repeat(40)),
+                _ => format!("dir/{i:03}.txt"),
+            };
+            let text = format!("file {i} ").repeat(i * 37 % 1000);
+            let mut header = tar::Header::new_gnu();
+            header.set_size(text.len() as u64);
+            header.set_mode(0o644);
+            builder.append_data(&mut header, &path, text.as_bytes())?;
+            files.push((path, text));
+        }
+        let tar = builder.into_inner()?;
+
+        // split at odd places, so some headers start in one member and end in the next
+        let mut gzip = Vec::new();
+        let mut zstd = Vec::new();
+        for piece in tar.chunks(100_003) {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+            encoder.write_all(piece)?;
+            gzip.extend(encoder.finish()?);
+            zstd.extend(zstd::encode_all(piece, 1)?);
+        }
+
+        let source_dir = TempDir::new()?;
+        for (name, compressed) in [
+            ("files.tar.gz", gzip),
+            ("files.tar.zst", zstd),
+            ("files.tar.xz", xz_blocks(&tar, 100_003)?),
+        ] {
+            let archive_path = source_dir.path().join(name);
+            fs::write(&archive_path, compressed)?;
+
+            let options = Options {
+                jobs: 4,
+                ..Options::default()
+            };
+            let mounted = TestSetup::from_archive(&archive_path, options)?;
+            for (path, text) in &files {
+                mounted.assert_is_file(path, Some(0o644), Some(text));
+            }
+            assert_eq!(fs::read_dir(mounted.mount_path.join("dir"))?.count(), 200);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_cache() -> std::io::Result<()> {
+        use crate::IndexCache;
+
+        fn tarball(files: &[(&str, &str)]) -> std::io::Result<Vec<u8>> {
+            let encoder = GzEncoder::new(Vec::new(), Compression::fast());
+            let mut builder = tar::Builder::new(encoder);
+            for (path, text) in files {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(text.len() as u64);
+                header.set_mode(0o644);
+                builder.append_data(&mut header, path, text.as_bytes())?;
+            }
+            builder.into_inner()?.finish()
+        }
+
+        let source_dir = TempDir::new()?;
+        let cache_dir = source_dir.path().join("cache");
+        let archive_path = source_dir.path().join("files.tar.gz");
+        fs::write(&archive_path, tarball(&[("docs/b.txt", "bravo")])?)?;
+        let options = Options {
+            case_insensitive: true,
+            index_cache: Some(IndexCache::Dir(cache_dir.clone())),
+            ..Options::default()
+        };
+
+        {
+            let mounted = TestSetup::from_archive_in_place(&archive_path, options.clone())?;
+            mounted.assert_is_file("DOCS/B.TXT", Some(0o644), Some("bravo"));
+        }
+        let saved: Vec<_> = fs::read_dir(&cache_dir)?.collect::<Result<_, _>>()?;
+        assert_eq!(saved.len(), 1);
+
+        // a mount that loads the index sees whatever the index says
+        let index_path = saved[0].path();
+        let index = String::from_utf8(zstd::decode_all(fs::File::open(&index_path)?)?).unwrap();
+        let index = index.replace("\"docs/b.txt\"", "\"docs/renamed.txt\"");
+        fs::write(&index_path, zstd::encode_all(index.as_bytes(), 1)?)?;
+        {
+            let mounted = TestSetup::from_archive_in_place(&archive_path, options.clone())?;
+            mounted.assert_is_file("docs/RENAMED.txt", Some(0o644), Some("bravo"));
+            assert!(!mounted.mount_path.join("docs/b.txt").exists());
+        }
+
+        // a changed archive gets indexed again
+        fs::write(&archive_path, tarball(&[("docs/b.txt", "bravo!")])?)?;
+        {
+            let mounted = TestSetup::from_archive_in_place(&archive_path, options)?;
+            mounted.assert_is_file("docs/b.txt", Some(0o644), Some("bravo!"));
+            assert!(!mounted.mount_path.join("docs/renamed.txt").exists());
+        }
+        assert_eq!(fs::read_dir(&cache_dir)?.count(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_conda() -> std::io::Result<()> {
+        use crate::tartarfs::formats::conda::tests::conda;
+
+        let source_dir = TempDir::new()?;
+        let archive_path = source_dir.path().join("hello-1.0-0.conda");
+        fs::write(&archive_path, conda()?)?;
+
+        let mounted = TestSetup::from_archive(&archive_path, Options::default())?;
+        mounted.assert_is_file("info/index.json", None, Some(r#"{"name": "hello"}"#));
+        mounted.assert_is_file("lib/hello.py", None, Some("print('hello')\n"));
+        mounted.assert_not_exists("metadata.json");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_oci() -> std::io::Result<()> {
+        use crate::tartarfs::formats::oci::tests::oci_layout;
+        use crate::tartarfs::formats::Oci;
+
+        let source_dir = TempDir::new()?;
+        let layout = source_dir.path().join("image");
+        oci_layout(Some(&layout));
+
+        let mut options = Options::default();
+        options
+            .formats
+            .replace(std::sync::Arc::new(Oci { layers: true }));
+        let mounted = TestSetup::from_archive_in_place(&layout, options)?;
+        mounted.assert_is_file("etc/hosts", None, Some("127.0.0.1 new\n"));
+        mounted.assert_not_exists("etc/shadow");
+        mounted.assert_not_exists("etc/.wh.shadow");
+        mounted.assert_not_exists("var/cache/old");
+        mounted.assert_is_file("var/cache/new", None, Some("fresh"));
+        mounted.assert_is_file("bin/sh", None, Some("#!"));
+
+        let layers = fs::read_dir(mounted.mount_path.join(".layers"))?;
+        let mut layers: Vec<_> = layers.map(|layer| layer.unwrap().path()).collect();
+        layers.sort();
+        assert_eq!(layers.len(), 2);
+        assert!(layers.iter().any(|layer| layer.join("etc/shadow").exists()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_stargz() -> std::io::Result<()> {
+        use crate::tartarfs::compression::Codec;
+        use crate::tartarfs::formats::stargz::tests::layer;
+
+        for (codec, name) in [
+            (Codec::Gzip, "layer.tar.gz"),
+            (Codec::Zstd, "layer.tar.zst"),
+        ] {
+            let source_dir = TempDir::new()?;
+            let archive_path = source_dir.path().join(name);
+            fs::write(&archive_path, layer(codec, false))?;
+
+            let mounted = TestSetup::from_archive(&archive_path, Options::default())?;
+            mounted.assert_is_file("docs/readme.txt", Some(0o644), Some("Hello from eStargz!"));
+            mounted.assert_is_file(
+                "big.txt",
+                None,
+                Some("0123456789abcdefghijklmnopqrstuvwxyz"),
+            );
+            mounted.assert_is_symlink("link", "docs/readme.txt");
+            mounted.assert_has_xattr("big.txt", "user.comment", b"hi");
+            mounted.assert_not_exists("stargz.index.json");
+        }
+
+        Ok(())
+    }
 }
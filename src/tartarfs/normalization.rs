@@ -0,0 +1,35 @@
+use unicode_normalization::UnicodeNormalization;
+
+/// Unicode normalization form applied to names when indexing and looking up,
+/// e.g. macOS archives store `café` decomposed (NFD) while Linux tools mostly
+/// use the composed form (NFC).
+#[derive(Debug, Copy, Clone, Eq, PartialEq, clap::ValueEnum)]
+pub enum Normalization {
+    Nfc,
+    Nfd,
+}
+
+impl Normalization {
+    pub fn apply(&self, text: &str) -> String {
+        match self {
+            Normalization::Nfc => text.nfc().collect(),
+            Normalization::Nfd => text.nfd().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Normalization;
+
+    #[test]
+    fn test_apply() {
+        let composed = "caf\u{e9}.txt";
+        let decomposed = "cafe\u{301}.txt";
+
+        assert_eq!(Normalization::Nfc.apply(decomposed), composed);
+        assert_eq!(Normalization::Nfc.apply(composed), composed);
+        assert_eq!(Normalization::Nfd.apply(composed), decomposed);
+        assert_eq!(Normalization::Nfd.apply(decomposed), decomposed);
+    }
+}
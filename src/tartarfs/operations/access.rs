@@ -13,7 +13,7 @@ impl TartarFS {
         reply: ReplyEmpty,
     ) {
         debug!("access(inode={})", inode_number);
-        if let Some(_) = self.inode_to_item.get(&Inode(inode_number)) {
+        if self.inode_to_item.contains_key(&Inode(inode_number)) {
             reply.ok();
         } else {
             reply.error(ENOENT);
@@ -0,0 +1,34 @@
+use crate::inode::Inode;
+use crate::TartarFS;
+use fuser::{ReplyXattr, Request};
+use libc::{ENODATA, ENOENT, ERANGE};
+use std::ffi::OsStr;
+use tracing::debug;
+
+impl TartarFS {
+    pub fn getxattr_impl(
+        &mut self,
+        _req: &Request,
+        inode_number: u64,
+        name: &OsStr,
+        size: u32,
+        reply: ReplyXattr,
+    ) {
+        debug!("getxattr(inode={}, name={:?})", inode_number, name);
+        let Some(item) = self.inode_to_item.get(&Inode(inode_number)) else {
+            return reply.error(ENOENT);
+        };
+        let Some((_, value)) = item.xattrs.iter().find(|(key, _)| OsStr::new(key) == name) else {
+            return reply.error(ENODATA);
+        };
+
+        // a size of 0 asks how big a buffer the value needs
+        if size == 0 {
+            reply.size(value.len() as u32);
+        } else if value.len() <= size as usize {
+            reply.data(value);
+        } else {
+            reply.error(ERANGE);
+        }
+    }
+}
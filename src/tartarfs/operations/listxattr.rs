@@ -0,0 +1,35 @@
+use crate::inode::Inode;
+use crate::TartarFS;
+use fuser::{ReplyXattr, Request};
+use libc::{ENOENT, ERANGE};
+use tracing::debug;
+
+impl TartarFS {
+    pub fn listxattr_impl(
+        &mut self,
+        _req: &Request,
+        inode_number: u64,
+        size: u32,
+        reply: ReplyXattr,
+    ) {
+        debug!("listxattr(inode={})", inode_number);
+        let Some(item) = self.inode_to_item.get(&Inode(inode_number)) else {
+            return reply.error(ENOENT);
+        };
+
+        // the names, each terminated by a NUL
+        let mut names = Vec::new();
+        for (key, _) in &item.xattrs {
+            names.extend_from_slice(key.as_bytes());
+            names.push(0);
+        }
+
+        if size == 0 {
+            reply.size(names.len() as u32);
+        } else if names.len() <= size as usize {
+            reply.data(&names);
+        } else {
+            reply.error(ERANGE);
+        }
+    }
+}
@@ -24,7 +24,7 @@ impl TartarFS {
             path => format!("{path}/{}", name.to_string_lossy()),
         };
 
-        let Some(&inode) = self.path_to_inode.get(&lookup_path) else {
+        let Some(inode) = self.find_inode(&lookup_path) else {
             return reply.error(ENOENT);
         };
 
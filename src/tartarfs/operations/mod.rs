@@ -1,5 +1,8 @@
 mod access;
 mod getattr;
+mod getxattr;
+mod listxattr;
 mod lookup;
 mod read;
 mod readdir;
+mod readlink;
@@ -1,39 +1,41 @@
 use crate::inode::Inode;
 use crate::TartarFS;
 use fuser::{ReplyData, Request};
-use libc::ENOENT;
-use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
+use libc::{EIO, EISDIR, ENOENT};
+use tracing::{debug, error};
 
 impl TartarFS {
+    #[allow(clippy::too_many_arguments)]
     pub fn read_impl(
         &mut self,
         _req: &Request,
         inode_number: u64,
         _fh: u64,
-        _offset: i64,
+        offset: i64,
         size: u32,
         _flags: i32,
         _lock: Option<u64>,
         reply: ReplyData,
     ) {
+        debug!(
+            "read(inode={}, offset={}, size={})",
+            inode_number, offset, size
+        );
         let Some(item) = self.inode_to_item.get(&Inode(inode_number)) else {
             return reply.error(ENOENT);
         };
 
-        let Ok(mut archive_file) = File::open(&self.archive_path) else {
-            return reply.error(ENOENT);
-        };
-
-        let Ok(_) = archive_file.seek(SeekFrom::Start(item.offset)) else {
-            return reply.error(ENOENT);
+        let Some(content) = &item.content else {
+            return reply.error(EISDIR);
         };
 
         let mut buffer = vec![0; size as usize];
-        let Ok(n) = archive_file.read(&mut buffer) else {
-            return reply.error(ENOENT);
-        };
-
-        reply.data(&buffer[..n]);
+        match content.read_full_at(offset.max(0) as u64, &mut buffer) {
+            Ok(n) => reply.data(&buffer[..n]),
+            Err(e) => {
+                error!("Failed to read {}: {}", item.name, e);
+                reply.error(EIO);
+            }
+        }
     }
 }
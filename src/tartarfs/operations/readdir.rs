@@ -24,27 +24,25 @@ impl TartarFS {
 
                 let dir_path_text = &dir_entry.name;
                 for (child_inode_number, entry) in &self.inode_to_item {
-                    if entry.name.starts_with(dir_path_text) {
-                        let remaining = if dir_path_text.is_empty() {
-                            &entry.name
-                        } else {
-                            entry
-                                .name
-                                .strip_prefix(&format!("{}/", dir_path_text))
-                                .unwrap_or(&entry.name)
-                        };
+                    let remaining = if dir_path_text.is_empty() {
+                        Some(entry.name.as_str())
+                    } else {
+                        // not the directory itself, nor a sibling like `docs2` of `docs`
+                        entry
+                            .name
+                            .strip_prefix(dir_path_text.as_str())
+                            .and_then(|rest| rest.strip_prefix('/'))
+                    };
 
-                        if !remaining.is_empty() && !remaining.contains('/') {
-                            entries.push((
-                                child_inode_number.0,
-                                if entry.is_dir {
-                                    FileType::Directory
-                                } else {
-                                    FileType::RegularFile
-                                },
-                                OsString::from(remaining),
-                            ));
-                        }
+                    if let Some(remaining) = remaining
+                        && !remaining.is_empty()
+                        && !remaining.contains('/')
+                    {
+                        entries.push((
+                            child_inode_number.0,
+                            entry.kind(),
+                            OsString::from(remaining),
+                        ));
                     }
                 }
 
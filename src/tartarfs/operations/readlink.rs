@@ -0,0 +1,19 @@
+use crate::inode::Inode;
+use crate::TartarFS;
+use fuser::{ReplyData, Request};
+use libc::{EINVAL, ENOENT};
+use tracing::debug;
+
+impl TartarFS {
+    pub fn readlink_impl(&mut self, _req: &Request, inode_number: u64, reply: ReplyData) {
+        debug!("readlink(inode={})", inode_number);
+        let Some(item) = self.inode_to_item.get(&Inode(inode_number)) else {
+            return reply.error(ENOENT);
+        };
+
+        match &item.link_target {
+            Some(target) => reply.data(target.as_bytes()),
+            None => reply.error(EINVAL),
+        }
+    }
+}
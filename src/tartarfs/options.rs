@@ -0,0 +1,50 @@
+use super::compression::{Decompress, Spool};
+use super::filter::PathFilter;
+use super::flatten::Collisions;
+use super::formats::Formats;
+use super::index_cache::IndexCache;
+use super::normalization::Normalization;
+
+/// Settings that shape how an archive is presented once mounted.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Which archive paths get indexed; the rest never get an inode.
+    pub filter: PathFilter,
+    /// Match looked up names using Unicode case folding;
+    /// directory listings keep the original spelling.
+    pub case_insensitive: bool,
+    /// Normalize names to this Unicode form when indexing and looking up.
+    pub normalization: Option<Normalization>,
+    /// Put every regular file directly in the root, naming clashes this way;
+    /// symlinks and special files keep their paths.
+    pub flatten: Option<Collisions>,
+    /// Show archive members like `.tar.gz` or `.jar` as directories of their
+    /// contents, this many archives deep; 0 leaves them as plain files.
+    pub nested_depth: u32,
+    /// Expose `.gz`, `.zst`, `.xz`, `.bz2`, `.lz4`, `.lz`, `.lzma` and `.br`
+    /// members under their decompressed name, decoded on read. Their size
+    /// comes from the gzip trailer or zstd frame header where that can be
+    /// trusted; other members are decoded once in full when indexing to find
+    /// it, and those that don't decode stay as is.
+    pub decompress: Option<Decompress>,
+    /// Where lz4, lzip, brotli and `.lzma` streams get decompressed to, as
+    /// they can't be read from the middle.
+    pub spool: Spool,
+    /// Index archives compressed as several independent members, like
+    /// concatenated gzip members, multi-frame zstd or multi-block xz, on this
+    /// many threads; 0 or 1 indexes them on the calling thread alone. A
+    /// single member, as plain `gzip` and `pigz` write, is decoded on one thread.
+    pub jobs: usize,
+    /// Check file digests recorded in the archive, e.g. by RPM, before the
+    /// first read of each file, failing reads of files that don't match.
+    pub verify_digests: bool,
+    /// The archive format backends to recognize archives with.
+    pub formats: Formats,
+    /// Save the index of an archive file here once it's indexed, and load
+    /// it instead of reading the archive while the archive is unchanged.
+    pub index_cache: Option<IndexCache>,
+    /// Key saved indexes by a hash of the whole archive rather than of its
+    /// first and last MiB, to notice changes in the middle that kept its
+    /// size and modification time.
+    pub index_full_hash: bool,
+}
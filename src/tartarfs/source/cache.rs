@@ -0,0 +1,132 @@
+use super::ByteSource;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+const BLOCK_SIZE: u64 = 256 * 1024;
+const MAX_BLOCKS: usize = 64;
+
+/// Keeps recently read blocks of a slow source in memory.
+///
+/// Reads are rounded out to whole blocks, so the many small reads of
+/// parsing headers cost one round trip to e.g. a web server instead of many.
+pub struct CachedSource {
+    inner: Arc<dyn ByteSource>,
+    blocks: Mutex<Blocks>,
+}
+
+#[derive(Default)]
+struct Blocks {
+    data: HashMap<u64, Arc<Vec<u8>>>,
+    /// Block numbers, least recently used first.
+    order: VecDeque<u64>,
+}
+
+impl CachedSource {
+    pub fn new(inner: Arc<dyn ByteSource>) -> Self {
+        Self {
+            inner,
+            blocks: Mutex::default(),
+        }
+    }
+
+    fn block(&self, number: u64) -> io::Result<Arc<Vec<u8>>> {
+        {
+            let mut blocks = self.blocks.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(block) = blocks.data.get(&number).cloned() {
+                blocks.order.retain(|&n| n != number);
+                blocks.order.push_back(number);
+                return Ok(block);
+            }
+        }
+
+        // read without holding the lock, so other blocks can be served meanwhile
+        let start = number * BLOCK_SIZE;
+        let mut data = vec![0; BLOCK_SIZE.min(self.size().saturating_sub(start)) as usize];
+        let n = self.inner.read_full_at(start, &mut data)?;
+        data.truncate(n);
+        let block = Arc::new(data);
+
+        let mut blocks = self.blocks.lock().unwrap_or_else(|e| e.into_inner());
+        if blocks.data.insert(number, Arc::clone(&block)).is_none() {
+            blocks.order.push_back(number);
+        }
+        while blocks.order.len() > MAX_BLOCKS {
+            if let Some(evicted) = blocks.order.pop_front() {
+                blocks.data.remove(&evicted);
+            }
+        }
+        Ok(block)
+    }
+}
+
+impl ByteSource for CachedSource {
+    fn size(&self) -> u64 {
+        self.inner.size()
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let block = self.block(offset / BLOCK_SIZE)?;
+        let data = block
+            .get((offset % BLOCK_SIZE) as usize..)
+            .unwrap_or_default();
+        let n = data.len().min(buf.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        Ok(n)
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        self.inner.modified()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::MemorySource;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct Counting {
+        inner: MemorySource,
+        reads: AtomicUsize,
+    }
+
+    impl ByteSource for Counting {
+        fn size(&self) -> u64 {
+            self.inner.size()
+        }
+
+        fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+            self.reads.fetch_add(1, Ordering::Relaxed);
+            self.inner.read_at(offset, buf)
+        }
+    }
+
+    #[test]
+    fn test_cached_reads() -> io::Result<()> {
+        let data: Vec<u8> = (0..BLOCK_SIZE as u32 * 3).map(|i| i as u8).collect();
+        let counting = Arc::new(Counting {
+            inner: MemorySource::new(data.clone()),
+            reads: AtomicUsize::new(0),
+        });
+        let cached = CachedSource::new(Arc::clone(&counting) as Arc<dyn ByteSource>);
+
+        // small reads within a block only hit the inner source once
+        let mut buf = [0; 100];
+        for offset in [0, 512, 1000, 100] {
+            cached.read_full_at(offset, &mut buf)?;
+            assert_eq!(&buf[..], &data[offset as usize..offset as usize + 100]);
+        }
+        assert_eq!(counting.reads.load(Ordering::Relaxed), 1);
+
+        // across a block boundary and up to the end
+        let offset = BLOCK_SIZE - 50;
+        cached.read_full_at(offset, &mut buf)?;
+        assert_eq!(&buf[..], &data[offset as usize..offset as usize + 100]);
+        assert_eq!(cached.read_full_at(data.len() as u64 - 10, &mut buf)?, 10);
+        assert_eq!(cached.read_full_at(data.len() as u64, &mut buf)?, 0);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,168 @@
+use super::ByteSource;
+use std::io::{self, Read};
+use std::time::Duration;
+
+/// An archive on a web server or object store, read with HTTP range requests.
+///
+/// The server has to answer ranged `GET`s with `206 Partial Content`, which
+/// static file servers, S3 and GCS all do; private buckets work through
+/// presigned URLs.
+pub struct HttpSource {
+    agent: ureq::Agent,
+    url: String,
+    size: u64,
+}
+
+impl HttpSource {
+    pub fn open(url: &str) -> io::Result<Self> {
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(Duration::from_secs(10))
+            .timeout_read(Duration::from_secs(60))
+            .build();
+
+        // the first byte tells us both that ranges work and the total size
+        let response = get_range(&agent, url, 0, 0)?;
+        let size = response
+            .header("Content-Range")
+            .and_then(|range| range.rsplit_once('/'))
+            .and_then(|(_, total)| total.trim().parse().ok())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{url}: no total size in Content-Range"),
+                )
+            })?;
+
+        Ok(Self {
+            agent,
+            url: url.to_string(),
+            size,
+        })
+    }
+}
+
+impl ByteSource for HttpSource {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        if offset >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let last = (offset + buf.len() as u64).min(self.size) - 1;
+        let response = get_range(&self.agent, &self.url, offset, last)?;
+
+        let mut body = response.into_reader().take(last - offset + 1);
+        let mut filled = 0;
+        loop {
+            match body.read(&mut buf[filled..]) {
+                Ok(0) => return Ok(filled),
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// `GET` the inclusive byte range `first..=last`, insisting on a partial response.
+fn get_range(agent: &ureq::Agent, url: &str, first: u64, last: u64) -> io::Result<ureq::Response> {
+    let response = agent
+        .get(url)
+        .set("Range", &format!("bytes={first}-{last}"))
+        .call()
+        .map_err(|e| io::Error::other(format!("{url}: {e}")))?;
+    if response.status() != 206 {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{url}: server doesn't support range requests"),
+        ));
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::Arc;
+    use std::thread;
+
+    /// Serve `data` at `http://127.0.0.1:<port>/<name>` with range support,
+    /// for as long as the test process runs.
+    pub(crate) fn serve(name: &str, data: Vec<u8>) -> io::Result<String> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}/{name}", listener.local_addr()?);
+        let data = Arc::new(data);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let data = Arc::clone(&data);
+                thread::spawn(move || respond(stream, &data));
+            }
+        });
+        Ok(url)
+    }
+
+    fn respond(mut stream: TcpStream, data: &[u8]) -> io::Result<()> {
+        let mut range = None;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':')
+                && name.eq_ignore_ascii_case("range")
+            {
+                let (first, last) = value
+                    .trim()
+                    .trim_start_matches("bytes=")
+                    .split_once('-')
+                    .unwrap();
+                range = Some((
+                    first.parse::<usize>().unwrap(),
+                    last.parse::<usize>().unwrap(),
+                ));
+            }
+        }
+
+        match range {
+            Some((first, last)) => {
+                let last = last.min(data.len() - 1);
+                let body = &data[first..=last];
+                write!(
+                    stream,
+                    "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {first}-{last}/{}\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n",
+                    data.len(),
+                    body.len()
+                )?;
+                stream.write_all(body)
+            }
+            None => write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+            ),
+        }
+    }
+
+    #[test]
+    fn test_range_reads() -> io::Result<()> {
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        let url = serve("data.bin", data.clone())?;
+
+        let source = HttpSource::open(&url)?;
+        assert_eq!(source.size(), data.len() as u64);
+
+        let mut buf = [0; 100];
+        assert_eq!(source.read_full_at(1234, &mut buf)?, 100);
+        assert_eq!(&buf[..], &data[1234..1334]);
+        assert_eq!(source.read_full_at(9950, &mut buf)?, 50);
+        assert_eq!(&buf[..50], &data[9950..]);
+        assert_eq!(source.read_full_at(10_000, &mut buf)?, 0);
+
+        Ok(())
+    }
+}
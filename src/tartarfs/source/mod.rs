@@ -0,0 +1,374 @@
+//! Where archive bytes come from.
+//!
+//! Everything the filesystem reads, both while indexing and when serving
+//! `read`, goes through a [`ByteSource`], so an archive can live in a local
+//! file, in memory or on a web server.
+
+mod cache;
+pub(crate) mod http;
+
+pub use cache::CachedSource;
+pub use http::HttpSource;
+
+use super::compression::{BgzfBlock, Codec};
+use super::digest::Digest;
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Open an archive location: an `http://` or `https://` URL
+/// (which also covers presigned object store URLs) or a local path.
+pub fn open(location: &str) -> io::Result<Arc<dyn ByteSource>> {
+    if is_remote(location) {
+        let remote: Arc<dyn ByteSource> = Arc::new(HttpSource::open(location)?);
+        // parsers do lots of small reads, so don't make each one a request
+        return Ok(Arc::new(CachedSource::new(remote)));
+    }
+    Ok(Arc::new(FileSource::open(location)?))
+}
+
+/// Whether an archive location is a URL rather than a local path.
+pub fn is_remote(location: &str) -> bool {
+    location.starts_with("http://") || location.starts_with("https://")
+}
+
+/// Random access to a run of bytes: the archive file itself, a member of it,
+/// or the decompressed contents of a member.
+pub trait ByteSource: Send + Sync {
+    fn size(&self) -> u64;
+
+    /// Read up to `buf.len()` bytes starting at `offset`, returning 0 at the end.
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize>;
+
+    /// When the underlying file was last changed, if there is one.
+    fn modified(&self) -> Option<SystemTime> {
+        None
+    }
+
+    /// How to make this source again from the ones it is made of, so that a
+    /// saved index can be loaded without reading the archive; `None` keeps
+    /// indexes of archives with such members from being saved.
+    fn recipe(&self) -> Option<Recipe> {
+        None
+    }
+
+    /// Fill `buf` starting at `offset`, stopping short only at the end.
+    fn read_full_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            match self.read_at(offset + filled as u64, &mut buf[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(filled)
+    }
+}
+
+/// What a source is made of, as told by [`ByteSource::recipe`].
+pub enum Recipe {
+    Memory(Vec<u8>),
+    Zero {
+        size: u64,
+    },
+    Slice {
+        parent: Arc<dyn ByteSource>,
+        offset: u64,
+        size: u64,
+    },
+    Concat(Vec<Arc<dyn ByteSource>>),
+    /// A [`DecompressedSource`](crate::compression::DecompressedSource), with
+    /// its size if that has been worked out.
+    Decompressed {
+        compressed: Arc<dyn ByteSource>,
+        codec: Codec,
+        size: Option<u64>,
+    },
+    Spooled {
+        compressed: Arc<dyn ByteSource>,
+        codec: Codec,
+    },
+    /// A [`BgzfSource`](crate::compression::BgzfSource), with its blocks if
+    /// they have been indexed.
+    Bgzf {
+        compressed: Arc<dyn ByteSource>,
+        blocks: Option<Vec<BgzfBlock>>,
+    },
+    Verified {
+        inner: Arc<dyn ByteSource>,
+        digest: Digest,
+    },
+}
+
+pub struct FileSource {
+    file: File,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+impl FileSource {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let metadata = file.metadata()?;
+        Ok(Self {
+            file,
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+}
+
+impl ByteSource for FileSource {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read_at(buf, offset)
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        self.modified
+    }
+}
+
+/// An archive held in memory, e.g. embedded with `include_bytes!`.
+pub struct MemorySource {
+    data: Cow<'static, [u8]>,
+}
+
+impl MemorySource {
+    pub fn new<D: Into<Cow<'static, [u8]>>>(data: D) -> Self {
+        Self { data: data.into() }
+    }
+}
+
+impl ByteSource for MemorySource {
+    fn size(&self) -> u64 {
+        self.data.len() as u64
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let data = usize::try_from(offset)
+            .ok()
+            .and_then(|offset| self.data.get(offset..))
+            .unwrap_or_default();
+        let n = data.len().min(buf.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        Ok(n)
+    }
+
+    fn recipe(&self) -> Option<Recipe> {
+        Some(Recipe::Memory(self.data.to_vec()))
+    }
+}
+
+/// A run of zeros that takes no memory, e.g. a hole in a sparse file.
+pub struct ZeroSource {
+    size: u64,
+}
+
+impl ZeroSource {
+    pub fn new(size: u64) -> Self {
+        Self { size }
+    }
+}
+
+impl ByteSource for ZeroSource {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let n = buf.len().min(self.size.saturating_sub(offset) as usize);
+        buf[..n].fill(0);
+        Ok(n)
+    }
+
+    fn recipe(&self) -> Option<Recipe> {
+        Some(Recipe::Zero { size: self.size })
+    }
+}
+
+/// A window of `size` bytes into another source, e.g. one tar member.
+pub struct SliceSource {
+    parent: Arc<dyn ByteSource>,
+    offset: u64,
+    size: u64,
+}
+
+impl SliceSource {
+    pub fn new(parent: Arc<dyn ByteSource>, offset: u64, size: u64) -> Self {
+        Self {
+            parent,
+            offset,
+            size,
+        }
+    }
+}
+
+impl ByteSource for SliceSource {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        if offset >= self.size {
+            return Ok(0);
+        }
+        let len = buf.len().min((self.size - offset) as usize);
+        self.parent.read_at(self.offset + offset, &mut buf[..len])
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        self.parent.modified()
+    }
+
+    fn recipe(&self) -> Option<Recipe> {
+        Some(Recipe::Slice {
+            parent: Arc::clone(&self.parent),
+            offset: self.offset,
+            size: self.size,
+        })
+    }
+}
+
+/// Several sources read back to back as one, e.g. a file stored in pieces.
+pub struct ConcatSource {
+    /// Each part along with where it starts.
+    parts: Vec<(u64, Arc<dyn ByteSource>)>,
+    size: u64,
+}
+
+impl ConcatSource {
+    pub fn new(parts: Vec<Arc<dyn ByteSource>>) -> Self {
+        let mut size = 0;
+        let parts = parts
+            .into_iter()
+            .map(|part| {
+                let start = size;
+                size += part.size();
+                (start, part)
+            })
+            .collect();
+        Self { parts, size }
+    }
+}
+
+impl ByteSource for ConcatSource {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let index = self.parts.partition_point(|(start, _)| *start <= offset);
+        let Some((start, part)) = index.checked_sub(1).map(|i| &self.parts[i]) else {
+            return Ok(0);
+        };
+        // a short read at the end of a part is fine, `read_full_at` goes on with the next
+        part.read_at(offset - start, buf)
+    }
+
+    fn recipe(&self) -> Option<Recipe> {
+        let parts = self.parts.iter().map(|(_, part)| Arc::clone(part));
+        Some(Recipe::Concat(parts.collect()))
+    }
+}
+
+/// Sequential `Read` + `Seek` over a source, for parsers that want a stream.
+pub struct SourceReader {
+    source: Arc<dyn ByteSource>,
+    position: u64,
+}
+
+impl SourceReader {
+    pub fn new(source: Arc<dyn ByteSource>) -> Self {
+        Self {
+            source,
+            position: 0,
+        }
+    }
+}
+
+impl Read for SourceReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.source.read_at(self.position, buf)?;
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for SourceReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.source.size().checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        };
+        let Some(position) = position else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek to a negative position",
+            ));
+        };
+        self.position = position;
+        Ok(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slice() -> io::Result<()> {
+        let parent: Arc<dyn ByteSource> = Arc::new(MemorySource::new(b"0123456789".as_slice()));
+        let slice = SliceSource::new(parent, 2, 5);
+        assert_eq!(slice.size(), 5);
+
+        let mut buf = [0; 8];
+        assert_eq!(slice.read_full_at(0, &mut buf)?, 5);
+        assert_eq!(&buf[..5], b"23456");
+        assert_eq!(slice.read_full_at(3, &mut buf)?, 2);
+        assert_eq!(&buf[..2], b"56");
+        assert_eq!(slice.read_full_at(7, &mut buf)?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_concat() -> io::Result<()> {
+        let parts: Vec<Arc<dyn ByteSource>> = [&b"012"[..], b"", b"3456", b"789"]
+            .into_iter()
+            .map(|part| Arc::new(MemorySource::new(part)) as Arc<dyn ByteSource>)
+            .collect();
+        let concat = ConcatSource::new(parts);
+        assert_eq!(concat.size(), 10);
+
+        let mut buf = [0; 8];
+        assert_eq!(concat.read_full_at(1, &mut buf)?, 8);
+        assert_eq!(&buf, b"12345678");
+        assert_eq!(concat.read_full_at(9, &mut buf)?, 1);
+        assert_eq!(concat.read_full_at(10, &mut buf)?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_zero() -> io::Result<()> {
+        // far more than could be allocated
+        let zeros = ZeroSource::new(100 << 30);
+        let mut buf = [1; 8];
+        assert_eq!(zeros.read_full_at((100 << 30) - 3, &mut buf)?, 3);
+        assert_eq!(buf, [0, 0, 0, 1, 1, 1, 1, 1]);
+        assert_eq!(zeros.read_full_at(100 << 30, &mut buf)?, 0);
+
+        Ok(())
+    }
+}
@@ -11,7 +11,7 @@ use walkdir::WalkDir;
 #[allow(dead_code)]
 pub struct TestSetup {
     test_dir: TempDir,     // the test workspace with the archive and mount point
-    archive_path: PathBuf, // the path to the tar archive to test
+    archive_path: PathBuf, // the path to the archive to test
     pub(crate) mount_path: PathBuf, // the path to the mount point to test
     mount_handle: JoinHandle<()>, // the handle to wait for the unmounting
 }
@@ -21,6 +21,14 @@ impl TestSetup {
     /// This creates the tar and mounts it which you can then test using
     /// the various `assert` methods.
     pub fn from_dir<P: AsRef<Path>>(source_dir: P) -> std::io::Result<Self> {
+        Self::from_dir_with_options(source_dir, Options::default())
+    }
+
+    /// Same as `from_dir` but mounts with the given options.
+    pub fn from_dir_with_options<P: AsRef<Path>>(
+        source_dir: P,
+        options: Options,
+    ) -> std::io::Result<Self> {
         let test_dir = TempDir::new()?;
         let archive_path = test_dir.path().join("test.tar");
 
@@ -47,10 +55,40 @@ impl TestSetup {
         }
         builder.finish()?;
 
+        Self::mount(test_dir, archive_path, options)
+    }
+
+    /// Mount an already built archive, e.g. a zip, keeping its file name.
+    pub fn from_archive<P: AsRef<Path>>(archive: P, options: Options) -> std::io::Result<Self> {
+        let test_dir = TempDir::new()?;
+        let archive = archive.as_ref();
+        let file_name = archive.file_name().expect("Archive must be a file");
+        let archive_path = test_dir.path().join(file_name);
+        fs::copy(archive, &archive_path)?;
+
+        Self::mount(test_dir, archive_path, options)
+    }
+
+    /// Mount an archive where it is, e.g. an OCI image layout directory or
+    /// a file that should keep its modification time.
+    pub fn from_archive_in_place<P: AsRef<Path>>(
+        archive: P,
+        options: Options,
+    ) -> std::io::Result<Self> {
+        Self::mount(TempDir::new()?, archive.as_ref().to_path_buf(), options)
+    }
+
+    /// Mount an archive served over HTTP.
+    pub fn from_url(url: &str, options: Options) -> std::io::Result<Self> {
+        Self::mount(TempDir::new()?, PathBuf::from(url), options)
+    }
+
+    fn mount(test_dir: TempDir, archive_path: PathBuf, options: Options) -> std::io::Result<Self> {
         let mount_path = test_dir.path().join("mount");
         fs::create_dir_all(&mount_path)?;
 
-        let filesystem = TartarFS::new(archive_path.to_string_lossy().to_string());
+        let filesystem =
+            TartarFS::with_options(archive_path.to_string_lossy().to_string(), options);
         let mount_path_clone = mount_path.clone();
         let mount_handle = spawn(move || {
             fuser::mount2(
@@ -128,6 +166,54 @@ impl TestSetup {
             );
         }
     }
+
+    /// Assert that a path is a symbolic link pointing at `expected_target`.
+    pub fn assert_is_symlink(&self, path: &str, expected_target: &str) {
+        let full_path = self.mount_path.join(path);
+        let target = fs::read_link(&full_path)
+            .unwrap_or_else(|e| panic!("Path {} should be a symlink: {}", path, e));
+        assert_eq!(
+            target,
+            Path::new(expected_target),
+            "Symlink {} target mismatch",
+            path
+        );
+    }
+
+    /// Assert that a path has an extended attribute with the expected value.
+    pub fn assert_has_xattr(&self, path: &str, name: &str, expected_value: &[u8]) {
+        let full_path = self.mount_path.join(path);
+        let c_path = std::ffi::CString::new(full_path.as_os_str().as_encoded_bytes()).unwrap();
+        let c_name = std::ffi::CString::new(name).unwrap();
+        let mut value = vec![0u8; 64 * 1024];
+        let size = unsafe {
+            libc::lgetxattr(
+                c_path.as_ptr(),
+                c_name.as_ptr(),
+                value.as_mut_ptr().cast(),
+                value.len(),
+            )
+        };
+        assert!(
+            size >= 0,
+            "Path {} should have xattr {}: {}",
+            path,
+            name,
+            std::io::Error::last_os_error()
+        );
+        value.truncate(size as usize);
+        assert_eq!(value, expected_value, "Xattr {} of {} mismatch", name, path);
+    }
+
+    /// Assert that a path does not exist in the mounted filesystem.
+    pub fn assert_not_exists(&self, path: &str) {
+        let full_path = self.mount_path.join(path);
+        assert!(
+            !full_path.exists(),
+            "Path {} should not exist in mounted filesystem",
+            path
+        );
+    }
 }
 
 impl Drop for TestSetup {